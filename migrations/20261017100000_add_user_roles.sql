@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'author'
+    CHECK (role IN ('admin', 'moderator', 'author', 'reader'));
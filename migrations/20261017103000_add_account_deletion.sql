@@ -0,0 +1,59 @@
+-- Add migration script here
+-- users is rebuilt rather than altered so its ids become AUTOINCREMENT: a deleted user's id is
+-- never handed to someone new, so logs that only keep ids, like the impersonation audit log,
+-- still point at one person. Runs with foreign keys off, see init_db.
+CREATE TABLE users_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL UNIQUE,
+    email TEXT NOT NULL UNIQUE,
+    password TEXT NOT NULL,
+    bio TEXT,
+    image TEXT,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    email_verified_at TIMESTAMP,
+    role TEXT NOT NULL DEFAULT 'author'
+        CHECK (role IN ('admin', 'moderator', 'author', 'reader')),
+    deletion_requested_at TIMESTAMP,
+    deletion_scheduled_at TIMESTAMP,
+    deletion_content TEXT CHECK (deletion_content IN ('delete', 'anonymise')),
+    deleted_at TIMESTAMP
+);
+INSERT INTO users_new (id, username, email, password, bio, image, created_at,
+    email_verified_at, role)
+SELECT id, username, email, password, bio, image, created_at, email_verified_at, role
+FROM users;
+DROP TABLE users;
+ALTER TABLE users_new RENAME TO users;
+
+-- follows and favourite were created without ON DELETE CASCADE, so deleting a user or an
+-- article left them pointing at nothing. SQLite can't alter a foreign key, so rebuild them and
+-- drop any rows that are already dangling.
+CREATE TABLE follows_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    follower_id INTEGER NOT NULL,
+    followed_id INTEGER NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (follower_id, followed_id),
+    FOREIGN KEY (follower_id) REFERENCES users (id) ON DELETE CASCADE,
+    FOREIGN KEY (followed_id) REFERENCES users (id) ON DELETE CASCADE
+);
+INSERT INTO follows_new (id, follower_id, followed_id, created_at)
+SELECT id, follower_id, followed_id, created_at FROM follows
+WHERE follower_id IN (SELECT id FROM users) AND followed_id IN (SELECT id FROM users);
+DROP TABLE follows;
+ALTER TABLE follows_new RENAME TO follows;
+
+CREATE TABLE favourite_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    article_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (article_id, user_id),
+    FOREIGN KEY (article_id) REFERENCES articles (id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+INSERT INTO favourite_new (id, article_id, user_id, created_at)
+SELECT id, article_id, user_id, created_at FROM favourite
+WHERE article_id IN (SELECT id FROM articles) AND user_id IN (SELECT id FROM users);
+DROP TABLE favourite;
+ALTER TABLE favourite_new RENAME TO favourite;
@@ -0,0 +1,16 @@
+-- Add migration script here
+ALTER TABLE sessions ADD COLUMN impersonator_id INTEGER REFERENCES users (id) ON DELETE CASCADE;
+
+-- No foreign keys, so the trail outlives the accounts and sessions it mentions
+CREATE TABLE IF NOT EXISTS impersonation_audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id INTEGER NOT NULL,
+    admin_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    method TEXT NOT NULL,
+    path TEXT NOT NULL,
+    blocked BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS impersonation_audit_log_admin_id ON impersonation_audit_log (admin_id);
@@ -0,0 +1,8 @@
+-- Add migration script here
+ALTER TABLE articles ADD COLUMN status TEXT NOT NULL DEFAULT 'published'
+    CHECK (status IN ('draft', 'published', 'unlisted'));
+ALTER TABLE articles ADD COLUMN published_at TIMESTAMP;
+
+UPDATE articles SET published_at = created_at;
+
+CREATE INDEX IF NOT EXISTS articles_status ON articles (status);
@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE articles ADD COLUMN publish_at TIMESTAMP;
+
+CREATE INDEX IF NOT EXISTS articles_publish_at ON articles (publish_at)
+    WHERE publish_at IS NOT NULL;
@@ -0,0 +1,23 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS article_revisions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    article_id INTEGER NOT NULL,
+    number INTEGER NOT NULL,
+    editor_id INTEGER,
+    title TEXT NOT NULL,
+    description TEXT NOT NULL,
+    body TEXT NOT NULL,
+    -- Comma separated names of the fields that differ from the previous revision
+    changes TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (article_id, number),
+    FOREIGN KEY (article_id) REFERENCES articles (id) ON DELETE CASCADE,
+    FOREIGN KEY (editor_id) REFERENCES users (id) ON DELETE SET NULL
+);
+
+-- Existing articles start their history from what they look like now
+INSERT INTO article_revisions (article_id, number, editor_id, title, description, body, changes,
+    created_at)
+SELECT id, 1, author_id, title, description, body, 'title,description,body',
+    COALESCE(updated_at, created_at)
+FROM articles;
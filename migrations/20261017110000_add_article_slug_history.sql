@@ -0,0 +1,10 @@
+-- Add migration script here
+-- Slugs an article was reachable at before it was renamed, so old links can be redirected.
+-- No foreign key: article ids are never reused, and rows of deleted articles are ignored
+CREATE TABLE IF NOT EXISTS article_slug_history (
+    slug TEXT PRIMARY KEY,
+    article_id INTEGER NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS article_slug_history_article_id ON article_slug_history (article_id);
@@ -0,0 +1,5 @@
+-- Add migration script here
+-- The revision's body rendered from Markdown, filled in the first time it is asked for
+ALTER TABLE article_revisions ADD COLUMN body_html TEXT;
+-- Which version of the renderer made body_html, so a change to it renders everything again
+ALTER TABLE article_revisions ADD COLUMN body_html_version INTEGER;
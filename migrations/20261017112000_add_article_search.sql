@@ -0,0 +1,51 @@
+-- Add migration script here
+-- Full-text index over articles, the rowid is the article's id. Tags are kept as one
+-- space separated string
+CREATE VIRTUAL TABLE IF NOT EXISTS articles_fts USING fts5 (
+    title,
+    description,
+    body,
+    tags,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+INSERT INTO articles_fts (rowid, title, description, body, tags)
+SELECT articles.id, title, description, body,
+    COALESCE((SELECT group_concat(tags.name, ' ') FROM tags
+        JOIN articletags ON articletags.tag_id = tags.id
+        WHERE articletags.article_id = articles.id), '')
+FROM articles;
+
+CREATE TRIGGER IF NOT EXISTS articles_fts_insert AFTER INSERT ON articles
+BEGIN
+    INSERT INTO articles_fts (rowid, title, description, body, tags)
+    VALUES (new.id, new.title, new.description, new.body, '');
+END;
+
+CREATE TRIGGER IF NOT EXISTS articles_fts_update AFTER UPDATE OF title, description, body
+ON articles
+BEGIN
+    UPDATE articles_fts SET title = new.title, description = new.description, body = new.body
+    WHERE rowid = new.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS articles_fts_delete AFTER DELETE ON articles
+BEGIN
+    DELETE FROM articles_fts WHERE rowid = old.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS articles_fts_tag AFTER INSERT ON articletags
+BEGIN
+    UPDATE articles_fts SET tags = COALESCE((SELECT group_concat(tags.name, ' ') FROM tags
+        JOIN articletags ON articletags.tag_id = tags.id
+        WHERE articletags.article_id = new.article_id), '')
+    WHERE rowid = new.article_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS articles_fts_untag AFTER DELETE ON articletags
+BEGIN
+    UPDATE articles_fts SET tags = COALESCE((SELECT group_concat(tags.name, ' ') FROM tags
+        JOIN articletags ON articletags.tag_id = tags.id
+        WHERE articletags.article_id = old.article_id), '')
+    WHERE rowid = old.article_id;
+END;
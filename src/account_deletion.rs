@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use sqlx::SqlitePool;
+
+use crate::authentication::get_setting_from_env;
+use crate::data_export::remove_export_files;
+use crate::db_helpers::{get_users_due_for_deletion_in_db, purge_user_in_db};
+use crate::errors::RequestError;
+
+const ACCOUNT_DELETION_GRACE_PERIOD: i64 = 30 * 24 * 60 * 60;
+const ACCOUNT_PURGE_INTERVAL: i64 = 60 * 60;
+
+/// How long a deleted account can still be restored by logging in.
+pub fn get_account_deletion_grace_period() -> i64 {
+    get_setting_from_env(
+        "ACCOUNT_DELETION_GRACE_PERIOD",
+        ACCOUNT_DELETION_GRACE_PERIOD,
+    )
+}
+
+/// Purges every account whose grace period is over and returns how many were removed.
+pub async fn purge_deleted_accounts(pool: &SqlitePool) -> Result<usize, RequestError> {
+    let mut purged = 0;
+    for (user_id, content) in get_users_due_for_deletion_in_db(pool).await? {
+        if let Some(export_ids) = purge_user_in_db(pool, user_id, content).await? {
+            remove_export_files(&export_ids).await?;
+            purged += 1;
+        }
+    }
+    Ok(purged)
+}
+
+/// Runs `purge_deleted_accounts` every `ACCOUNT_PURGE_INTERVAL` seconds for as long as the
+/// server is up.
+pub fn spawn_account_purge(pool: SqlitePool) {
+    let interval = get_setting_from_env("ACCOUNT_PURGE_INTERVAL", ACCOUNT_PURGE_INTERVAL).max(1);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval as u64));
+        loop {
+            interval.tick().await;
+            match purge_deleted_accounts(&pool).await {
+                Ok(0) => {}
+                Ok(purged) => println!("Purged {} deleted accounts", purged),
+                Err(e) => eprintln!("Could not purge deleted accounts: {:?}", e),
+            }
+        }
+    });
+}
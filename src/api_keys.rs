@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+
+use crate::authentication::generate_random_token;
+
+/// Every API key starts with this, which is how `MaybeUser` tells them apart from JWTs.
+pub const API_KEY_PREFIX: &str = "conduit_";
+
+/// What an API key is allowed to do. Sessions can do everything, keys only what they were
+/// granted when they were created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum ApiKeyScope {
+    #[serde(rename = "articles:write")]
+    ArticlesWrite,
+    #[serde(rename = "comments:write")]
+    CommentsWrite,
+    #[serde(rename = "profile:read")]
+    ProfileRead,
+    #[serde(rename = "profile:write")]
+    ProfileWrite,
+}
+
+impl ApiKeyScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiKeyScope::ArticlesWrite => "articles:write",
+            ApiKeyScope::CommentsWrite => "comments:write",
+            ApiKeyScope::ProfileRead => "profile:read",
+            ApiKeyScope::ProfileWrite => "profile:write",
+        }
+    }
+
+    fn from_str(scope: &str) -> Option<Self> {
+        match scope {
+            "articles:write" => Some(ApiKeyScope::ArticlesWrite),
+            "comments:write" => Some(ApiKeyScope::CommentsWrite),
+            "profile:read" => Some(ApiKeyScope::ProfileRead),
+            "profile:write" => Some(ApiKeyScope::ProfileWrite),
+            _ => None,
+        }
+    }
+}
+
+/// Scopes are stored as a space separated list.
+pub fn scopes_to_string(scopes: &[ApiKeyScope]) -> String {
+    scopes
+        .iter()
+        .map(ApiKeyScope::as_str)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn scopes_from_string(scopes: &str) -> Vec<ApiKeyScope> {
+    scopes
+        .split_whitespace()
+        .filter_map(ApiKeyScope::from_str)
+        .collect()
+}
+
+pub fn generate_api_key() -> String {
+    format!("{}{}", API_KEY_PREFIX, generate_random_token())
+}
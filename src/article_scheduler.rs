@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use sqlx::SqlitePool;
+use tokio::sync::Notify;
+
+use crate::authentication::get_setting_from_env;
+use crate::db_helpers::{get_next_scheduled_publish_in_db, publish_due_articles_in_db};
+
+const ARTICLE_SCHEDULER_INTERVAL: i64 = 60;
+
+//? Wakes the scheduler when an article is scheduled before the time it is sleeping until
+static SCHEDULE_CHANGED: Notify = Notify::const_new();
+
+/// Tells the scheduler that an article was scheduled, so it can work out when to wake up again.
+pub fn notify_article_scheduled() {
+    SCHEDULE_CHANGED.notify_one();
+}
+
+/// Publishes scheduled articles once their `publish_at` comes, for as long as the server is up.
+///
+/// The schedule only lives in the database, so articles that came due while the server was down
+/// are published as soon as it starts. Between articles it sleeps until the next one is due, but
+/// never longer than `ARTICLE_SCHEDULER_INTERVAL` seconds.
+pub fn spawn_article_scheduler(pool: SqlitePool) {
+    let interval =
+        get_setting_from_env("ARTICLE_SCHEDULER_INTERVAL", ARTICLE_SCHEDULER_INTERVAL).max(1);
+    tokio::spawn(async move {
+        loop {
+            match publish_due_articles_in_db(&pool).await {
+                Ok(0) => {}
+                Ok(published) => println!("Published {} scheduled articles", published),
+                Err(e) => eprintln!("Could not publish scheduled articles: {:?}", e),
+            }
+
+            let mut wait = Duration::from_secs(interval as u64);
+            match get_next_scheduled_publish_in_db(&pool).await {
+                Ok(Some(next)) => {
+                    let until_next = (next - Utc::now().naive_utc())
+                        .to_std()
+                        .unwrap_or(Duration::ZERO);
+                    //? At least a second, the database only compares times to the second
+                    wait = wait.min(until_next.max(Duration::from_secs(1)));
+                }
+                Ok(None) => {}
+                Err(e) => eprintln!("Could not read the article schedule: {:?}", e),
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => {}
+                _ = SCHEDULE_CHANGED.notified() => {}
+            }
+        }
+    });
+}
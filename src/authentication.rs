@@ -1,23 +1,140 @@
+use crate::api_keys::{scopes_from_string, ApiKeyScope, API_KEY_PREFIX};
+use crate::db_helpers::{
+    cancel_user_deletion_in_db, create_session_in_db, get_user_by_id, get_user_role_in_db,
+    touch_session_in_db, update_password_hash_in_db, use_api_key_in_db,
+};
 use crate::errors::RequestError;
+use crate::impersonation::{audit_impersonated_request, get_impersonation_token_expiry};
+use crate::jwt_keys::{jwt_keys, TokenKind};
+use crate::models::Role;
+use crate::passwords::password_settings;
 use anyhow::{Context, Result};
 use argon2::PasswordVerifier;
-use argon2::{password_hash::SaltString, Argon2, PasswordHash};
-use axum::extract::FromRequestParts;
-use axum::http::request::Parts;
+use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher};
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::{header::USER_AGENT, request::Parts};
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::net::SocketAddr;
+use std::sync::Arc;
 use time::OffsetDateTime;
 
-const JWT_EXPIRY_DURATION: time::Duration = time::Duration::days(90);
+static DUMMY_PASSWORD_HASH: tokio::sync::OnceCell<String> = tokio::sync::OnceCell::const_new();
+
+const JWT_EXPIRY_DURATION: i64 = 15 * 60;
+const REFRESH_TOKEN_EXPIRY_DURATION: i64 = 30 * 24 * 60 * 60;
+const PASSWORD_RESET_TOKEN_EXPIRY_DURATION: i64 = 60 * 60;
+const EMAIL_VERIFICATION_TOKEN_EXPIRY_DURATION: i64 = 24 * 60 * 60;
+const TWO_FACTOR_CHALLENGE_EXPIRY_DURATION: i64 = 5 * 60;
+const OIDC_LOGIN_STATE_EXPIRY_DURATION: i64 = 10 * 60;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct AuthClaim {
+    id: i64,
+    sid: i64,
+    exp: i64,
+    /// Only on impersonation tokens, naming the admin acting as `id`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    act: Option<ActorClaim>,
+}
+
+/// Who is really making requests with an impersonation token, after the `act` claim of RFC 8693.
+#[derive(Debug, Serialize, Deserialize)]
+struct ActorClaim {
+    id: i64,
+}
+
+/// Claim for the short-lived token `login_user` hands out when a second factor is still needed.
+/// It has no session, so it can never be used as an access token.
+#[derive(Debug, Serialize, Deserialize)]
+struct ChallengeClaim {
     id: i64,
     exp: i64,
 }
 
+/// How the request was authenticated.
+pub enum Credential {
+    Session(i64),
+    ApiKey(Vec<ApiKeyScope>),
+}
+
 pub struct AuthUser {
     pub id: i64,
-    pub token: String,
+    /// The access token the request was made with, `None` for API keys
+    pub token: Option<String>,
+    pub credential: Credential,
+    pub role: Role,
+    /// The admin making the request when it was made with an impersonation token
+    pub impersonator_id: Option<i64>,
+}
+
+impl AuthUser {
+    /// Sessions can do anything the user can, API keys only what they were granted.
+    pub fn ensure_scope(&self, scope: ApiKeyScope) -> Result<(), RequestError> {
+        match &self.credential {
+            Credential::Session(_) => Ok(()),
+            Credential::ApiKey(scopes) if scopes.contains(&scope) => Ok(()),
+            Credential::ApiKey(_) => Err(RequestError::Forbidden(
+                "API key is missing the required scope",
+            )),
+        }
+    }
+
+    /// Returns the session id for account management that API keys and impersonating admins are
+    /// never allowed to do.
+    pub fn ensure_session(&self) -> Result<i64, RequestError> {
+        if self.impersonator_id.is_some() {
+            return Err(RequestError::Forbidden("Not allowed while impersonating"));
+        }
+        self.current_session()
+    }
+
+    /// Returns the session id of the request, even when it is an impersonation session, so that
+    /// it can still be signed out.
+    pub fn current_session(&self) -> Result<i64, RequestError> {
+        match &self.credential {
+            Credential::Session(session_id) => Ok(*session_id),
+            Credential::ApiKey(_) => Err(RequestError::Forbidden("API keys cannot be used here")),
+        }
+    }
+}
+
+/// Where a request came from, recorded against the session it belongs to.
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+impl ClientInfo {
+    fn from_parts(parts: &Parts) -> Self {
+        ClientInfo {
+            user_agent: parts
+                .headers
+                .get(USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned),
+            ip_address: parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(address)| address.ip().to_string()),
+        }
+    }
+}
+
+#[axum::async_trait]
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync + 'static,
+{
+    type Rejection = RequestError;
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _: &S,
+    ) -> std::result::Result<Self, Self::Rejection> {
+        Ok(ClientInfo::from_parts(parts))
+    }
 }
 
 pub struct MaybeUser(pub Option<AuthUser>);
@@ -58,54 +175,221 @@ where
             }
         };
 
-        let id = match verify_jwt_token(token) {
-            Ok(id) => id,
-            Err(e) => return Err(e),
+        let pool = parts
+            .extensions
+            .get::<Arc<SqlitePool>>()
+            .ok_or(RequestError::ServerError)?;
+
+        let (id, credential, impersonator_id) = if token.starts_with(API_KEY_PREFIX) {
+            match use_api_key_in_db(pool, &hash_token(token)).await? {
+                Some((id, scopes)) => (id, Credential::ApiKey(scopes_from_string(&scopes)), None),
+                None => return Err(RequestError::NotAuthorized("Invalid API key")),
+            }
+        } else {
+            let (id, session_id, impersonator_id) = match verify_jwt_token(token) {
+                Ok(claim) => claim,
+                Err(e) => return Err(e),
+            };
+            let client = ClientInfo::from_parts(parts);
+            let ip_address = client.ip_address.as_deref();
+            if !touch_session_in_db(pool, session_id, id, impersonator_id, ip_address).await? {
+                return Err(RequestError::NotAuthorized("Session has been revoked"));
+            }
+            if let Some(admin_id) = impersonator_id {
+                audit_impersonated_request(pool, parts, session_id, admin_id, id).await?;
+            }
+            (id, Credential::Session(session_id), impersonator_id)
+        };
+
+        //? Looked up on every request so role changes apply straight away
+        let role = match get_user_role_in_db(pool, id).await? {
+            Some(role) => role,
+            None => return Err(RequestError::NotAuthorized("User not found")),
         };
 
         Ok(MaybeUser(Some(AuthUser {
             id,
-            token: token.to_string(),
+            token: match credential {
+                Credential::Session(_) => Some(token.to_string()),
+                Credential::ApiKey(_) => None,
+            },
+            credential,
+            role,
+            impersonator_id,
         })))
     }
 }
 
-pub fn get_jwt_token(id: i64) -> Result<String> {
-    let jwt_secret = std::env::var("JWT_SECRET").context("Failed to get JWT_SECRET")?;
-    let expiry_date = OffsetDateTime::now_utc() + JWT_EXPIRY_DURATION;
+/// Reads a setting such as a duration in seconds or a limit from the environment, falling back
+/// to `default` when it is unset or can't be parsed.
+pub fn get_setting_from_env<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+pub fn get_refresh_token_expiry() -> i64 {
+    get_setting_from_env(
+        "REFRESH_TOKEN_EXPIRY_DURATION",
+        REFRESH_TOKEN_EXPIRY_DURATION,
+    )
+}
+
+pub fn get_password_reset_token_expiry() -> i64 {
+    get_setting_from_env(
+        "PASSWORD_RESET_TOKEN_EXPIRY_DURATION",
+        PASSWORD_RESET_TOKEN_EXPIRY_DURATION,
+    )
+}
+
+pub fn get_email_verification_token_expiry() -> i64 {
+    get_setting_from_env(
+        "EMAIL_VERIFICATION_TOKEN_EXPIRY_DURATION",
+        EMAIL_VERIFICATION_TOKEN_EXPIRY_DURATION,
+    )
+}
+
+/// Whether `REQUIRE_EMAIL_VERIFICATION` is turned on, which stops unverified users from
+/// publishing articles and comments.
+pub fn is_email_verification_required() -> bool {
+    std::env::var("REQUIRE_EMAIL_VERIFICATION")
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false)
+}
+
+pub async fn ensure_email_verified(pool: &SqlitePool, id: i64) -> Result<(), RequestError> {
+    if !is_email_verification_required() {
+        return Ok(());
+    }
+    match get_user_by_id(pool, id).await? {
+        Some(user) if user.email_verified_at.is_some() => Ok(()),
+        Some(_) => Err(RequestError::Forbidden("Email must be verified first")),
+        None => Err(RequestError::NotFound("User not found")),
+    }
+}
+
+pub fn get_jwt_token(id: i64, session_id: i64) -> Result<String> {
+    let expiry_date = OffsetDateTime::now_utc()
+        + time::Duration::seconds(get_setting_from_env(
+            "JWT_EXPIRY_DURATION",
+            JWT_EXPIRY_DURATION,
+        ));
     let claim = AuthClaim {
         id,
+        sid: session_id,
         exp: expiry_date.unix_timestamp(),
+        act: None,
     };
 
-    let token = jsonwebtoken::encode(
-        &jsonwebtoken::Header::default(),
-        &claim,
-        &jsonwebtoken::EncodingKey::from_secret(jwt_secret.as_ref()),
-    )
-    .context("Failed to generate jwt token");
-    token
+    jwt_keys()?
+        .encode(TokenKind::Access, &claim)
+        .context("Failed to generate jwt token")
 }
 
-pub fn verify_jwt_token(token: &str) -> Result<i64, RequestError> {
-    let jwt_secret = std::env::var("JWT_SECRET").map_err(|_| RequestError::ServerError)?;
-    let token_data = jsonwebtoken::decode::<AuthClaim>(
-        token,
-        &jsonwebtoken::DecodingKey::from_secret(jwt_secret.as_ref()),
-        &jsonwebtoken::Validation::default(),
-    )
-    .map_err(|e| {
-        println!("Error verifying token:\n {}", e);
-        RequestError::NotAuthorized("Invalid Token")
-    })?;
-    let claim = token_data.claims;
+/// An access token for `admin_id` to act as user `id` in an impersonation session.
+pub fn get_impersonation_token(id: i64, session_id: i64, admin_id: i64) -> Result<String> {
+    let expiry_date =
+        OffsetDateTime::now_utc() + time::Duration::seconds(get_impersonation_token_expiry());
+    let claim = AuthClaim {
+        id,
+        sid: session_id,
+        exp: expiry_date.unix_timestamp(),
+        act: Some(ActorClaim { id: admin_id }),
+    };
+
+    jwt_keys()?
+        .encode(TokenKind::Access, &claim)
+        .context("Failed to generate impersonation token")
+}
+
+/// Returns the `(user_id, session_id, impersonator_id)` the token was issued for.
+pub fn verify_jwt_token(token: &str) -> Result<(i64, i64, Option<i64>), RequestError> {
+    let claim = jwt_keys()
+        .map_err(|_| RequestError::ServerError)?
+        .decode::<AuthClaim>(TokenKind::Access, token)?;
     if claim.exp < OffsetDateTime::now_utc().unix_timestamp() {
         return Err(RequestError::NotAuthorized("Token expired"));
     }
+    Ok((claim.id, claim.sid, claim.act.map(|actor| actor.id)))
+}
+
+pub fn get_two_factor_challenge_expiry() -> i64 {
+    get_setting_from_env(
+        "TWO_FACTOR_CHALLENGE_EXPIRY_DURATION",
+        TWO_FACTOR_CHALLENGE_EXPIRY_DURATION,
+    )
+}
+
+pub fn get_oidc_login_state_expiry() -> i64 {
+    get_setting_from_env(
+        "OIDC_LOGIN_STATE_EXPIRY_DURATION",
+        OIDC_LOGIN_STATE_EXPIRY_DURATION,
+    )
+}
+
+pub fn get_two_factor_challenge_token(id: i64) -> Result<String> {
+    let expiry_date =
+        OffsetDateTime::now_utc() + time::Duration::seconds(get_two_factor_challenge_expiry());
+    let claim = ChallengeClaim {
+        id,
+        exp: expiry_date.unix_timestamp(),
+    };
+
+    jwt_keys()?
+        .encode(TokenKind::TwoFactorChallenge, &claim)
+        .context("Failed to generate challenge token")
+}
+
+/// Returns the id of the user the challenge token was issued to.
+pub fn verify_two_factor_challenge_token(token: &str) -> Result<i64, RequestError> {
+    let claim = jwt_keys()
+        .map_err(|_| RequestError::ServerError)?
+        .decode::<ChallengeClaim>(TokenKind::TwoFactorChallenge, token)
+        .map_err(|_| RequestError::NotAuthorized("Invalid challenge token"))?;
     Ok(claim.id)
 }
 
+/// Generates an opaque random token suitable for handing out to clients.
+pub fn generate_random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Hashes an opaque token for storage. These tokens are high-entropy, so a fast hash is enough.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Starts a new session for the user and returns its `(access_token, refresh_token)` pair.
+///
+/// Logging in again during the grace period of an account deletion keeps the account.
+pub async fn create_session(
+    pool: &SqlitePool,
+    user_id: i64,
+    client: &ClientInfo,
+) -> Result<(String, String), RequestError> {
+    cancel_user_deletion_in_db(pool, user_id).await?;
+    let refresh_token = generate_random_token();
+    let session_id = create_session_in_db(
+        pool,
+        user_id,
+        client.user_agent.as_deref(),
+        client.ip_address.as_deref(),
+        &hash_token(&refresh_token),
+        get_refresh_token_expiry(),
+    )
+    .await?;
+    let token = get_jwt_token(user_id, session_id).map_err(|_| RequestError::ServerError)?;
+    Ok((token, refresh_token))
+}
+
 pub async fn verify_password_argon2(password: String, hash: &str) -> Result<bool> {
+    //? Nobody could have picked it, so it can't match and isn't worth the hashing
+    if password_settings()?.is_too_long(&password) {
+        return Ok(false);
+    }
     let hash = hash.to_owned();
     tokio::task::spawn_blocking(move || {
         let hash = PasswordHash::new(hash.as_str())
@@ -119,13 +403,42 @@ pub async fn verify_password_argon2(password: String, hash: &str) -> Result<bool
     .context("Failed to verify password")?
 }
 
+/// A hash of a random password to verify against when the user doesn't exist, so a failed
+/// login takes about as long whether or not the email is registered.
+pub async fn get_dummy_password_hash() -> Result<&'static str> {
+    let hash = DUMMY_PASSWORD_HASH
+        .get_or_try_init(|| hash_password_argon2(generate_random_token()))
+        .await?;
+    Ok(hash.as_str())
+}
+
 pub async fn hash_password_argon2(password: String) -> Result<String> {
+    let argon2 = password_settings()?.argon2();
     tokio::task::spawn_blocking(move || {
         let salt = SaltString::generate(rand::thread_rng());
-        let hash = PasswordHash::generate(Argon2::default(), password, salt.as_salt())
+        let hash = argon2
+            .hash_password(password.as_bytes(), salt.as_salt())
             .map_err(|_| anyhow::anyhow!("Failed to hash password"))?;
         Ok(hash.to_string())
     })
     .await
     .context("Failed to hash password")?
 }
+
+/// Replaces the user's password hash when it was made with weaker argon2 parameters than we use
+/// now. Only possible right after the password was verified, while we still have it.
+pub async fn upgrade_password_hash(
+    pool: &SqlitePool,
+    user_id: i64,
+    current_hash: &str,
+    password: String,
+) -> Result<(), RequestError> {
+    let settings = password_settings().map_err(|_| RequestError::ServerError)?;
+    if !settings.needs_rehash(current_hash) {
+        return Ok(());
+    }
+    let new_hash = hash_password_argon2(password)
+        .await
+        .map_err(|_| RequestError::ServerError)?;
+    update_password_hash_in_db(pool, user_id, current_hash, &new_hash).await
+}
@@ -0,0 +1,303 @@
+use std::{
+    io::{Cursor, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use zip::{write::FileOptions, ZipWriter};
+
+use crate::authentication::get_setting_from_env;
+use crate::data_formats::{
+    datetime_to_string, response::ApiKeyResponse, response::ArticleResponse, ArticleQueryParams,
+    ArticleSort,
+};
+use crate::db_helpers::{
+    delete_expired_data_exports_in_db, finish_data_export_in_db, get_comments_by_author_in_db,
+    get_followed_usernames_in_db, get_follower_usernames_in_db, get_user_by_id, list_all_articles,
+    list_all_sessions_in_db, list_api_keys_in_db, list_pending_data_exports_in_db,
+};
+use crate::errors::RequestError;
+use crate::models::{Article, DataExportStatus};
+use crate::pagination::Page;
+
+const DATA_EXPORT_EXPIRY: i64 = 24 * 60 * 60;
+const DATA_EXPORT_CLEANUP_INTERVAL: u64 = 60 * 60;
+
+const EXPORT_README: &str = "# Your Conduit data\n\n\
+    - `profile.json`: your account details\n\
+    - `articles.json` and `articles/`: the articles you wrote, also as Markdown\n\
+    - `comments.json` and `comments.md`: the comments you wrote\n\
+    - `favourites.json`: the articles you favourited\n\
+    - `follows.json`: who you follow and who follows you\n\
+    - `sessions.json`: every device you logged in from\n\
+    - `api_keys.json`: your API keys (the keys themselves are never stored)\n";
+
+/// How long the download link for an export stays valid.
+pub fn get_data_export_expiry() -> i64 {
+    get_setting_from_env("DATA_EXPORT_EXPIRY_DURATION", DATA_EXPORT_EXPIRY)
+}
+
+fn export_dir() -> PathBuf {
+    std::env::var("DATA_EXPORT_DIR")
+        .unwrap_or_else(|_| "exports".into())
+        .into()
+}
+
+pub fn export_path(export_id: i64) -> PathBuf {
+    export_dir().join(format!("{}.zip", export_id))
+}
+
+fn export_error(error: impl std::fmt::Debug) -> RequestError {
+    eprintln!("Export error: {:?}", error);
+    RequestError::ServerError
+}
+
+fn to_json(value: &impl serde::Serialize) -> Result<Vec<u8>, RequestError> {
+    serde_json::to_vec_pretty(value).map_err(export_error)
+}
+
+fn article_to_markdown(article: &Article) -> String {
+    let mut markdown = format!("# {}\n\n", article.title);
+    if !article.description.is_empty() {
+        markdown.push_str(&format!("> {}\n\n", article.description));
+    }
+    markdown.push_str(&format!(
+        "Published {}, last updated {}\n\n",
+        datetime_to_string(article.created_at),
+        datetime_to_string(article.updated_at)
+    ));
+    if !article.tag_list.is_empty() {
+        markdown.push_str(&format!(
+            "Tags: {}\n\n",
+            article.tag_list.replace(',', ", ")
+        ));
+    }
+    markdown.push_str(&article.body);
+    markdown.push('\n');
+    markdown
+}
+
+/// Collects everything we hold about the user into a zip archive of JSON and Markdown files.
+pub async fn build_export_archive(
+    pool: &SqlitePool,
+    user_id: i64,
+) -> Result<Vec<u8>, RequestError> {
+    let user = get_user_by_id(pool, user_id)
+        .await?
+        .ok_or(RequestError::NotFound("User not found"))?;
+    //? Every article at once rather than a page of them
+    let all_articles = |author: Option<String>, favourited: Option<String>| ArticleQueryParams {
+        tag: None,
+        author,
+        favourited,
+        limit: u32::MAX,
+        offset: 0,
+        cursor: None,
+        sort: ArticleSort::Newest,
+    };
+    let (
+        Page {
+            items: articles, ..
+        },
+        _,
+    ) = list_all_articles(
+        pool,
+        Some(user_id),
+        all_articles(Some(user.username.clone()), None),
+        None,
+    )
+    .await?;
+    let (
+        Page {
+            items: favourites, ..
+        },
+        _,
+    ) = list_all_articles(
+        pool,
+        Some(user_id),
+        all_articles(None, Some(user.username.clone())),
+        None,
+    )
+    .await?;
+    let comments = get_comments_by_author_in_db(pool, user_id).await?;
+    let following = get_followed_usernames_in_db(pool, user_id).await?;
+    let followers = get_follower_usernames_in_db(pool, user_id).await?;
+    let sessions = list_all_sessions_in_db(pool, user_id).await?;
+    let api_keys = list_api_keys_in_db(pool, user_id).await?;
+
+    let profile = json!({
+        "id": user.id,
+        "username": user.username,
+        "email": user.email,
+        "emailVerifiedAt": user.email_verified_at.map(datetime_to_string),
+        "bio": user.bio,
+        "image": user.image,
+        "role": user.role,
+        "createdAt": datetime_to_string(user.created_at),
+    });
+    let comments_json: Vec<Value> = comments
+        .iter()
+        .map(|(slug, comment)| {
+            json!({
+                "id": comment.id,
+                "article": slug,
+                "body": comment.body,
+                "createdAt": datetime_to_string(comment.created_at),
+                "updatedAt": datetime_to_string(comment.updated_at),
+            })
+        })
+        .collect();
+    let comments_markdown: String = comments
+        .iter()
+        .map(|(slug, comment)| {
+            format!(
+                "## On `{}`, {}\n\n{}\n\n",
+                slug,
+                datetime_to_string(comment.created_at),
+                comment.body
+            )
+        })
+        .collect();
+    let favourites_json: Vec<Value> = favourites
+        .iter()
+        .map(|article| {
+            json!({
+                "slug": article.slug,
+                "title": article.title,
+                "author": article.author_username,
+            })
+        })
+        .collect();
+    let sessions_json: Vec<Value> = sessions
+        .iter()
+        .map(|session| {
+            json!({
+                "id": session.id,
+                "userAgent": session.user_agent,
+                "ipAddress": session.ip_address,
+                "createdAt": datetime_to_string(session.created_at),
+                "lastSeenAt": session.last_seen_at.map(datetime_to_string),
+                "signedOutAt": session.revoked_at.map(datetime_to_string),
+            })
+        })
+        .collect();
+    let api_keys: Vec<ApiKeyResponse> = api_keys.into_iter().map(ApiKeyResponse::new).collect();
+
+    let mut files = vec![
+        ("README.md".to_owned(), EXPORT_README.as_bytes().to_vec()),
+        ("profile.json".to_owned(), to_json(&profile)?),
+        ("comments.json".to_owned(), to_json(&comments_json)?),
+        (
+            "comments.md".to_owned(),
+            format!("# Comments\n\n{}", comments_markdown).into_bytes(),
+        ),
+        ("favourites.json".to_owned(), to_json(&favourites_json)?),
+        (
+            "follows.json".to_owned(),
+            to_json(&json!({ "following": following, "followers": followers }))?,
+        ),
+        ("sessions.json".to_owned(), to_json(&sessions_json)?),
+        ("api_keys.json".to_owned(), to_json(&api_keys)?),
+    ];
+    for article in &articles {
+        files.push((
+            //? Slugs come from titles, which may contain path separators
+            format!("articles/{}.md", article.slug.replace(['/', '\\'], "-")),
+            article_to_markdown(article).into_bytes(),
+        ));
+    }
+    let articles: Vec<ArticleResponse> = articles.into_iter().map(ArticleResponse::new).collect();
+    files.push(("articles.json".to_owned(), to_json(&articles)?));
+
+    let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, contents) in files {
+        archive
+            .start_file(name, FileOptions::default())
+            .map_err(export_error)?;
+        archive.write_all(&contents).map_err(export_error)?;
+    }
+    Ok(archive.finish().map_err(export_error)?.into_inner())
+}
+
+async fn write_export(pool: &SqlitePool, user_id: i64, export_id: i64) -> Result<(), RequestError> {
+    let archive = build_export_archive(pool, user_id).await?;
+    tokio::fs::create_dir_all(export_dir())
+        .await
+        .map_err(export_error)?;
+    tokio::fs::write(export_path(export_id), archive)
+        .await
+        .map_err(export_error)
+}
+
+/// Builds the export in the background and marks it ready (or failed) when done.
+pub fn spawn_data_export(pool: SqlitePool, user_id: i64, export_id: i64) {
+    tokio::spawn(async move {
+        let status = match write_export(&pool, user_id, export_id).await {
+            Ok(()) => DataExportStatus::Ready,
+            Err(e) => {
+                eprintln!("Could not export data for user {}: {:?}", user_id, e);
+                DataExportStatus::Failed
+            }
+        };
+        if let Err(e) = finish_data_export_in_db(&pool, export_id, status).await {
+            eprintln!("Could not update export {}: {:?}", export_id, e);
+        }
+    });
+}
+
+/// Starts again on the exports that were still being built when the server last stopped, which
+/// would otherwise stay pending and keep their users from asking for another.
+pub async fn resume_pending_data_exports(pool: &SqlitePool) -> Result<(), RequestError> {
+    for (export_id, user_id) in list_pending_data_exports_in_db(pool).await? {
+        spawn_data_export(pool.clone(), user_id, export_id);
+    }
+    Ok(())
+}
+
+/// Deletes the archives of these exports, for when the account they belong to is purged.
+pub async fn remove_export_files(export_ids: &[i64]) -> Result<(), RequestError> {
+    for &export_id in export_ids {
+        match tokio::fs::remove_file(export_path(export_id)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(export_error(e)),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Deletes expired exports, including the files of users that have since been deleted.
+async fn remove_expired_exports(pool: &SqlitePool) -> Result<(), RequestError> {
+    let live_exports = delete_expired_data_exports_in_db(pool).await?;
+    let mut entries = match tokio::fs::read_dir(export_dir()).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(export_error(e)),
+    };
+    while let Some(entry) = entries.next_entry().await.map_err(export_error)? {
+        let export_id = entry
+            .path()
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<i64>().ok());
+        if matches!(export_id, Some(id) if !live_exports.contains(&id)) {
+            tokio::fs::remove_file(entry.path())
+                .await
+                .map_err(export_error)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn spawn_data_export_cleanup(pool: SqlitePool) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(DATA_EXPORT_CLEANUP_INTERVAL));
+        loop {
+            interval.tick().await;
+            if let Err(e) = remove_expired_exports(&pool).await {
+                eprintln!("Could not remove expired exports: {:?}", e);
+            }
+        }
+    });
+}
@@ -2,9 +2,25 @@ pub mod request;
 pub mod response;
 pub mod wrapper;
 
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Timelike, Utc};
 use serde::{Deserialize, Serialize};
 
+/// The orders article lists can come in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum ArticleSort {
+    /// Most recently published first
+    #[default]
+    Newest,
+    Oldest,
+    MostFavourited,
+    RecentlyUpdated,
+    MostCommented,
+    /// Favourites and comments, weighed down by how long ago the article was published
+    Trending,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ArticleQueryParams {
     #[serde(default)]
@@ -17,21 +33,98 @@ pub struct ArticleQueryParams {
     pub limit: u32,
     #[serde(default)]
     pub offset: u32,
+    /// Where to carry on from, as handed out in `nextCursor`. Takes the place of `offset`, and
+    /// keeps the sort the cursor was made with.
+    #[serde(default)]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub sort: ArticleSort,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
-pub struct FeedQueryParams {
+pub struct SearchQueryParams {
+    #[serde(default)]
+    pub q: String,
+    #[serde(default)]
+    pub tag: Option<String>,
+    #[serde(default)]
+    pub author: Option<String>,
     #[serde(default = "get_default_limit")]
-    limit: u32,
+    pub limit: u32,
+    #[serde(default)]
+    pub offset: u32,
+    /// Best match first unless asked for one of the orders article lists come in
+    #[serde(default)]
+    pub sort: Option<ArticleSort>,
+}
+
+/// Comments are all returned at once unless a `limit` or a `cursor` is given.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CommentQueryParams {
     #[serde(default)]
-    offset: u32,
+    pub limit: Option<u32>,
+    #[serde(default)]
+    pub offset: u32,
+    #[serde(default)]
+    pub cursor: Option<String>,
+}
+
+impl CommentQueryParams {
+    /// How many comments a page holds, if the comments are paged at all. Carrying on from a
+    /// cursor without a `limit` gets the same default as article lists.
+    pub fn page_limit(&self) -> Option<u32> {
+        self.limit
+            .or_else(|| self.cursor.as_ref().map(|_| get_default_limit()))
+    }
+}
+
+/// Lets clients that would rather not render Markdown themselves ask for `bodyHtml` with
+/// `?html=true`.
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct BodyFormatQueryParams {
+    #[serde(default)]
+    pub html: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RevisionDiffQueryParams {
+    /// The revision to compare against, the one before by default
+    #[serde(default)]
+    pub against: Option<i64>,
 }
 
 fn get_default_limit() -> u32 {
     20
 }
 
-fn datetime_to_string(date: NaiveDateTime) -> String {
+pub fn datetime_to_string(date: NaiveDateTime) -> String {
     let date: DateTime<Utc> = DateTime::from_utc(date, Utc);
     date.to_rfc3339()
 }
+
+/// Reads an optional RFC 3339 timestamp from a request as a UTC time, to the second like the
+/// timestamps SQLite makes.
+pub fn deserialize_optional_datetime<'de, D>(
+    deserializer: D,
+) -> Result<Option<NaiveDateTime>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = match Option::<String>::deserialize(deserializer)? {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let date = DateTime::parse_from_rfc3339(&value)
+        .map_err(|_| serde::de::Error::custom("expected an RFC 3339 timestamp"))?;
+    Ok(date.naive_utc().with_nanosecond(0))
+}
+
+pub fn serialize_optional_datetime<S>(
+    date: &Option<NaiveDateTime>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    date.map(datetime_to_string).serialize(serializer)
+}
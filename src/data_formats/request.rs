@@ -1,6 +1,11 @@
 use serde::{Deserialize, Serialize};
 
+use chrono::NaiveDateTime;
+
 use super::wrapper::Tags;
+use super::{deserialize_optional_datetime, serialize_optional_datetime};
+use crate::api_keys::ApiKeyScope;
+use crate::models::{ArticleStatus, ContentAction, Role};
 
 // ----------------- User Request -----------------
 #[derive(Deserialize, Serialize, Debug)]
@@ -9,11 +14,63 @@ pub struct LoginRequest {
     pub password: String,
 }
 
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct OidcAuthorizationRequest {
+    /// Only needed to sign up while registration is invite-only
+    #[serde(default, rename = "invitationCode")]
+    pub invitation_code: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct OidcCallbackRequest {
+    pub code: String,
+    pub state: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TwoFactorLoginRequest {
+    #[serde(rename = "challengeToken")]
+    pub challenge_token: String,
+    #[serde(default)]
+    pub code: Option<String>,
+    #[serde(default, rename = "recoveryCode")]
+    pub recovery_code: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TwoFactorCodeRequest {
+    pub code: String,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct RegisterRequest {
     pub email: String,
     pub password: String,
     pub username: String,
+    #[serde(default, rename = "invitationCode")]
+    pub invitation_code: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RefreshTokenRequest {
+    #[serde(rename = "refreshToken")]
+    pub refresh_token: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String,
 }
 
 #[derive(Deserialize, Serialize, Debug, Default)]
@@ -26,6 +83,35 @@ pub struct UpdateUserRequest {
     pub password: Option<String>,
 }
 
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DeleteUserRequest {
+    pub content: ContentAction,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct UpdateRoleRequest {
+    pub role: Role,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CreateInvitationRequest {
+    #[serde(default = "get_default_invitation_uses", rename = "maxUses")]
+    pub max_uses: i64,
+    /// Seconds until the invitation expires, `INVITATION_EXPIRY_DURATION` when not given
+    #[serde(default, rename = "expiresIn")]
+    pub expires_in: Option<i64>,
+}
+
+fn get_default_invitation_uses() -> i64 {
+    1
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<ApiKeyScope>,
+}
+
 // ----------------- Article Request -----------------
 #[derive(Deserialize, Serialize, Debug)]
 pub struct CreateArticleRequest {
@@ -34,6 +120,17 @@ pub struct CreateArticleRequest {
     pub body: String,
     #[serde(flatten)]
     pub tag_list: Option<Tags>,
+    /// Published straight away unless asked otherwise
+    #[serde(default)]
+    pub status: Option<ArticleStatus>,
+    /// Keeps the article hidden until this time, when it is published
+    #[serde(
+        default,
+        rename = "publishAt",
+        deserialize_with = "deserialize_optional_datetime",
+        serialize_with = "serialize_optional_datetime"
+    )]
+    pub publish_at: Option<NaiveDateTime>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -44,6 +141,15 @@ pub struct UpdateArticleRequest {
     pub description: Option<String>,
     #[serde(default)]
     pub body: Option<String>,
+    #[serde(default)]
+    pub status: Option<ArticleStatus>,
+    /// Replaces all of the article's tags
+    #[serde(default, rename = "tagList")]
+    pub tag_list: Option<Vec<String>>,
+    #[serde(default, rename = "addTags")]
+    pub add_tags: Option<Vec<String>>,
+    #[serde(default, rename = "removeTags")]
+    pub remove_tags: Option<Vec<String>>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -1,15 +1,125 @@
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, TextDiff};
 
-use crate::models::{Article, Comment, User};
+use crate::api_keys::{scopes_from_string, ApiKeyScope};
+use crate::models::{
+    ApiKey, Article, ArticleRevision, ArticleSearchResult, ArticleStatus, Comment, ContentAction,
+    DataExport, DataExportStatus, Invitation, Role, Session, User,
+};
 
 use super::{datetime_to_string, wrapper::Tags};
 #[derive(Deserialize, Serialize, Debug)]
 pub struct UserResponse {
     pub email: String,
-    pub token: String,
+    /// Left out for requests made with an API key, which is never echoed back
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
     pub username: String,
     pub bio: String,
     pub image: Option<String>,
+    #[serde(rename = "emailVerified")]
+    pub email_verified: bool,
+    pub role: Role,
+    #[serde(rename = "refreshToken", skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TwoFactorChallengeResponse {
+    #[serde(rename = "challengeToken")]
+    pub challenge_token: String,
+    #[serde(rename = "expiresIn")]
+    pub expires_in: i64,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct OidcAuthorizationResponse {
+    #[serde(rename = "authorizationUrl")]
+    pub authorization_url: String,
+    pub state: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct TwoFactorSetupResponse {
+    pub secret: String,
+    #[serde(rename = "otpauthUri")]
+    pub otpauth_uri: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RecoveryCodesResponse {
+    #[serde(rename = "recoveryCodes")]
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ApiKeyResponse {
+    pub id: i64,
+    pub name: String,
+    pub scopes: Vec<ApiKeyScope>,
+    #[serde(rename = "createdAt")]
+    pub created_at: String,
+    #[serde(rename = "lastUsedAt")]
+    pub last_used_at: Option<String>,
+    /// Only sent back when the key is created, it can't be recovered afterwards.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct AccountDeletionResponse {
+    #[serde(rename = "scheduledFor")]
+    pub scheduled_for: String,
+    pub content: ContentAction,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DataExportResponse {
+    pub id: i64,
+    pub status: DataExportStatus,
+    #[serde(rename = "createdAt")]
+    pub created_at: String,
+    #[serde(rename = "completedAt")]
+    pub completed_at: Option<String>,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: String,
+    /// Only sent back when the export is requested, it can't be recovered afterwards.
+    #[serde(rename = "downloadUrl", skip_serializing_if = "Option::is_none")]
+    pub download_url: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct InvitationResponse {
+    pub id: i64,
+    #[serde(rename = "maxUses")]
+    pub max_uses: i64,
+    pub uses: i64,
+    #[serde(rename = "createdAt")]
+    pub created_at: String,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: String,
+    pub revoked: bool,
+    /// Only sent back when the invitation is created, it can't be recovered afterwards.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SessionResponse {
+    pub id: i64,
+    #[serde(rename = "userAgent")]
+    pub user_agent: Option<String>,
+    #[serde(rename = "ipAddress")]
+    pub ip_address: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: String,
+    #[serde(rename = "lastSeenAt")]
+    pub last_seen_at: Option<String>,
+    /// Whether this is the session the request was made with.
+    pub current: bool,
 }
 
 #[derive(Deserialize, Serialize, Debug, Default, Clone)]
@@ -26,6 +136,9 @@ pub struct ArticleResponse {
     title: String,
     description: String,
     body: String,
+    /// `body` rendered from Markdown, only when asked for
+    #[serde(rename = "bodyHtml", skip_serializing_if = "Option::is_none")]
+    body_html: Option<String>,
     #[serde(flatten)]
     tag_list: Tags,
     #[serde(rename = "createdAt")]
@@ -36,6 +149,61 @@ pub struct ArticleResponse {
     #[serde(rename = "favoritesCount")]
     favorites_count: i64,
     author: ProfileResponse,
+    status: ArticleStatus,
+    #[serde(rename = "publishedAt")]
+    published_at: Option<String>,
+    #[serde(rename = "publishAt", skip_serializing_if = "Option::is_none")]
+    publish_at: Option<String>,
+    /// Only in search results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    highlight: Option<SearchHighlightResponse>,
+}
+
+/// Where an article matched a search, as HTML escaped text with the matches in `<mark>` tags.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SearchHighlightResponse {
+    pub title: String,
+    pub snippet: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RevisionResponse {
+    pub number: i64,
+    pub editor: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: String,
+    pub changes: Vec<String>,
+    /// Only sent back when a single revision is asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffOperation {
+    Equal,
+    Insert,
+    Delete,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DiffLineResponse {
+    pub operation: DiffOperation,
+    pub line: String,
+}
+
+/// Line by line changes to each field going from one revision to another.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RevisionDiffResponse {
+    pub from: i64,
+    pub to: i64,
+    pub title: Vec<DiffLineResponse>,
+    pub description: Vec<DiffLineResponse>,
+    pub body: Vec<DiffLineResponse>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -56,16 +224,204 @@ impl UserResponse {
             email,
             bio,
             image,
+            email_verified_at,
+            role,
             ..
         }: User,
-        token: String,
+        token: impl Into<Option<String>>,
     ) -> Self {
         UserResponse {
             username,
             email,
             bio: bio.unwrap_or_default(),
             image,
-            token,
+            email_verified: email_verified_at.is_some(),
+            role,
+            token: token.into(),
+            refresh_token: None,
+        }
+    }
+
+    pub fn with_refresh_token(mut self, refresh_token: String) -> Self {
+        self.refresh_token = Some(refresh_token);
+        self
+    }
+}
+
+impl RevisionResponse {
+    pub fn new(
+        ArticleRevision {
+            number,
+            editor_username,
+            title,
+            description,
+            body,
+            changes,
+            created_at,
+        }: ArticleRevision,
+    ) -> Self {
+        RevisionResponse {
+            number,
+            editor: editor_username,
+            created_at: datetime_to_string(created_at),
+            changes: changes.split(',').map(str::to_owned).collect(),
+            title: Some(title),
+            description: Some(description),
+            body: Some(body),
+        }
+    }
+
+    /// Who made the revision and what it changed, without the content.
+    pub fn summary(revision: ArticleRevision) -> Self {
+        RevisionResponse {
+            title: None,
+            description: None,
+            body: None,
+            ..RevisionResponse::new(revision)
+        }
+    }
+}
+
+/// Diffs by line, ignoring whether the last line ends in a newline. Gives up looking for the
+/// smallest diff after a second so that huge articles can't tie up the server.
+fn diff_lines(old: &str, new: &str) -> Vec<DiffLineResponse> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    TextDiff::configure()
+        .timeout(Duration::from_secs(1))
+        .diff_slices(&old, &new)
+        .iter_all_changes()
+        .map(|change| DiffLineResponse {
+            operation: match change.tag() {
+                ChangeTag::Equal => DiffOperation::Equal,
+                ChangeTag::Insert => DiffOperation::Insert,
+                ChangeTag::Delete => DiffOperation::Delete,
+            },
+            line: change.value().to_owned(),
+        })
+        .collect()
+}
+
+impl RevisionDiffResponse {
+    pub fn new(from: &ArticleRevision, to: &ArticleRevision) -> Self {
+        RevisionDiffResponse {
+            from: from.number,
+            to: to.number,
+            title: diff_lines(&from.title, &to.title),
+            description: diff_lines(&from.description, &to.description),
+            body: diff_lines(&from.body, &to.body),
+        }
+    }
+}
+
+impl ApiKeyResponse {
+    pub fn new(
+        ApiKey {
+            id,
+            name,
+            scopes,
+            created_at,
+            last_used_at,
+        }: ApiKey,
+    ) -> Self {
+        ApiKeyResponse {
+            id,
+            name,
+            scopes: scopes_from_string(&scopes),
+            created_at: datetime_to_string(created_at),
+            last_used_at: last_used_at.map(datetime_to_string),
+            key: None,
+        }
+    }
+
+    pub fn with_key(mut self, key: String) -> Self {
+        self.key = Some(key);
+        self
+    }
+}
+
+impl AccountDeletionResponse {
+    pub fn new(scheduled_for: NaiveDateTime, content: ContentAction) -> Self {
+        AccountDeletionResponse {
+            scheduled_for: datetime_to_string(scheduled_for),
+            content,
+        }
+    }
+}
+
+impl DataExportResponse {
+    pub fn new(
+        DataExport {
+            id,
+            status,
+            created_at,
+            completed_at,
+            expires_at,
+        }: DataExport,
+    ) -> Self {
+        DataExportResponse {
+            id,
+            status,
+            created_at: datetime_to_string(created_at),
+            completed_at: completed_at.map(datetime_to_string),
+            expires_at: datetime_to_string(expires_at),
+            download_url: None,
+        }
+    }
+
+    pub fn with_download_url(mut self, download_url: String) -> Self {
+        self.download_url = Some(download_url);
+        self
+    }
+}
+
+impl InvitationResponse {
+    pub fn new(
+        Invitation {
+            id,
+            max_uses,
+            uses,
+            created_at,
+            expires_at,
+            revoked_at,
+        }: Invitation,
+    ) -> Self {
+        InvitationResponse {
+            id,
+            max_uses,
+            uses,
+            created_at: datetime_to_string(created_at),
+            expires_at: datetime_to_string(expires_at),
+            revoked: revoked_at.is_some(),
+            code: None,
+        }
+    }
+
+    pub fn with_code(mut self, code: String) -> Self {
+        self.code = Some(code);
+        self
+    }
+}
+
+impl SessionResponse {
+    pub fn new(
+        Session {
+            id,
+            user_agent,
+            ip_address,
+            created_at,
+            last_seen_at,
+            ..
+        }: Session,
+        current_session_id: i64,
+    ) -> Self {
+        SessionResponse {
+            id,
+            user_agent,
+            ip_address,
+            created_at: datetime_to_string(created_at),
+            last_seen_at: last_seen_at.map(datetime_to_string),
+            current: id == current_session_id,
         }
     }
 }
@@ -126,6 +482,9 @@ impl ArticleResponse {
             author_image,
             author_bio,
             following,
+            status,
+            published_at,
+            publish_at,
             ..
         }: Article,
     ) -> Self {
@@ -134,8 +493,13 @@ impl ArticleResponse {
             title,
             description,
             body,
+            body_html: None,
             tag_list: Tags {
-                tag_list: tag_list.split(',').map(|s| s.to_string()).collect(),
+                tag_list: tag_list
+                    .split(',')
+                    .filter(|s| !s.is_empty())
+                    .map(|s| s.to_string())
+                    .collect(),
             },
             created_at: datetime_to_string(created_at),
             updated_at: datetime_to_string(updated_at),
@@ -147,6 +511,52 @@ impl ArticleResponse {
                 image: author_image,
                 following,
             },
+            status,
+            published_at: published_at.map(datetime_to_string),
+            publish_at: publish_at.map(datetime_to_string),
+            highlight: None,
+        }
+    }
+
+    pub fn from_search_result(
+        ArticleSearchResult {
+            article,
+            title_highlight,
+            snippet,
+        }: ArticleSearchResult,
+    ) -> Self {
+        ArticleResponse {
+            highlight: Some(SearchHighlightResponse {
+                title: mark_matches(&title_highlight),
+                snippet: mark_matches(&snippet),
+            }),
+            ..ArticleResponse::new(article)
+        }
+    }
+
+    pub fn with_body_html(self, body_html: String) -> Self {
+        ArticleResponse {
+            body_html: Some(body_html),
+            ..self
+        }
+    }
+}
+
+/// Escapes text from the search index for HTML, turning the `\u{2}`...`\u{3}` around each match
+/// into `<mark>` tags.
+fn mark_matches(text: &str) -> String {
+    let mut html = String::with_capacity(text.len() + 32);
+    for c in text.chars() {
+        match c {
+            '\u{2}' => html.push_str("<mark>"),
+            '\u{3}' => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
         }
     }
+    html
 }
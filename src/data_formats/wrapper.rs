@@ -1,12 +1,72 @@
 use serde::{Deserialize, Serialize};
 
-use super::response::{ArticleResponse, CommentResponse, ProfileResponse};
+use super::response::{
+    AccountDeletionResponse, ApiKeyResponse, ArticleResponse, CommentResponse, DataExportResponse,
+    InvitationResponse, ProfileResponse, RevisionDiffResponse, RevisionResponse, SessionResponse,
+    TwoFactorChallengeResponse, UserResponse,
+};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct UserWrapper<T> {
     pub user: T,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TwoFactorWrapper<T> {
+    #[serde(rename = "twoFactor")]
+    pub two_factor: T,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AccountDeletionWrapper {
+    pub deletion: AccountDeletionResponse,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DataExportWrapper {
+    pub export: DataExportResponse,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InvitationWrapper<T> {
+    pub invitation: T,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MultipleInvitationsWrapper {
+    pub invitations: Vec<InvitationResponse>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OidcWrapper<T> {
+    pub oidc: T,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ApiKeyWrapper<T> {
+    #[serde(rename = "apiKey")]
+    pub api_key: T,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MultipleApiKeysWrapper {
+    #[serde(rename = "apiKeys")]
+    pub api_keys: Vec<ApiKeyResponse>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MultipleSessionsWrapper {
+    pub sessions: Vec<SessionResponse>,
+}
+
+/// `login_user` either logs the user in or asks for their second factor.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum LoginWrapper {
+    User(UserWrapper<UserResponse>),
+    TwoFactorRequired(TwoFactorWrapper<TwoFactorChallengeResponse>),
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ProfileWrapper {
     pub profile: ProfileResponse,
@@ -25,13 +85,34 @@ pub struct ArticleWrapper<T> {
 #[derive(Debug, Deserialize, Serialize)]
 pub struct MultipleArticlesWrapper {
     pub articles: Vec<ArticleResponse>,
+    /// Across all pages, not just this one
     #[serde(rename = "articlesCount")]
-    pub article_count: usize,
+    pub article_count: i64,
+    /// Passed back as `cursor` for the next page, if there is one
+    #[serde(rename = "nextCursor", skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RevisionWrapper {
+    pub revision: RevisionResponse,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MultipleRevisionsWrapper {
+    pub revisions: Vec<RevisionResponse>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RevisionDiffWrapper {
+    pub diff: RevisionDiffResponse,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct MultipleCommentsWrapper {
     pub comments: Vec<CommentResponse>,
+    #[serde(rename = "nextCursor", skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Default)]
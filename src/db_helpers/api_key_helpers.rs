@@ -0,0 +1,129 @@
+use sqlx::SqlitePool;
+
+use crate::{errors::RequestError, models::ApiKey};
+
+use super::LAST_USED_RESOLUTION_SECONDS;
+
+pub async fn create_api_key_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+    name: &str,
+    key_hash: &str,
+    scopes: &str,
+) -> Result<ApiKey, RequestError> {
+    let mut tx = pool.begin().await?;
+    let api_key_id = sqlx::query!(
+        r#"
+        INSERT INTO api_keys (user_id, name, key_hash, scopes)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id as "id!"
+        "#,
+        user_id,
+        name,
+        key_hash,
+        scopes
+    )
+    .fetch_one(&mut tx)
+    .await?
+    .id;
+
+    let result = sqlx::query_as!(
+        ApiKey,
+        r#"
+        SELECT id as "id!", name, scopes, created_at as "created_at!", last_used_at
+        FROM api_keys WHERE id = $1
+        "#,
+        api_key_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result)
+}
+
+pub async fn list_api_keys_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+) -> Result<Vec<ApiKey>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query_as!(
+        ApiKey,
+        r#"
+        SELECT id as "id!", name, scopes, created_at as "created_at!", last_used_at
+        FROM api_keys WHERE user_id = $1 AND revoked_at IS NULL
+        ORDER BY id
+        "#,
+        user_id
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result)
+}
+
+pub async fn revoke_api_key_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+    api_key_id: i64,
+) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        UPDATE api_keys SET revoked_at = CURRENT_TIMESTAMP
+        WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        api_key_id,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(RequestError::NotFound("API key not found"));
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Looks up an active key, marks it as used and returns the `(user_id, scopes)` it belongs to.
+/// `last_used_at` is only written when it is older than `LAST_USED_RESOLUTION_SECONDS`.
+pub async fn use_api_key_in_db(
+    pool: &SqlitePool,
+    key_hash: &str,
+) -> Result<Option<(i64, String)>, RequestError> {
+    let api_key = sqlx::query!(
+        r#"
+        SELECT api_keys.id as "id!", user_id, scopes,
+            (last_used_at IS NULL OR last_used_at <= datetime('now', '-' || $2 || ' seconds'))
+                as "is_stale!: bool"
+        FROM api_keys
+            JOIN users ON users.id = api_keys.user_id
+        WHERE key_hash = $1 AND revoked_at IS NULL AND users.deletion_requested_at IS NULL
+        "#,
+        key_hash,
+        LAST_USED_RESOLUTION_SECONDS
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let api_key = match api_key {
+        Some(api_key) => api_key,
+        None => return Ok(None),
+    };
+
+    if api_key.is_stale {
+        //? Checked again here so concurrent requests with the same key only write once
+        sqlx::query!(
+            r#"
+            UPDATE api_keys SET last_used_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+                AND (last_used_at IS NULL OR last_used_at <= datetime('now', '-' || $2 || ' seconds'))
+            "#,
+            api_key.id,
+            LAST_USED_RESOLUTION_SECONDS
+        )
+        .execute(pool)
+        .await?;
+    }
+    Ok(Some((api_key.user_id, api_key.scopes)))
+}
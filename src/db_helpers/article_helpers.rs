@@ -1,67 +1,111 @@
-use sqlx::{Sqlite, SqlitePool};
+use chrono::{NaiveDateTime, Utc};
+use sqlx::{Sqlite, SqlitePool, Transaction};
 
 use crate::data_formats::request::CreateArticleRequest;
 use crate::data_formats::wrapper::Tags;
-use crate::data_formats::{request::UpdateArticleRequest, ArticleQueryParams};
+use crate::data_formats::{
+    request::UpdateArticleRequest, ArticleQueryParams, ArticleSort, SearchQueryParams,
+};
 use crate::errors::RequestError;
-use crate::models::Article;
+use crate::models::{Article, ArticleSearchResult, ArticleStatus, CountedArticle};
+use crate::pagination::{ArticlePosition, Page};
 use crate::slugify;
 
+use super::revision_helpers::record_article_revision;
+use super::tag_helpers::{
+    add_article_tags, clear_article_tags, delete_unused_tags, remove_article_tags,
+};
 use super::{get_user_by_username, QueryBuilder};
 
+//? Filters are subqueries rather than joins so each article is one row, which lets the window
+//? count every matching article. The cursor is applied outside, so the count isn't limited to
+//? the articles after it either. Every sort is a number to order by, picked with the bound sort
+//? name, so the ordering never has to be put together as SQL
 const ARTICLE_QUERY: &str = r#"
-            SELECT DISTINCT articles.id                                   AS "id",
-                            title                                         AS "title",
-                            slug                                          AS "slug",
-                            body                                          AS "body",
-                            description                                   AS "description",
-                            author_id                                     AS "author_id",
-                            articles.created_at                           AS "created_at",
-                            updated_at                                    AS "updated_at",
-                            (SELECT Group_concat(tags.name, ',')
-                            FROM   tags
-                                    JOIN articletags
-                                    ON articletags.tag_id = tags.id
-                            WHERE  articletags.article_id = articles.id) AS "tag_list",
-                            users.username                                AS
-                            "author_username",
-                            users.image                                   AS "author_image",
-                            users.bio                                     AS "author_bio",
-                            (SELECT Count(favourite.article_id)
-                            FROM   favourite
-                            WHERE  favourite.article_id = articles.id)   AS
-                            "favorites_count",
-                            EXISTS (SELECT 1
-                                    FROM   favourite
-                                    WHERE  favourite.article_id = articles.id
-                                        AND favourite.user_id = $1)    AS "favorited",
-                            EXISTS (SELECT 1
-                                    FROM   follows
-                                    WHERE  followed_id = articles.author_id
-                                        AND follower_id = $1)          AS "following"
-            FROM   articles
-                JOIN users
-                    ON articles.author_id = users.id
-                LEFT JOIN favourite
-                        ON favourite.article_id = articles.id
-                LEFT JOIN follows
-                        ON follows.followed_id = articles.author_id
-                            AND ( follows.follower_id = $1
-                                    OR $1 IS NULL )
-                LEFT JOIN articletags
-                        ON articletags.article_id = articles.id
-                LEFT JOIN tags
-                        ON tags.id = articletags.tag_id
-            WHERE  ( users.username = $2
-                    OR $2 IS NULL )
-                AND ( favourite.user_id = $6
-                        OR $6 IS NULL )
-                AND ( tags.name = $3
-                        OR $3 IS NULL )
-                AND ( follows.follower_id = $7
-                        OR $7 IS NULL )
-            ORDER  BY articles.created_at DESC
-            LIMIT  $4 offset $5 
+            SELECT *
+            FROM   (SELECT *,
+                           Cast(CASE $11
+                                  WHEN 'oldest' THEN -Julianday(listed_at)
+                                  WHEN 'most_favourited' THEN favorites_count
+                                  WHEN 'recently_updated' THEN Julianday(updated_at)
+                                  WHEN 'most_commented' THEN comments_count
+                                  WHEN 'trending' THEN ( favorites_count + comments_count ) /
+                                                       ( ( age_hours + 2 ) * ( age_hours + 2 ) )
+                                  ELSE Julianday(listed_at)
+                                END AS REAL)                          AS "sort_key",
+                           CASE $11
+                             WHEN 'oldest' THEN -id
+                             ELSE id
+                           END                                        AS "sort_id"
+                    FROM   (SELECT articles.id                                        AS "id",
+                               title                                                  AS "title",
+                               slug                                                   AS "slug",
+                               body                                                   AS "body",
+                               description                                            AS "description",
+                               author_id                                              AS "author_id",
+                               articles.created_at                                    AS "created_at",
+                               updated_at                                             AS "updated_at",
+                               articles.status                                        AS "status",
+                               articles.published_at                                  AS "published_at",
+                               articles.publish_at                                    AS "publish_at",
+                               (SELECT Group_concat(tags.name, ',')
+                                FROM   tags
+                                       JOIN articletags
+                                         ON articletags.tag_id = tags.id
+                                WHERE  articletags.article_id = articles.id)          AS "tag_list",
+                               users.username                                         AS "author_username",
+                               users.image                                            AS "author_image",
+                               users.bio                                              AS "author_bio",
+                               (SELECT Count(favourite.article_id)
+                                FROM   favourite
+                                WHERE  favourite.article_id = articles.id)            AS "favorites_count",
+                               EXISTS (SELECT 1
+                                       FROM   favourite
+                                       WHERE  favourite.article_id = articles.id
+                                              AND favourite.user_id = $1)             AS "favorited",
+                               EXISTS (SELECT 1
+                                       FROM   follows
+                                       WHERE  followed_id = articles.author_id
+                                              AND follower_id = $1)                   AS "following",
+                               (SELECT Count(*)
+                                FROM   comments
+                                WHERE  comments.article_id = articles.id)             AS "comments_count",
+                               COALESCE(articles.published_at, articles.created_at)   AS "listed_at",
+                               Max(Julianday($12) - Julianday(COALESCE(articles.published_at,
+                                                                       articles.created_at)), 0)
+                                   * 24                                               AS "age_hours",
+                               Count(*) OVER ()                                       AS "total_count"
+                        FROM   articles
+                            JOIN users
+                                ON articles.author_id = users.id
+                        WHERE  ( users.username = $2
+                                OR $2 IS NULL )
+                            AND ( $6 IS NULL
+                                    OR EXISTS (SELECT 1
+                                               FROM   favourite
+                                               WHERE  favourite.article_id = articles.id
+                                                      AND favourite.user_id = $6) )
+                            AND ( $3 IS NULL
+                                    OR EXISTS (SELECT 1
+                                               FROM   articletags
+                                                      JOIN tags
+                                                        ON tags.id = articletags.tag_id
+                                               WHERE  articletags.article_id = articles.id
+                                                      AND tags.name = $3) )
+                            AND ( $7 IS NULL
+                                    OR EXISTS (SELECT 1
+                                               FROM   follows
+                                               WHERE  follows.followed_id = articles.author_id
+                                                      AND follows.follower_id = $7) )
+                            AND ( articles.status = $8
+                                    OR $8 IS NULL )
+                            AND ( articles.status = 'published'
+                                    OR articles.author_id = $1 )))
+            WHERE  ( $9 IS NULL
+                        OR ( sort_key, sort_id ) < ( $9, $10 ) )
+            ORDER  BY sort_key DESC,
+                      sort_id DESC
+            LIMIT  $4 offset $5
      "#;
 
 const SINGLE_ARTICLE_QUERY: &str = r#"
@@ -73,6 +117,9 @@ const SINGLE_ARTICLE_QUERY: &str = r#"
                             author_id                                     AS "author_id",
                             articles.created_at                           AS "created_at",
                             updated_at                                    AS "updated_at",
+                            articles.status                               AS "status",
+                            articles.published_at                         AS "published_at",
+                            articles.publish_at                           AS "publish_at",
                             (SELECT Group_concat(tags.NAME, ',')
                             FROM   tags
                                     JOIN articletags
@@ -109,6 +156,244 @@ const SINGLE_ARTICLE_QUERY: &str = r#"
                     OR $2 IS NULL )  
 "#;
 
+//? bm25 ranks lower as better. Weighted by column: title, description, body, tags. A sort other
+//? than the best match ($7) uses the same keys as `ARTICLE_QUERY`, with the rank breaking ties.
+//? Highlighting has to happen on the FTS table itself, so the keys are worked out around it
+const SEARCH_QUERY: &str = r#"
+            SELECT *
+            FROM   (SELECT articles.id                                            AS "id",
+                           articles.title                                         AS "title",
+                           articles.slug                                          AS "slug",
+                           articles.body                                          AS "body",
+                           articles.description                                   AS "description",
+                           articles.author_id                                     AS "author_id",
+                           articles.created_at                                    AS "created_at",
+                           articles.updated_at                                    AS "updated_at",
+                           articles.status                                        AS "status",
+                           articles.published_at                                  AS "published_at",
+                           articles.publish_at                                    AS "publish_at",
+                           (SELECT Group_concat(tags.name, ',')
+                            FROM   tags
+                                   JOIN articletags
+                                     ON articletags.tag_id = tags.id
+                            WHERE  articletags.article_id = articles.id)          AS "tag_list",
+                           users.username                                         AS "author_username",
+                           users.image                                            AS "author_image",
+                           users.bio                                              AS "author_bio",
+                           (SELECT Count(favourite.article_id)
+                            FROM   favourite
+                            WHERE  favourite.article_id = articles.id)            AS "favorites_count",
+                           EXISTS (SELECT 1
+                                   FROM   favourite
+                                   WHERE  favourite.article_id = articles.id
+                                          AND favourite.user_id = $1)             AS "favorited",
+                           EXISTS (SELECT 1
+                                   FROM   follows
+                                   WHERE  followed_id = articles.author_id
+                                          AND follower_id = $1)                   AS "following",
+                           highlight(articles_fts, 0, char(2), char(3))           AS "title_highlight",
+                           snippet(articles_fts, 2, char(2), char(3), '…', 24)    AS "snippet",
+                           bm25(articles_fts, 10.0, 4.0, 1.0, 6.0)                AS "match_rank",
+                           (SELECT Count(*)
+                            FROM   comments
+                            WHERE  comments.article_id = articles.id)             AS "comments_count",
+                           COALESCE(articles.published_at, articles.created_at)   AS "listed_at",
+                           Max(Julianday('now') - Julianday(COALESCE(articles.published_at,
+                                                                     articles.created_at)), 0)
+                               * 24                                               AS "age_hours"
+                    FROM   articles_fts
+                        JOIN articles
+                            ON articles.id = articles_fts.rowid
+                        JOIN users
+                            ON articles.author_id = users.id
+                    WHERE  articles_fts MATCH $2
+                        AND ( users.username = $3
+                                OR $3 IS NULL )
+                        AND ( $4 IS NULL
+                                OR EXISTS (SELECT 1
+                                           FROM   articletags
+                                                  JOIN tags
+                                                    ON tags.id = articletags.tag_id
+                                           WHERE  articletags.article_id = articles.id
+                                                  AND tags.name = $4) )
+                        AND ( articles.status = 'published'
+                                OR articles.author_id = $1 ))
+            ORDER  BY CASE
+                        WHEN $7 IS NULL THEN 0
+                        ELSE Cast(CASE $7
+                                    WHEN 'oldest' THEN -Julianday(listed_at)
+                                    WHEN 'most_favourited' THEN favorites_count
+                                    WHEN 'recently_updated' THEN Julianday(updated_at)
+                                    WHEN 'most_commented' THEN comments_count
+                                    WHEN 'trending' THEN ( favorites_count + comments_count ) /
+                                                         ( ( age_hours + 2 ) * ( age_hours + 2 ) )
+                                    ELSE Julianday(listed_at)
+                                  END AS REAL)
+                      END DESC,
+                      match_rank,
+                      id DESC
+            LIMIT  $5 offset $6
+     "#;
+
+//? FTS5's ranking and highlighting can't be used alongside a window function, so the total
+//? is counted separately with the same filters as `SEARCH_QUERY`
+const SEARCH_COUNT_QUERY: &str = r#"
+            SELECT Count(*)
+            FROM   articles_fts
+                JOIN articles
+                    ON articles.id = articles_fts.rowid
+                JOIN users
+                    ON articles.author_id = users.id
+            WHERE  articles_fts MATCH $2
+                AND ( users.username = $3
+                        OR $3 IS NULL )
+                AND ( $4 IS NULL
+                        OR EXISTS (SELECT 1
+                                   FROM   articletags
+                                          JOIN tags
+                                            ON tags.id = articletags.tag_id
+                                   WHERE  articletags.article_id = articles.id
+                                          AND tags.name = $4) )
+                AND ( articles.status = 'published'
+                        OR articles.author_id = $1 )
+     "#;
+
+/// Turns what the user typed into an FTS5 query matching articles that contain every word, the
+/// last one possibly unfinished. Each word is quoted so FTS5 operators are searched for as text.
+fn to_fts_query(search: &str) -> Option<String> {
+    let words: Vec<String> = search
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect();
+    if words.is_empty() {
+        return None;
+    }
+    Some(format!("{}*", words.join(" ")))
+}
+
+/// Paths under `/articles` that would be routed elsewhere if an article had them as its slug.
+const RESERVED_SLUGS: &[&str] = &["feed", "search"];
+
+/// Takes SQLite's write lock before `tx` reads anything, so that a slug found free by
+/// `find_free_slug` is still free when it is written. Other writers wait for the lock instead
+/// of failing once they find their snapshot out of date.
+async fn lock_articles(tx: &mut Transaction<'_, Sqlite>) -> Result<(), RequestError> {
+    //? sqlx has no BEGIN IMMEDIATE, but any write takes the lock even if it changes nothing
+    sqlx::query!("UPDATE articles SET id = id WHERE 0")
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
+
+/// The first of `base`, `base-2`, `base-3`... that no other article uses, or used before it was
+/// renamed, so that an old link never starts pointing at a different article. Call
+/// `lock_articles` first.
+async fn find_free_slug(
+    tx: &mut Transaction<'_, Sqlite>,
+    base: &str,
+    article_id: Option<i64>,
+) -> Result<String, RequestError> {
+    let mut slug = base.to_owned();
+    let mut suffix = 1;
+    loop {
+        let in_use = sqlx::query!(
+            r#"
+            SELECT id FROM articles WHERE slug = $1 AND id IS NOT $2
+            "#,
+            slug,
+            article_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .is_some();
+        let used_before = sqlx::query!(
+            r#"
+            SELECT article_id FROM article_slug_history
+                JOIN articles ON articles.id = article_slug_history.article_id
+            WHERE article_slug_history.slug = $1 AND article_id IS NOT $2
+            "#,
+            slug,
+            article_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .is_some();
+        if !in_use && !used_before && !RESERVED_SLUGS.contains(&slug.as_str()) {
+            return Ok(slug);
+        }
+        suffix += 1;
+        slug = format!("{}-{}", base, suffix);
+    }
+}
+
+pub type ArticlePage = Page<Article, ArticlePosition>;
+
+/// Which articles `ARTICLE_QUERY` returns, as seen by `user_id`.
+#[derive(Default)]
+struct ArticleFilter {
+    user_id: Option<i64>,
+    author: Option<String>,
+    tag: Option<String>,
+    favourited_by: Option<i64>,
+    followed_by: Option<i64>,
+    status: Option<ArticleStatus>,
+}
+
+/// A page of the articles matching `filter` in `sort` order, along with how many match in total.
+/// The page starts right after `after` when given, in the order it was made for, and at `offset`
+/// otherwise.
+async fn list_articles_in_db(
+    pool: &SqlitePool,
+    filter: ArticleFilter,
+    sort: ArticleSort,
+    limit: u32,
+    offset: u32,
+    after: Option<ArticlePosition>,
+) -> Result<(ArticlePage, i64), RequestError> {
+    let offset = if after.is_some() { 0 } else { offset };
+    let sort = after.map_or(sort, |after| after.sort);
+    let as_of = after.map_or_else(|| Utc::now().naive_utc(), |after| after.as_of);
+    let mut tx = pool.begin().await?;
+    let fetch_page = |limit: u32, offset: u32, after: Option<ArticlePosition>| {
+        sqlx::query_as::<Sqlite, CountedArticle>(ARTICLE_QUERY)
+            .bind(filter.user_id)
+            .bind(filter.author.clone())
+            .bind(filter.tag.clone())
+            .bind(limit)
+            .bind(offset)
+            .bind(filter.favourited_by)
+            .bind(filter.followed_by)
+            .bind(filter.status)
+            .bind(after.map(|position| position.sort_key))
+            .bind(after.map(|position| position.sort_id))
+            .bind(sort)
+            .bind(as_of)
+    };
+    //? One more than asked for, to tell whether there is a next page
+    let page = fetch_page(limit.saturating_add(1), offset, after)
+        .fetch_all(&mut tx)
+        .await?;
+    let total_count = match page.first() {
+        Some(article) => article.total_count,
+        //? Past the last page, so no row to read the count from
+        None if offset > 0 || after.is_some() => fetch_page(1, 0, None)
+            .fetch_optional(&mut tx)
+            .await?
+            .map_or(0, |article| article.total_count),
+        None => 0,
+    };
+    tx.commit().await?;
+
+    let page = Page::from_overfetched(page, limit, |article| ArticlePosition {
+        sort,
+        sort_key: article.sort_key,
+        sort_id: article.sort_id,
+        as_of,
+    });
+    Ok((page.map(|article| article.article), total_count))
+}
+
+/// Published articles, plus the user's own, and how many there are in total.
 pub async fn list_all_articles(
     pool: &SqlitePool,
     id: Option<i64>,
@@ -118,31 +403,35 @@ pub async fn list_all_articles(
         favourited,
         limit,
         offset,
+        sort,
+        ..
     }: ArticleQueryParams,
-) -> Result<Vec<Article>, RequestError> {
-    let mut tx = pool.begin().await?;
-    let favourite_id = match &favourited {
-        Some(username) => get_user_by_username(pool, username)
-            .await?
-            .map(|user| user.id),
+    after: Option<ArticlePosition>,
+) -> Result<(ArticlePage, i64), RequestError> {
+    let favourited_by = match &favourited {
+        Some(username) => match get_user_by_username(pool, username).await? {
+            Some(user) => Some(user.id),
+            None => {
+                let page = Page {
+                    items: Vec::new(),
+                    next: None,
+                };
+                return Ok((page, 0));
+            }
+        },
         None => None,
     };
-    let article = sqlx::query_as::<Sqlite, Article>(ARTICLE_QUERY)
-        .bind(id)
-        .bind(author)
-        .bind(tag)
-        .bind(limit)
-        .bind(offset)
-        .bind(favourite_id)
-        //? This is set to none coz we want to get all the articles not just the articles that a user is following
-        .bind(Option::<String>::None)
-        .fetch_all(&mut tx)
-        .await?;
-
-    tx.commit().await?;
-    Ok(article)
+    let filter = ArticleFilter {
+        user_id: id,
+        author,
+        tag,
+        favourited_by,
+        ..Default::default()
+    };
+    list_articles_in_db(pool, filter, sort, limit, offset, after).await
 }
 
+/// Articles by the authors the user follows, and how many there are in total.
 pub async fn list_articles_feed_in_db(
     pool: &SqlitePool,
     id: i64,
@@ -152,21 +441,111 @@ pub async fn list_articles_feed_in_db(
         favourited,
         limit,
         offset,
+        sort,
+        ..
     }: ArticleQueryParams,
-) -> Result<Vec<Article>, RequestError> {
+    after: Option<ArticlePosition>,
+) -> Result<(ArticlePage, i64), RequestError> {
+    let favourited_by = match &favourited {
+        Some(username) => match get_user_by_username(pool, username).await? {
+            Some(user) => Some(user.id),
+            None => {
+                let page = Page {
+                    items: Vec::new(),
+                    next: None,
+                };
+                return Ok((page, 0));
+            }
+        },
+        None => None,
+    };
+    let filter = ArticleFilter {
+        user_id: Some(id),
+        author,
+        tag,
+        favourited_by,
+        followed_by: Some(id),
+        ..Default::default()
+    };
+    list_articles_in_db(pool, filter, sort, limit, offset, after).await
+}
+
+/// The user's own drafts in `sort` order, and how many there are in total.
+pub async fn list_drafts_in_db(
+    pool: &SqlitePool,
+    id: i64,
+    sort: ArticleSort,
+    limit: u32,
+    offset: u32,
+    after: Option<ArticlePosition>,
+) -> Result<(ArticlePage, i64), RequestError> {
+    //? Drafts are only visible to their author, so filtering on the status is enough
+    let filter = ArticleFilter {
+        user_id: Some(id),
+        status: Some(ArticleStatus::Draft),
+        ..Default::default()
+    };
+    list_articles_in_db(pool, filter, sort, limit, offset, after).await
+}
+
+/// Published articles, plus the user's own, matching the search, best first unless `sort` says
+/// otherwise, and how many match in total.
+pub async fn search_articles_in_db(
+    pool: &SqlitePool,
+    id: Option<i64>,
+    SearchQueryParams {
+        q,
+        tag,
+        author,
+        limit,
+        offset,
+        sort,
+    }: SearchQueryParams,
+) -> Result<(Vec<ArticleSearchResult>, i64), RequestError> {
+    let query = match to_fts_query(&q) {
+        Some(query) => query,
+        None => return Err(RequestError::RunTimeError("Search query cannot be empty")),
+    };
     let mut tx = pool.begin().await?;
-    let article = sqlx::query_as::<Sqlite, Article>(ARTICLE_QUERY)
+    let articles = sqlx::query_as::<Sqlite, ArticleSearchResult>(SEARCH_QUERY)
         .bind(id)
-        .bind(author)
-        .bind(tag)
+        .bind(&query)
+        .bind(&author)
+        .bind(&tag)
         .bind(limit)
         .bind(offset)
-        .bind(favourited)
-        .bind(id)
+        .bind(sort)
         .fetch_all(&mut tx)
         .await?;
+    let total_count = sqlx::query_scalar::<Sqlite, i64>(SEARCH_COUNT_QUERY)
+        .bind(id)
+        .bind(&query)
+        .bind(&author)
+        .bind(&tag)
+        .fetch_one(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok((articles, total_count))
+}
 
-    Ok(article)
+/// The slug an article that was renamed away from `old_slug` goes by now.
+pub async fn get_renamed_article_slug_in_db(
+    pool: &SqlitePool,
+    old_slug: &str,
+) -> Result<Option<String>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        SELECT articles.slug FROM article_slug_history
+            JOIN articles ON articles.id = article_slug_history.article_id
+        WHERE article_slug_history.slug = $1
+        "#,
+        old_slug
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result.map(|article| article.slug))
 }
 
 pub async fn get_article_by_slug_in_db(
@@ -195,109 +574,257 @@ pub async fn create_article_in_db(
         description,
         body,
         tag_list,
+        status,
+        publish_at,
     }: CreateArticleRequest,
 ) -> Result<Article, RequestError> {
     let mut tx = pool.begin().await?;
+    lock_articles(&mut tx).await?;
 
-    let slug = slugify(&title);
+    let slug = find_free_slug(&mut tx, &slugify(&title), None).await?;
+    //? Scheduled articles wait as drafts until the scheduler publishes them
+    let status = match publish_at {
+        Some(_) => ArticleStatus::Draft,
+        None => status.unwrap_or_default(),
+    };
 
-    let result = sqlx::query!(
+    //? No RETURNING: with it, checking this query at compile time takes gigabytes of memory
+    let article_id = sqlx::query!(
         r#"
-
-        INSERT INTO articles (slug, title, description, body, author_id)
-        VALUES ($1, $2, $3, $4, $5)
-        RETURNING id, slug as "slug!" 
+        INSERT INTO articles (slug, title, description, body, author_id, status, published_at,
+            publish_at)
+        VALUES ($1, $2, $3, $4, $5, $6, CASE WHEN $6 = 'published' THEN CURRENT_TIMESTAMP END, $7)
         "#,
         slug,
         title,
         description,
         body,
-        id
+        id,
+        status,
+        publish_at
     )
-    .fetch_one(&mut tx)
-    .await?;
-
-    let article_id = result.id;
-    if let Some(Tags { tag_list: tag }) = tag_list {
-        for tag in tag {
-            let tag_id = sqlx::query!(
-                r#"
-            INSERT INTO tags (name)
-            VALUES ($1)
-            ON CONFLICT (name) DO UPDATE SET name = $1
-            RETURNING id
-            "#,
-                tag,
-            )
-            .fetch_one(&mut tx)
-            .await?
-            .id;
+    .execute(&mut tx)
+    .await?
+    .last_insert_rowid();
 
-            sqlx::query!(
-                r#"
-            INSERT INTO articletags (article_id, tag_id)
-            VALUES ($1, $2)
-            "#,
-                article_id,
-                tag_id
-            )
-            .execute(&mut tx)
-            .await?;
-        }
+    if let Some(Tags { tag_list }) = tag_list {
+        add_article_tags(&mut tx, article_id, &tag_list).await?;
     }
+    record_article_revision(&mut tx, article_id, id).await?;
     tx.commit().await?;
 
-    let result = get_article_by_slug_in_db(pool, &result.slug, Some(id))
+    let result = get_article_by_slug_in_db(pool, &slug, Some(id))
         .await?
         .unwrap();
 
     Ok(result)
 }
 
+/// Whether the user may edit the article is up to the caller, see `policy::ensure_can_modify`;
+/// `author_id` is the author that check was made against, and nothing is changed if the
+/// article at `slug` turns out to be someone else's by now. The new content is kept as a
+/// revision made by `id`. A new title gives the article a new slug, and the old one is
+/// remembered so links to it can be redirected.
+///
+/// `tag_list` replaces the article's tags, after which `add_tags` and `remove_tags` are applied.
+/// Tags left without any article are deleted.
 pub async fn update_article_in_db(
     pool: &SqlitePool,
     id: i64,
     slug: &str,
+    author_id: i64,
     UpdateArticleRequest {
         title,
         description,
         body,
+        status,
+        tag_list,
+        add_tags,
+        remove_tags,
     }: UpdateArticleRequest,
 ) -> Result<Article, RequestError> {
     let mut tx = pool.begin().await?;
-    let new_slug = title.as_ref().map(|title| slugify(title));
+    lock_articles(&mut tx).await?;
+    let article_id = match sqlx::query!(
+        r#"SELECT id as "id!" FROM articles WHERE slug = $1 AND author_id = $2"#,
+        slug,
+        author_id
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    {
+        Some(article) => article.id,
+        None => return Err(RequestError::NotFound("Article not found")),
+    };
+    let new_slug = match &title {
+        Some(title) => Some(find_free_slug(&mut tx, &slugify(title), Some(article_id)).await?),
+        None => None,
+    };
     let (query_1, params_1) = QueryBuilder::new(String::from("SET "), Some(", "), None)
         .add_param("title", title)
         .add_param("description", description)
         .add_param("body", body)
         .add_param("slug", new_slug.clone())
         .build();
-    let query = format!("UPDATE articles {query_1}, updated_at = CURRENT_TIMESTAMP WHERE articles.slug = {slug} AND articles.author_id = {id}");
-    let mut result = sqlx::query(&query);
+    let tags_changed = tag_list.is_some() || add_tags.is_some() || remove_tags.is_some();
+    //? Nothing but the status to change
+    if !query_1.is_empty() {
+        let query = format!(
+            "UPDATE articles {query_1}, updated_at = CURRENT_TIMESTAMP WHERE articles.id = ${}",
+            params_1.len() + 1
+        );
+        let mut result = sqlx::query(&query);
+
+        for param in params_1 {
+            result = result.bind(param);
+        }
 
-    for param in params_1 {
-        result = result.bind(param);
+        result.bind(article_id).execute(&mut tx).await?;
+    } else if tags_changed {
+        sqlx::query!(
+            r#"
+            UPDATE articles SET updated_at = CURRENT_TIMESTAMP WHERE id = $1
+            "#,
+            article_id
+        )
+        .execute(&mut tx)
+        .await?;
     }
 
-    let result = result.execute(&mut tx).await?;
-    if result.rows_affected() == 0 {
-        return Err(RequestError::Forbidden);
+    if let Some(tag_list) = tag_list {
+        clear_article_tags(&mut tx, article_id).await?;
+        add_article_tags(&mut tx, article_id, &tag_list).await?;
+    }
+    if let Some(add_tags) = add_tags {
+        add_article_tags(&mut tx, article_id, &add_tags).await?;
+    }
+    if let Some(remove_tags) = remove_tags {
+        remove_article_tags(&mut tx, article_id, &remove_tags).await?;
+    }
+    if tags_changed {
+        delete_unused_tags(&mut tx).await?;
     }
 
-    let slug = new_slug.unwrap_or(slug.to_owned());
+    let new_slug = new_slug.unwrap_or(slug.to_owned());
+    if let Some(status) = status {
+        set_article_status(&mut tx, &new_slug, author_id, status).await?;
+    }
 
-    let result = get_article_by_slug_in_db(pool, &slug, Some(id))
-        .await?
-        .unwrap();
+    if new_slug != slug {
+        sqlx::query!(
+            r#"
+            INSERT INTO article_slug_history (slug, article_id) VALUES ($1, $2)
+            ON CONFLICT (slug) DO UPDATE SET article_id = $2, created_at = CURRENT_TIMESTAMP
+            "#,
+            slug,
+            article_id
+        )
+        .execute(&mut tx)
+        .await?;
+        //? Renamed back to a slug it had before
+        sqlx::query!(
+            r#"
+            DELETE FROM article_slug_history WHERE slug = $1
+            "#,
+            new_slug
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    record_article_revision(&mut tx, article_id, id).await?;
+    tx.commit().await?;
+
+    match get_article_by_slug_in_db(pool, &new_slug, Some(id)).await? {
+        Some(article) => Ok(article),
+        None => Err(RequestError::NotFound("Article not found")),
+    }
+}
 
+/// Moves the article to `status`. `published_at` is when it last went live, so it is set when
+/// the article is published and cleared when it is taken down again. Any schedule the article
+/// had is dropped, since the author has decided for themselves. Like `update_article_in_db`,
+/// only changes the article if it is still by `author_id`.
+pub async fn set_article_status_in_db(
+    pool: &SqlitePool,
+    slug: &str,
+    author_id: i64,
+    status: ArticleStatus,
+) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    set_article_status(&mut tx, slug, author_id, status).await?;
     tx.commit().await?;
-    Ok(result)
+    Ok(())
 }
 
+async fn set_article_status(
+    tx: &mut Transaction<'_, Sqlite>,
+    slug: &str,
+    author_id: i64,
+    status: ArticleStatus,
+) -> Result<(), RequestError> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE articles SET status = $1,
+            published_at = CASE
+                WHEN $1 != 'published' THEN NULL
+                WHEN status = 'published' THEN published_at
+                ELSE CURRENT_TIMESTAMP
+            END,
+            publish_at = NULL
+        WHERE slug = $2 AND author_id = $3
+        "#,
+        status,
+        slug,
+        author_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(RequestError::NotFound("Article not found"));
+    }
+    Ok(())
+}
+
+/// Publishes every scheduled article that has come due, dated to when it was scheduled for, and
+/// returns how many there were.
+pub async fn publish_due_articles_in_db(pool: &SqlitePool) -> Result<u64, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        UPDATE articles SET status = 'published', published_at = publish_at, publish_at = NULL
+        WHERE publish_at IS NOT NULL AND publish_at <= CURRENT_TIMESTAMP
+        "#
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result.rows_affected())
+}
+
+/// When the next scheduled article is due, if any are.
+pub async fn get_next_scheduled_publish_in_db(
+    pool: &SqlitePool,
+) -> Result<Option<NaiveDateTime>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        SELECT MIN(publish_at) as "publish_at: NaiveDateTime" FROM articles
+        WHERE publish_at IS NOT NULL
+        "#
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result.publish_at)
+}
+
+/// Deletes the article at `slug` if it is still by `author_id`, the author the caller checked
+/// the user may delete articles of.
 pub async fn delete_article_in_db(
     pool: &SqlitePool,
-    id: i64,
     slug: &str,
+    author_id: i64,
 ) -> Result<(), RequestError> {
     let mut tx = pool.begin().await?;
 
@@ -307,14 +834,15 @@ pub async fn delete_article_in_db(
         WHERE articles.slug = $1 AND articles.author_id = $2
         "#,
         slug,
-        id
+        author_id
     )
     .execute(&mut tx)
     .await?;
 
     if result.rows_affected() == 0 {
-        return Err(RequestError::Forbidden);
+        return Err(RequestError::NotFound("Article not found"));
     }
+    delete_unused_tags(&mut tx).await?;
 
     tx.commit().await?;
     Ok(())
@@ -1,6 +1,11 @@
 use sqlx::SqlitePool;
 
-use crate::{data_formats::request::CommentRequest, errors::RequestError, models::Comment};
+use crate::{
+    data_formats::request::CommentRequest,
+    errors::RequestError,
+    models::Comment,
+    pagination::{ListPosition, Page},
+};
 
 use super::get_article_id_by_slug_in_db;
 
@@ -35,7 +40,6 @@ pub async fn add_comments_to_article_in_db(
          body as "body!",
          created_at as "created_at!",
          updated_at as "updated_at!",
-         article_id as "article_id!",
          author_id as "author_id!"
         "#,
         body,
@@ -49,24 +53,30 @@ pub async fn add_comments_to_article_in_db(
     Ok(result)
 }
 
+/// Deletes the comment if it is still by `author_id`, the author the caller checked the user
+/// may delete comments of.
 pub async fn delete_comment_in_db(
     pool: &SqlitePool,
-    user_id: i64,
     comment_id: i64,
     slug: &str,
+    author_id: i64,
 ) -> Result<(), RequestError> {
     let mut tx = pool.begin().await?;
     let article_id = get_article_id_by_slug_in_db(pool, slug).await?;
-    sqlx::query!(
+    let result = sqlx::query!(
         r#"
-        DELETE FROM comments WHERE author_id = $1 AND article_id = $2 AND id = $3
+        DELETE FROM comments WHERE article_id = $1 AND id = $2 AND author_id = $3
         "#,
-        user_id,
         article_id,
         comment_id,
+        author_id
     )
     .execute(&mut tx)
     .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(RequestError::NotFound("Comment not found"));
+    }
     tx.commit().await?;
     Ok(())
 }
@@ -84,7 +94,6 @@ pub async fn get_comment_for_article_in_db(
         body, 
         created_at as "created_at!", 
         updated_at as "updated_at!", 
-        article_id, 
         author_id
          from comments 
          WHERE article_id = $1 AND id = $2
@@ -102,29 +111,89 @@ pub async fn get_comment_for_article_in_db(
     Ok(result)
 }
 
+/// The article's comments, oldest first. Without a `limit` that is all of them, otherwise a page
+/// that starts right after `after` when given, and at `offset` otherwise.
 pub async fn get_comments_for_article_in_db(
     pool: &SqlitePool,
     slug: &str,
-) -> Result<Vec<Comment>, RequestError> {
+    limit: Option<u32>,
+    offset: u32,
+    after: Option<ListPosition>,
+) -> Result<Page<Comment, ListPosition>, RequestError> {
+    let offset = if after.is_some() { 0 } else { offset };
+    //? One more than asked for, to tell whether there is a next page. SQLite takes a negative
+    //? limit as none
+    let fetch_limit = limit.map_or(-1, |limit| i64::from(limit) + 1);
+    let (after_at, after_id) = (
+        after.map(|position| position.at),
+        after.map(|position| position.id),
+    );
     let mut tx = pool.begin().await?;
     let article_id = get_article_id_by_slug_in_db(pool, slug).await?;
     let result = sqlx::query_as!(
         Comment,
         r#"
         SELECT id as "id!",
-         body,
+         body as "body!",
          created_at as "created_at!",
          updated_at as "updated_at!",
-         article_id,
-         author_id
+         author_id as "author_id!"
             from comments 
               WHERE article_id = $1
-        
+                AND ($2 IS NULL OR (created_at, id) > ($2, $3))
+              ORDER BY created_at, id
+              LIMIT $4 OFFSET $5
         "#,
-        article_id
+        article_id,
+        after_at,
+        after_id,
+        fetch_limit,
+        offset
     )
     .fetch_all(&mut tx)
     .await?;
     tx.commit().await?;
-    Ok(result)
+    let page = Page::from_overfetched(result, limit.unwrap_or(u32::MAX), |comment| ListPosition {
+        at: comment.created_at,
+        id: comment.id,
+    });
+    Ok(page)
+}
+
+/// Every comment the user has written, along with the slug of the article it is on.
+pub async fn get_comments_by_author_in_db(
+    pool: &SqlitePool,
+    author_id: i64,
+) -> Result<Vec<(String, Comment)>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        SELECT comments.id as "id!",
+            comments.body,
+            comments.created_at as "created_at!",
+            comments.updated_at as "updated_at!",
+            comments.author_id,
+            articles.slug
+        FROM comments JOIN articles ON articles.id = comments.article_id
+        WHERE comments.author_id = $1
+        ORDER BY comments.created_at
+        "#,
+        author_id
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result
+        .into_iter()
+        .map(|record| {
+            let comment = Comment {
+                id: record.id,
+                body: record.body,
+                created_at: record.created_at,
+                updated_at: record.updated_at,
+                author_id: record.author_id,
+            };
+            (record.slug, comment)
+        })
+        .collect())
 }
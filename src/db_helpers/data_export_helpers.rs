@@ -0,0 +1,169 @@
+use sqlx::SqlitePool;
+
+use crate::{
+    errors::RequestError,
+    models::{DataExport, DataExportStatus},
+};
+
+/// Records a new export for the user and returns its id. Only one export can be in progress at
+/// a time.
+pub async fn create_data_export_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+    token_hash: &str,
+    expiry: i64,
+) -> Result<i64, RequestError> {
+    let mut tx = pool.begin().await?;
+    let pending = sqlx::query!(
+        r#"
+        SELECT id FROM data_exports WHERE user_id = $1 AND status = 'pending'
+        "#,
+        user_id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    if pending.is_some() {
+        return Err(RequestError::RunTimeError(
+            "An export is already being prepared",
+        ));
+    }
+
+    let export_id = sqlx::query!(
+        r#"
+        INSERT INTO data_exports (user_id, token_hash, expires_at)
+        VALUES ($1, $2, datetime('now', '+' || $3 || ' seconds'))
+        "#,
+        user_id,
+        token_hash,
+        expiry
+    )
+    .execute(&mut tx)
+    .await?
+    .last_insert_rowid();
+    tx.commit().await?;
+    Ok(export_id)
+}
+
+/// The `(export_id, user_id)` of every export that hasn't finished.
+pub async fn list_pending_data_exports_in_db(
+    pool: &SqlitePool,
+) -> Result<Vec<(i64, i64)>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        SELECT id as "id!", user_id FROM data_exports WHERE status = 'pending'
+        "#
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result
+        .into_iter()
+        .map(|export| (export.id, export.user_id))
+        .collect())
+}
+
+pub async fn finish_data_export_in_db(
+    pool: &SqlitePool,
+    export_id: i64,
+    status: DataExportStatus,
+) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE data_exports SET status = $1, completed_at = CURRENT_TIMESTAMP WHERE id = $2
+        "#,
+        status,
+        export_id
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn get_data_export_in_db(
+    pool: &SqlitePool,
+    export_id: i64,
+) -> Result<Option<DataExport>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query_as!(
+        DataExport,
+        r#"
+        SELECT id as "id!", status as "status: DataExportStatus", created_at as "created_at!",
+            completed_at, expires_at
+        FROM data_exports WHERE id = $1
+        "#,
+        export_id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result)
+}
+
+/// The most recent export the user asked for, if it hasn't expired yet.
+pub async fn get_latest_data_export_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+) -> Result<Option<DataExport>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query_as!(
+        DataExport,
+        r#"
+        SELECT id as "id!", status as "status: DataExportStatus", created_at as "created_at!",
+            completed_at, expires_at
+        FROM data_exports WHERE user_id = $1 AND expires_at > CURRENT_TIMESTAMP
+        ORDER BY id DESC LIMIT 1
+        "#,
+        user_id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result)
+}
+
+pub async fn get_data_export_by_token_in_db(
+    pool: &SqlitePool,
+    token_hash: &str,
+) -> Result<Option<DataExport>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query_as!(
+        DataExport,
+        r#"
+        SELECT id as "id!", status as "status: DataExportStatus", created_at as "created_at!",
+            completed_at, expires_at
+        FROM data_exports WHERE token_hash = $1 AND expires_at > CURRENT_TIMESTAMP
+        "#,
+        token_hash
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result)
+}
+
+/// Forgets expired exports and returns the ids of the ones that are still downloadable.
+pub async fn delete_expired_data_exports_in_db(
+    pool: &SqlitePool,
+) -> Result<Vec<i64>, RequestError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM data_exports WHERE expires_at <= CURRENT_TIMESTAMP
+        "#
+    )
+    .execute(&mut tx)
+    .await?;
+
+    let result = sqlx::query!(
+        r#"
+        SELECT id as "id!" FROM data_exports
+        "#
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result.into_iter().map(|record| record.id).collect())
+}
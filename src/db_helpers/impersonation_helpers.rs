@@ -0,0 +1,32 @@
+use sqlx::SqlitePool;
+
+use crate::errors::RequestError;
+
+/// Writes one request made with an impersonation token to the audit log.
+pub async fn record_impersonated_request_in_db(
+    pool: &SqlitePool,
+    session_id: i64,
+    admin_id: i64,
+    user_id: i64,
+    method: &str,
+    path: &str,
+    blocked: bool,
+) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO impersonation_audit_log (session_id, admin_id, user_id, method, path, blocked)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        session_id,
+        admin_id,
+        user_id,
+        method,
+        path,
+        blocked
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use sqlx::{Sqlite, SqlitePool, Transaction};
+
+use crate::{errors::RequestError, models::Invitation};
+
+/// Takes one use of the invitation, as part of creating the account it is used for.
+pub(super) async fn use_invitation(
+    tx: &mut Transaction<'_, Sqlite>,
+    code_hash: &str,
+) -> Result<(), RequestError> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE invitations SET uses = uses + 1
+        WHERE code_hash = $1 AND uses < max_uses AND revoked_at IS NULL
+            AND expires_at > CURRENT_TIMESTAMP
+        "#,
+        code_hash
+    )
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(RequestError::Forbidden(
+            "Invalid or expired invitation code",
+        ));
+    }
+    Ok(())
+}
+
+/// Stores a new invitation. With a `quota`, fails if it would leave the user with more unused
+/// seats than that across their active invitations.
+pub async fn create_invitation_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+    code_hash: &str,
+    max_uses: i64,
+    expiry: i64,
+    quota: Option<i64>,
+) -> Result<Invitation, RequestError> {
+    let mut tx = pool.begin().await?;
+    if let Some(quota) = quota {
+        let outstanding = sqlx::query!(
+            r#"
+            SELECT COALESCE(SUM(max_uses - uses), 0) as "outstanding!: i64" FROM invitations
+            WHERE created_by = $1 AND revoked_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+            "#,
+            user_id
+        )
+        .fetch_one(&mut tx)
+        .await?
+        .outstanding;
+        if outstanding + max_uses > quota {
+            return Err(RequestError::Forbidden("You have used up your invitations"));
+        }
+    }
+
+    let invitation_id = sqlx::query!(
+        r#"
+        INSERT INTO invitations (created_by, code_hash, max_uses, expires_at)
+        VALUES ($1, $2, $3, datetime('now', '+' || $4 || ' seconds'))
+        "#,
+        user_id,
+        code_hash,
+        max_uses,
+        expiry
+    )
+    .execute(&mut tx)
+    .await?
+    .last_insert_rowid();
+
+    let result = sqlx::query_as!(
+        Invitation,
+        r#"
+        SELECT id as "id!", max_uses, uses, created_at as "created_at!", expires_at, revoked_at
+        FROM invitations WHERE id = $1
+        "#,
+        invitation_id
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result)
+}
+
+pub async fn list_invitations_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+) -> Result<Vec<Invitation>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query_as!(
+        Invitation,
+        r#"
+        SELECT id as "id!", max_uses, uses, created_at as "created_at!", expires_at, revoked_at
+        FROM invitations WHERE created_by = $1
+        ORDER BY id DESC
+        "#,
+        user_id
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result)
+}
+
+pub async fn revoke_invitation_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+    invitation_id: i64,
+) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        UPDATE invitations SET revoked_at = CURRENT_TIMESTAMP
+        WHERE id = $1 AND created_by = $2 AND revoked_at IS NULL
+        "#,
+        invitation_id,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(RequestError::NotFound("Invitation not found"));
+    }
+    tx.commit().await?;
+    Ok(())
+}
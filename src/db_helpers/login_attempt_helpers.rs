@@ -0,0 +1,92 @@
+use sqlx::SqlitePool;
+
+use crate::errors::RequestError;
+
+/// Returns true when any of the keys is currently locked out.
+pub async fn is_login_locked_in_db(
+    pool: &SqlitePool,
+    keys: &[String],
+) -> Result<bool, RequestError> {
+    let mut tx = pool.begin().await?;
+    let mut locked = false;
+    for key in keys {
+        let result = sqlx::query!(
+            r#"
+            SELECT key FROM login_attempts WHERE key = $1 AND locked_until > CURRENT_TIMESTAMP
+            "#,
+            key
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+        if result.is_some() {
+            locked = true;
+            break;
+        }
+    }
+    tx.commit().await?;
+    Ok(locked)
+}
+
+/// Counts a failed attempt against `key` and returns the new number of consecutive failures.
+/// Failures older than `window` seconds are forgotten first.
+pub async fn record_failed_login_in_db(
+    pool: &SqlitePool,
+    key: &str,
+    window: i64,
+) -> Result<i64, RequestError> {
+    let mut tx = pool.begin().await?;
+    let failures = sqlx::query!(
+        r#"
+        INSERT INTO login_attempts (key, failures)
+        VALUES ($1, 1)
+        ON CONFLICT (key) DO UPDATE SET
+            failures = CASE
+                WHEN last_failure_at < datetime('now', '-' || $2 || ' seconds') THEN 1
+                ELSE failures + 1
+            END,
+            last_failure_at = CURRENT_TIMESTAMP
+        RETURNING failures
+        "#,
+        key,
+        window
+    )
+    .fetch_one(&mut tx)
+    .await?
+    .failures;
+    tx.commit().await?;
+    Ok(failures)
+}
+
+pub async fn lock_login_in_db(
+    pool: &SqlitePool,
+    key: &str,
+    duration: i64,
+) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE login_attempts SET locked_until = datetime('now', '+' || $2 || ' seconds')
+        WHERE key = $1
+        "#,
+        key,
+        duration
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn clear_failed_logins_in_db(pool: &SqlitePool, key: &str) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM login_attempts WHERE key = $1
+        "#,
+        key
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
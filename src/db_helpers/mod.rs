@@ -1,17 +1,43 @@
 use sqlx::{Sqlite, SqlitePool};
 
-use crate::{errors::RequestError, models::User, ultra_fast_string_converter};
+use crate::{
+    errors::RequestError,
+    models::{Role, User},
+    ultra_fast_string_converter,
+};
 
+/// How long a session or API key goes without its last-used time being written again, so that
+/// a busy client doesn't turn every request into a write.
+const LAST_USED_RESOLUTION_SECONDS: i64 = 5 * 60;
+
+mod api_key_helpers;
 mod article_helpers;
 mod comment_helpers;
+mod data_export_helpers;
+mod impersonation_helpers;
+mod invitation_helpers;
+mod login_attempt_helpers;
+mod oidc_helpers;
 mod profile_helpers;
+mod revision_helpers;
+mod session_helpers;
 mod tag_helpers;
+mod two_factor_helpers;
 mod user_helpers;
 
+pub use api_key_helpers::*;
 pub use article_helpers::*;
 pub use comment_helpers::*;
+pub use data_export_helpers::*;
+pub use impersonation_helpers::*;
+pub use invitation_helpers::*;
+pub use login_attempt_helpers::*;
+pub use oidc_helpers::*;
 pub use profile_helpers::*;
+pub use revision_helpers::*;
+pub use session_helpers::*;
 pub use tag_helpers::*;
+pub use two_factor_helpers::*;
 pub use user_helpers::*;
 
 struct QueryBuilder {
@@ -67,7 +93,7 @@ impl QueryBuilder {
 
 // ----------------- Helper Functions -----------------
 
-async fn get_user_by_username(
+pub async fn get_user_by_username(
     pool: &SqlitePool,
     username: &str,
 ) -> Result<Option<User>, sqlx::Error> {
@@ -75,7 +101,7 @@ async fn get_user_by_username(
     let result = sqlx::query_as!(
         User,
         r#"
-        SELECT id as "id!", created_at as 'created_at!', username, email, image, bio, password  FROM users WHERE username = $1
+        SELECT id as "id!", created_at as 'created_at!', username, email, image, bio, password, email_verified_at, role as "role: Role" FROM users WHERE username = $1
         "#,
         username
     )
@@ -93,7 +119,7 @@ pub async fn get_user_by_email(
     let result = sqlx::query_as!(
         User,
         r#"
-        SELECT id as "id!", created_at as 'created_at!', username, email, image, bio, password  FROM users WHERE email = $1
+        SELECT id as "id!", created_at as 'created_at!', username, email, image, bio, password, email_verified_at, role as "role: Role" FROM users WHERE email = $1
         "#,
         email
     )
@@ -108,7 +134,7 @@ pub async fn get_user_by_email(
 pub async fn get_users_by_id(pool: &SqlitePool, id: &[i64]) -> Result<Vec<User>, RequestError> {
     let mut tx = pool.begin().await?;
     let ids = ultra_fast_string_converter(id);
-    let query = format!("SELECT id as 'id!', created_at as 'created_at!', username, email, image, bio, password  FROM users WHERE id IN {}", ids);
+    let query = format!("SELECT id as 'id!', created_at as 'created_at!', username, email, image, bio, password, email_verified_at, role FROM users WHERE id IN {}", ids);
     let result = sqlx::query_as::<Sqlite, User>(&query)
         .fetch_all(&mut tx)
         .await?;
@@ -121,7 +147,7 @@ pub async fn get_user_by_id(pool: &SqlitePool, id: i64) -> Result<Option<User>,
     let result = sqlx::query_as!(
         User,
         r#"
-        SELECT id as "id!", created_at as 'created_at!', username, email, image, bio, password  FROM users WHERE id = $1
+        SELECT id as "id!", created_at as 'created_at!', username, email, image, bio, password, email_verified_at, role as "role: Role" FROM users WHERE id = $1
         "#,
         id
     )
@@ -0,0 +1,193 @@
+use sqlx::SqlitePool;
+
+use crate::{
+    errors::RequestError,
+    models::{OidcLoginState, Role, User},
+    oidc::{username_from_claims, IdTokenClaims},
+};
+
+use super::get_user_by_id;
+use super::invitation_helpers::use_invitation;
+
+/// Remembers an authorization request until the provider redirects back to us, along with the
+/// invitation the user may sign up with.
+pub async fn create_oidc_login_state_in_db(
+    pool: &SqlitePool,
+    state_hash: &str,
+    provider: &str,
+    code_verifier: &str,
+    nonce: &str,
+    invitation_code_hash: Option<&str>,
+    expiry: i64,
+) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    //? Abandoned logins would otherwise pile up forever
+    sqlx::query!(
+        r#"
+        DELETE FROM oidc_login_states WHERE expires_at <= CURRENT_TIMESTAMP
+        "#
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO oidc_login_states (state_hash, provider, code_verifier, nonce,
+            invitation_code_hash, expires_at)
+        VALUES ($1, $2, $3, $4, $5, datetime('now', '+' || $6 || ' seconds'))
+        "#,
+        state_hash,
+        provider,
+        code_verifier,
+        nonce,
+        invitation_code_hash,
+        expiry
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Consumes the state of an authorization request so the same callback can't be replayed.
+pub async fn consume_oidc_login_state_in_db(
+    pool: &SqlitePool,
+    state_hash: &str,
+    provider: &str,
+) -> Result<OidcLoginState, RequestError> {
+    let mut tx = pool.begin().await?;
+    let state = sqlx::query_as!(
+        OidcLoginState,
+        r#"
+        DELETE FROM oidc_login_states
+        WHERE state_hash = $1 AND provider = $2 AND expires_at > CURRENT_TIMESTAMP
+        RETURNING code_verifier as "code_verifier!", nonce as "nonce!", invitation_code_hash
+        "#,
+        state_hash,
+        provider
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    tx.commit().await?;
+    state.ok_or(RequestError::RunTimeError("Invalid or expired login state"))
+}
+
+pub async fn get_user_by_identity_in_db(
+    pool: &SqlitePool,
+    issuer: &str,
+    subject: &str,
+) -> Result<Option<User>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query_as!(
+        User,
+        r#"
+        SELECT users.id as "id!", users.created_at as 'created_at!', username, email, image, bio,
+            password, email_verified_at, role as "role: Role"
+        FROM users INNER JOIN user_identities ON user_identities.user_id = users.id
+        WHERE user_identities.issuer = $1 AND user_identities.subject = $2
+        "#,
+        issuer,
+        subject
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result)
+}
+
+/// Links an external identity to an existing user. The provider vouched for the email, so it
+/// counts as verified from now on.
+pub async fn link_identity_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+    issuer: &str,
+    subject: &str,
+) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO user_identities (user_id, issuer, subject) VALUES ($1, $2, $3)
+        "#,
+        user_id,
+        issuer,
+        subject
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE users SET email_verified_at = COALESCE(email_verified_at, CURRENT_TIMESTAMP)
+        WHERE id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Creates a user for the identity in `claims` on their first login, named after the claims
+/// with a number appended until the username is unique. With an `invitation_code_hash`, one use
+/// of that invitation is taken in the same transaction, like `insert_user`.
+pub async fn create_user_from_identity_in_db(
+    pool: &SqlitePool,
+    claims: &IdTokenClaims,
+    email: &str,
+    password_hash: &str,
+    invitation_code_hash: Option<&str>,
+) -> Result<User, RequestError> {
+    let username = username_from_claims(claims);
+    let mut tx = pool.begin().await?;
+    if let Some(code_hash) = invitation_code_hash {
+        use_invitation(&mut tx, code_hash).await?;
+    }
+    let mut candidate = username.clone();
+    let mut suffix = 1;
+    while sqlx::query!(
+        r#"
+        SELECT id FROM users WHERE username = $1
+        "#,
+        candidate
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .is_some()
+    {
+        suffix += 1;
+        candidate = format!("{}{}", username, suffix);
+    }
+
+    //? Checked at compile time, so without the RETURNING insert_user has to leave unchecked
+    let id = sqlx::query!(
+        r#"
+        INSERT INTO users (email, username, password, email_verified_at)
+        VALUES ($1, $2, $3, CASE WHEN $4 THEN CURRENT_TIMESTAMP END)
+        "#,
+        email,
+        candidate,
+        password_hash,
+        claims.email_verified
+    )
+    .execute(&mut tx)
+    .await?
+    .last_insert_rowid();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO user_identities (user_id, issuer, subject) VALUES ($1, $2, $3)
+        "#,
+        id,
+        claims.iss,
+        claims.sub
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    match get_user_by_id(pool, id).await? {
+        Some(user) => Ok(user),
+        None => Err(RequestError::NotFound("User not found")),
+    }
+}
@@ -113,3 +113,43 @@ pub async fn unfollow_user_in_db(
 
     Ok(profile_result)
 }
+
+/// The usernames of everyone the user follows.
+pub async fn get_followed_usernames_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+) -> Result<Vec<String>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        SELECT users.username FROM follows JOIN users ON users.id = follows.followed_id
+        WHERE follows.follower_id = $1
+        ORDER BY users.username
+        "#,
+        user_id
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result.into_iter().map(|record| record.username).collect())
+}
+
+/// The usernames of everyone following the user.
+pub async fn get_follower_usernames_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+) -> Result<Vec<String>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        SELECT users.username FROM follows JOIN users ON users.id = follows.follower_id
+        WHERE follows.followed_id = $1
+        ORDER BY users.username
+        "#,
+        user_id
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result.into_iter().map(|record| record.username).collect())
+}
@@ -0,0 +1,181 @@
+use sqlx::{Sqlite, SqlitePool, Transaction};
+
+use crate::{errors::RequestError, models::ArticleRevision};
+
+/// Snapshots the article as it is now into a new revision made by `editor_id`, noting which
+/// fields changed since the last one. Nothing is stored if the content is unchanged, e.g. when
+/// only the status was updated. Runs in the transaction that changed the article, so the
+/// revision can't go missing or be taken from someone else's edit.
+pub(super) async fn record_article_revision(
+    tx: &mut Transaction<'_, Sqlite>,
+    article_id: i64,
+    editor_id: i64,
+) -> Result<(), RequestError> {
+    let article = sqlx::query!(
+        r#"
+        SELECT title, description, body FROM articles WHERE id = $1
+        "#,
+        article_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    let article = match article {
+        Some(article) => article,
+        None => return Err(RequestError::NotFound("Article not found")),
+    };
+
+    let previous = sqlx::query!(
+        r#"
+        SELECT title, description, body FROM article_revisions
+        WHERE article_id = $1
+        ORDER BY number DESC
+        LIMIT 1
+        "#,
+        article_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let changes = match previous {
+        Some(previous) => {
+            let mut changes = Vec::new();
+            if previous.title != article.title {
+                changes.push("title");
+            }
+            if previous.description != article.description {
+                changes.push("description");
+            }
+            if previous.body != article.body {
+                changes.push("body");
+            }
+            changes
+        }
+        None => vec!["title", "description", "body"],
+    };
+    if changes.is_empty() {
+        return Ok(());
+    }
+    let changes = changes.join(",");
+
+    //? The number is worked out by the INSERT itself rather than from the row read above
+    sqlx::query!(
+        r#"
+        INSERT INTO article_revisions (article_id, number, editor_id, title, description, body,
+            changes)
+        SELECT $1, COALESCE(MAX(number), 0) + 1, $2, $3, $4, $5, $6
+        FROM article_revisions WHERE article_id = $1
+        "#,
+        article_id,
+        editor_id,
+        article.title,
+        article.description,
+        article.body,
+        changes
+    )
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+/// Every revision of the article, newest first.
+pub async fn list_article_revisions_in_db(
+    pool: &SqlitePool,
+    article_id: i64,
+) -> Result<Vec<ArticleRevision>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query_as!(
+        ArticleRevision,
+        r#"
+        SELECT number as "number!", users.username as "editor_username?", title, description,
+            body, changes, article_revisions.created_at as "created_at!"
+        FROM article_revisions
+            LEFT JOIN users ON users.id = article_revisions.editor_id
+        WHERE article_id = $1
+        ORDER BY number DESC
+        "#,
+        article_id
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result)
+}
+
+pub async fn get_article_revision_in_db(
+    pool: &SqlitePool,
+    article_id: i64,
+    number: i64,
+) -> Result<ArticleRevision, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query_as!(
+        ArticleRevision,
+        r#"
+        SELECT number as "number!", users.username as "editor_username?", title, description,
+            body, changes, article_revisions.created_at as "created_at!"
+        FROM article_revisions
+            LEFT JOIN users ON users.id = article_revisions.editor_id
+        WHERE article_id = $1 AND number = $2
+        "#,
+        article_id,
+        number
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    tx.commit().await?;
+    match result {
+        Some(revision) => Ok(revision),
+        None => Err(RequestError::NotFound("Revision not found")),
+    }
+}
+
+/// The number and cached HTML of the newest revision of the article whose body is `body`. HTML
+/// made by any other `renderer_version` doesn't count.
+pub async fn get_cached_body_html_in_db(
+    pool: &SqlitePool,
+    article_id: i64,
+    body: &str,
+    renderer_version: i64,
+) -> Result<Option<(i64, Option<String>)>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        SELECT number,
+            CASE WHEN body_html_version = $3 THEN body_html END as "body_html: String"
+        FROM article_revisions
+        WHERE article_id = $1 AND body = $2
+        ORDER BY number DESC
+        LIMIT 1
+        "#,
+        article_id,
+        body,
+        renderer_version
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result.map(|revision| (revision.number, revision.body_html)))
+}
+
+pub async fn store_body_html_in_db(
+    pool: &SqlitePool,
+    article_id: i64,
+    number: i64,
+    body_html: &str,
+    renderer_version: i64,
+) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE article_revisions SET body_html = $1, body_html_version = $2
+        WHERE article_id = $3 AND number = $4
+        "#,
+        body_html,
+        renderer_version,
+        article_id,
+        number
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,310 @@
+use sqlx::SqlitePool;
+
+use crate::{errors::RequestError, models::Session};
+
+use super::LAST_USED_RESOLUTION_SECONDS;
+
+pub async fn create_session_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+    user_agent: Option<&str>,
+    ip_address: Option<&str>,
+    refresh_token_hash: &str,
+    refresh_token_expiry: i64,
+) -> Result<i64, RequestError> {
+    let mut tx = pool.begin().await?;
+    let session_id = sqlx::query!(
+        r#"
+        INSERT INTO sessions (user_id, user_agent, ip_address, last_seen_at)
+        VALUES ($1, $2, $3, CURRENT_TIMESTAMP)
+        RETURNING id as "id!"
+        "#,
+        user_id,
+        user_agent,
+        ip_address
+    )
+    .fetch_one(&mut tx)
+    .await?
+    .id;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO refresh_tokens (session_id, token_hash, expires_at)
+        VALUES ($1, $2, datetime('now', '+' || $3 || ' seconds'))
+        "#,
+        session_id,
+        refresh_token_hash,
+        refresh_token_expiry
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(session_id)
+}
+
+/// Starts a session for an admin acting as `user_id`. It has no refresh token, so it can't be
+/// extended and never shows up in the user's own session list.
+pub async fn create_impersonation_session_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+    admin_id: i64,
+    user_agent: Option<&str>,
+    ip_address: Option<&str>,
+) -> Result<i64, RequestError> {
+    let mut tx = pool.begin().await?;
+    let session_id = sqlx::query!(
+        r#"
+        INSERT INTO sessions (user_id, impersonator_id, user_agent, ip_address, last_seen_at)
+        VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP)
+        RETURNING id as "id!"
+        "#,
+        user_id,
+        admin_id,
+        user_agent,
+        ip_address
+    )
+    .fetch_one(&mut tx)
+    .await?
+    .id;
+    tx.commit().await?;
+    Ok(session_id)
+}
+
+/// Checks that the session hasn't been signed out and records that it was just used from
+/// `ip_address`. The session also has to have been started by `impersonator_id`, so a token
+/// can't switch between impersonating and not. `last_seen_at` is only written when it is older
+/// than `LAST_USED_RESOLUTION_SECONDS` or the address changed.
+pub async fn touch_session_in_db(
+    pool: &SqlitePool,
+    session_id: i64,
+    user_id: i64,
+    impersonator_id: Option<i64>,
+    ip_address: Option<&str>,
+) -> Result<bool, RequestError> {
+    let touched = sqlx::query!(
+        r#"
+        UPDATE sessions SET last_seen_at = CURRENT_TIMESTAMP, ip_address = COALESCE($1, ip_address)
+        WHERE id = $2 AND user_id = $3 AND impersonator_id IS $4 AND revoked_at IS NULL AND (
+            last_seen_at IS NULL
+            OR last_seen_at <= datetime('now', '-' || $5 || ' seconds')
+            OR ($1 IS NOT NULL AND ip_address IS NOT $1)
+        )
+        "#,
+        ip_address,
+        session_id,
+        user_id,
+        impersonator_id,
+        LAST_USED_RESOLUTION_SECONDS
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+    if touched > 0 {
+        return Ok(true);
+    }
+
+    //? Nothing written, either because it was seen recently or because it isn't valid
+    let session = sqlx::query!(
+        r#"
+        SELECT id FROM sessions
+        WHERE id = $1 AND user_id = $2 AND impersonator_id IS $3 AND revoked_at IS NULL
+        "#,
+        session_id,
+        user_id,
+        impersonator_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(session.is_some())
+}
+
+/// The user's sessions that haven't been signed out and can still be refreshed, most recently
+/// used first.
+pub async fn list_sessions_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+) -> Result<Vec<Session>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query_as!(
+        Session,
+        r#"
+        SELECT id as "id!", user_agent, ip_address, created_at as "created_at!", last_seen_at,
+            revoked_at
+        FROM sessions
+        WHERE user_id = $1 AND revoked_at IS NULL AND EXISTS (
+            SELECT 1 FROM refresh_tokens
+            WHERE refresh_tokens.session_id = sessions.id
+                AND refresh_tokens.used_at IS NULL
+                AND refresh_tokens.expires_at > CURRENT_TIMESTAMP
+        )
+        ORDER BY COALESCE(last_seen_at, created_at) DESC
+        "#,
+        user_id
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result)
+}
+
+/// Every session the user has had, including the ones that were signed out.
+pub async fn list_all_sessions_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+) -> Result<Vec<Session>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query_as!(
+        Session,
+        r#"
+        SELECT id as "id!", user_agent, ip_address, created_at as "created_at!", last_seen_at,
+            revoked_at
+        FROM sessions WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result)
+}
+
+/// Signs one of the user's sessions out, wherever it is being used.
+pub async fn revoke_user_session_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+    session_id: i64,
+) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP
+        WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        session_id,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(RequestError::NotFound("Session not found"));
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn revoke_session_in_db(pool: &SqlitePool, session_id: i64) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP
+        WHERE id = $1 AND revoked_at IS NULL
+        "#,
+        session_id
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Swaps a refresh token for a new one in the same session and returns `(user_id, session_id)`.
+///
+/// Presenting a refresh token that was already rotated means it has leaked, so the whole
+/// session (every token descended from the original login) is revoked.
+pub async fn rotate_refresh_token_in_db(
+    pool: &SqlitePool,
+    refresh_token_hash: &str,
+    new_refresh_token_hash: &str,
+    refresh_token_expiry: i64,
+) -> Result<(i64, i64), RequestError> {
+    let mut tx = pool.begin().await?;
+    let token = sqlx::query!(
+        r#"
+        SELECT refresh_tokens.id as "id!",
+            refresh_tokens.session_id as "session_id!",
+            sessions.user_id as "user_id!",
+            refresh_tokens.used_at IS NOT NULL as "used!: bool",
+            refresh_tokens.expires_at < CURRENT_TIMESTAMP as "expired!: bool",
+            sessions.revoked_at IS NOT NULL as "revoked!: bool"
+        FROM refresh_tokens
+            JOIN sessions ON sessions.id = refresh_tokens.session_id
+        WHERE refresh_tokens.token_hash = $1
+        "#,
+        refresh_token_hash
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let token = match token {
+        Some(token) => token,
+        None => return Err(RequestError::NotAuthorized("Invalid refresh token")),
+    };
+
+    if token.revoked {
+        return Err(RequestError::NotAuthorized("Session has been revoked"));
+    }
+
+    if token.used {
+        sqlx::query!(
+            r#"
+            UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP WHERE id = $1
+            "#,
+            token.session_id
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        return Err(RequestError::NotAuthorized("Refresh token reuse detected"));
+    }
+
+    if token.expired {
+        return Err(RequestError::NotAuthorized("Refresh token expired"));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE refresh_tokens SET used_at = CURRENT_TIMESTAMP WHERE id = $1
+        "#,
+        token.id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO refresh_tokens (session_id, token_hash, expires_at)
+        VALUES ($1, $2, datetime('now', '+' || $3 || ' seconds'))
+        "#,
+        token.session_id,
+        new_refresh_token_hash,
+        refresh_token_expiry
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok((token.user_id, token.session_id))
+}
+
+/// Signs the user out everywhere, except for `keep_session_id` when given.
+pub async fn revoke_all_sessions_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+    keep_session_id: Option<i64>,
+) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP
+        WHERE user_id = $1 AND revoked_at IS NULL AND id IS NOT $2
+        "#,
+        user_id,
+        keep_session_id
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
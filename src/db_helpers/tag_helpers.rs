@@ -1,4 +1,4 @@
-use sqlx::SqlitePool;
+use sqlx::{Sqlite, SqlitePool, Transaction};
 
 use crate::errors::RequestError;
 
@@ -18,3 +18,92 @@ pub async fn get_tags_in_db(pool: &SqlitePool) -> Result<Vec<String>, RequestErr
     tx.commit().await?;
     Ok(result)
 }
+
+/// Tags the article with each of `tags`, creating the ones nobody has used yet. Tags the article
+/// already has are left as they are.
+pub(super) async fn add_article_tags(
+    tx: &mut Transaction<'_, Sqlite>,
+    article_id: i64,
+    tags: &[String],
+) -> Result<(), RequestError> {
+    for tag in tags {
+        let tag = tag.trim();
+        if tag.is_empty() {
+            continue;
+        }
+        let tag_id = sqlx::query!(
+            r#"
+            INSERT INTO tags (name)
+            VALUES ($1)
+            ON CONFLICT (name) DO UPDATE SET name = $1
+            RETURNING id
+            "#,
+            tag,
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .id;
+
+        sqlx::query!(
+            r#"
+            INSERT OR IGNORE INTO articletags (article_id, tag_id)
+            VALUES ($1, $2)
+            "#,
+            article_id,
+            tag_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    Ok(())
+}
+
+pub(super) async fn remove_article_tags(
+    tx: &mut Transaction<'_, Sqlite>,
+    article_id: i64,
+    tags: &[String],
+) -> Result<(), RequestError> {
+    for tag in tags {
+        let tag = tag.trim();
+        sqlx::query!(
+            r#"
+            DELETE FROM articletags
+            WHERE article_id = $1 AND tag_id = (SELECT id FROM tags WHERE name = $2)
+            "#,
+            article_id,
+            tag
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    Ok(())
+}
+
+pub(super) async fn clear_article_tags(
+    tx: &mut Transaction<'_, Sqlite>,
+    article_id: i64,
+) -> Result<(), RequestError> {
+    sqlx::query!(
+        r#"
+        DELETE FROM articletags WHERE article_id = $1
+        "#,
+        article_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+/// Drops tags no article uses any more, so `GET /tags` only lists ones that lead somewhere.
+pub(super) async fn delete_unused_tags(
+    tx: &mut Transaction<'_, Sqlite>,
+) -> Result<(), RequestError> {
+    sqlx::query!(
+        r#"
+        DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM articletags)
+        "#
+    )
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,175 @@
+use sqlx::SqlitePool;
+
+use crate::{
+    errors::RequestError,
+    models::{RecoveryCode, TwoFactor},
+};
+
+pub async fn get_two_factor_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+) -> Result<Option<TwoFactor>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query_as!(
+        TwoFactor,
+        r#"
+        SELECT secret, enabled_at FROM two_factor WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result)
+}
+
+/// Stores a secret that still has to be confirmed with a first code before it is enabled.
+pub async fn set_pending_two_factor_secret_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+    secret: &str,
+) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO two_factor (user_id, secret)
+        VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE SET secret = $2, created_at = CURRENT_TIMESTAMP
+        WHERE enabled_at IS NULL
+        "#,
+        user_id,
+        secret
+    )
+    .execute(&mut tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(RequestError::RunTimeError(
+            "Two-factor authentication is already enabled",
+        ));
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn enable_two_factor_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+    recovery_code_hashes: &[String],
+) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE two_factor SET enabled_at = CURRENT_TIMESTAMP WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM recovery_codes WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    for code_hash in recovery_code_hashes {
+        sqlx::query!(
+            r#"
+            INSERT INTO recovery_codes (user_id, code_hash)
+            VALUES ($1, $2)
+            "#,
+            user_id,
+            code_hash
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn disable_two_factor_in_db(pool: &SqlitePool, user_id: i64) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM two_factor WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM recovery_codes WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Records that a code from TOTP time step `step` was used. Returns false if a code from that
+/// step or a later one was already used, which makes the code a replay.
+pub async fn use_totp_step_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+    step: i64,
+) -> Result<bool, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        UPDATE two_factor SET last_used_step = $2
+        WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)
+        "#,
+        user_id,
+        step
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn get_unused_recovery_codes_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+) -> Result<Vec<RecoveryCode>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query_as!(
+        RecoveryCode,
+        r#"
+        SELECT id as "id!", code_hash FROM recovery_codes WHERE user_id = $1 AND used_at IS NULL
+        "#,
+        user_id
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result)
+}
+
+pub async fn use_recovery_code_in_db(pool: &SqlitePool, code_id: i64) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        UPDATE recovery_codes SET used_at = CURRENT_TIMESTAMP WHERE id = $1 AND used_at IS NULL
+        "#,
+        code_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    //? Two logins raced on the same code and the other one won
+    if result.rows_affected() == 0 {
+        return Err(RequestError::NotAuthorized("Invalid two-factor code"));
+    }
+    tx.commit().await?;
+    Ok(())
+}
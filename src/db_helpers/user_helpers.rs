@@ -1,27 +1,41 @@
-use sqlx::SqlitePool;
+use chrono::NaiveDateTime;
+use sqlx::{Sqlite, SqlitePool};
 
 use crate::{
     authentication::hash_password_argon2,
     data_formats::request::{RegisterRequest, UpdateUserRequest},
     errors::RequestError,
-    models::User,
+    login_throttle::account_key,
+    models::{ContentAction, Role, User, VerificationPurpose},
 };
 
+use super::invitation_helpers::use_invitation;
+use super::tag_helpers::delete_unused_tags;
 use super::{get_user_by_id, QueryBuilder};
 
-pub async fn insert_user(pool: &SqlitePool, user: &RegisterRequest) -> Result<User, RequestError> {
+/// Creates the user. With an `invitation_code_hash`, one use of that invitation is taken in the
+/// same transaction so it can't be spent twice.
+pub async fn insert_user(
+    pool: &SqlitePool,
+    user: &RegisterRequest,
+    invitation_code_hash: Option<&str>,
+) -> Result<User, RequestError> {
     let mut tx = pool.begin().await?;
-    let user = sqlx::query_as!(
-        User,
+    if let Some(code_hash) = invitation_code_hash {
+        use_invitation(&mut tx, code_hash).await?;
+    }
+    //? Unchecked, since sqlx runs out of memory explaining a RETURNING on a table this many
+    //? foreign keys point at
+    let user = sqlx::query_as::<Sqlite, User>(
         r#"
         INSERT INTO users (email, username, password)
         VALUES ($1, $2, $3)
-        RETURNING id, created_at as "created_at!", username as "username!", email as "email!", image, bio, password as "password!"
+        RETURNING id, created_at, username, email, image, bio, password, email_verified_at, role
         "#,
-        user.email,
-        user.username,
-        user.password
     )
+    .bind(&user.email)
+    .bind(&user.username)
+    .bind(&user.password)
     .fetch_one(&mut tx)
     .await?;
     tx.commit().await?;
@@ -57,12 +71,15 @@ pub async fn update_user_in_db(
         .add_param("password", password)
         .build();
 
-    let query = format!("{query} WHERE id = {id}");
-    let mut query = sqlx::query(&query);
-    for i in params {
-        query = query.bind(i);
+    //? Nothing to update, e.g. when the only change was an email that still needs verifying
+    if !query.is_empty() {
+        let query = format!("{query} WHERE id = {id}");
+        let mut query = sqlx::query(&query);
+        for i in params {
+            query = query.bind(i);
+        }
+        query.execute(&mut tx).await?;
     }
-    query.execute(&mut tx).await?;
 
     tx.commit().await?;
 
@@ -75,3 +92,462 @@ pub async fn update_user_in_db(
 
     Ok(result)
 }
+
+pub async fn get_user_role_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+) -> Result<Option<Role>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        SELECT role as "role: Role" FROM users WHERE id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result.map(|record| record.role))
+}
+
+/// Makes the user with this verified email an admin, as long as there is no admin yet.
+pub async fn promote_first_admin_in_db(
+    pool: &SqlitePool,
+    email: &str,
+) -> Result<bool, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        UPDATE users SET role = 'admin'
+        WHERE email = $1 AND email_verified_at IS NOT NULL
+            AND NOT EXISTS (SELECT 1 FROM users WHERE role = 'admin')
+        "#,
+        email
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn set_user_role_in_db(
+    pool: &SqlitePool,
+    username: &str,
+    role: Role,
+) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        UPDATE users SET role = $1 WHERE username = $2
+        "#,
+        role,
+        username
+    )
+    .execute(&mut tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(RequestError::NotFound("User not found"));
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Stores a new password reset token for the user, replacing any that are still outstanding.
+pub async fn create_password_reset_token_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+    token_hash: &str,
+    expiry: i64,
+) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM password_reset_tokens WHERE user_id = $1 AND used_at IS NULL
+        "#,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (user_id, token_hash, expires_at)
+        VALUES ($1, $2, datetime('now', '+' || $3 || ' seconds'))
+        "#,
+        user_id,
+        token_hash,
+        expiry
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Consumes a password reset token and sets the new password, returning the user's id.
+pub async fn reset_password_in_db(
+    pool: &SqlitePool,
+    token_hash: &str,
+    password_hash: &str,
+) -> Result<i64, RequestError> {
+    let mut tx = pool.begin().await?;
+    let token = sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens SET used_at = CURRENT_TIMESTAMP
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+        RETURNING user_id as "user_id!"
+        "#,
+        token_hash
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let user_id = match token {
+        Some(token) => token.user_id,
+        None => return Err(RequestError::RunTimeError("Invalid or expired reset token")),
+    };
+
+    sqlx::query!(
+        r#"
+        UPDATE users SET password = $1 WHERE id = $2
+        "#,
+        password_hash,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(user_id)
+}
+
+/// The user a password reset token belongs to, as long as it can still be used.
+pub async fn get_password_reset_user_in_db(
+    pool: &SqlitePool,
+    token_hash: &str,
+) -> Result<Option<User>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query_as!(
+        User,
+        r#"
+        SELECT users.id as "id!", users.created_at as 'created_at!', username, email, image, bio,
+            password, email_verified_at, role as "role: Role"
+        FROM password_reset_tokens
+            JOIN users ON users.id = password_reset_tokens.user_id
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+        "#,
+        token_hash
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result)
+}
+
+/// Swaps the user's password hash for a stronger hash of the same password. Does nothing if the
+/// password was changed in the meantime.
+pub async fn update_password_hash_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+    old_hash: &str,
+    new_hash: &str,
+) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE users SET password = $1 WHERE id = $2 AND password = $3
+        "#,
+        new_hash,
+        user_id,
+        old_hash
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Replaces the user's unused token for the same `purpose`, so resending a verification email
+/// doesn't cancel a pending email change or the other way round.
+pub async fn create_email_verification_token_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+    email: &str,
+    purpose: VerificationPurpose,
+    token_hash: &str,
+    expiry: i64,
+) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM email_verification_tokens
+        WHERE user_id = $1 AND used_at IS NULL AND purpose = $2
+        "#,
+        user_id,
+        purpose
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO email_verification_tokens (user_id, email, purpose, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4, datetime('now', '+' || $5 || ' seconds'))
+        "#,
+        user_id,
+        email,
+        purpose,
+        token_hash,
+        expiry
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Consumes a verification token and marks the address it was sent to as the user's verified
+/// email. For an email change this is the point where the new address replaces the old one.
+pub async fn verify_email_in_db(pool: &SqlitePool, token_hash: &str) -> Result<User, RequestError> {
+    let mut tx = pool.begin().await?;
+    let token = sqlx::query!(
+        r#"
+        UPDATE email_verification_tokens SET used_at = CURRENT_TIMESTAMP
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > CURRENT_TIMESTAMP
+        RETURNING user_id as "user_id!", email as "email!",
+            purpose as "purpose!: VerificationPurpose"
+        "#,
+        token_hash
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let token = match token {
+        Some(token) => token,
+        None => {
+            return Err(RequestError::RunTimeError(
+                "Invalid or expired verification token",
+            ))
+        }
+    };
+
+    let updated = match token.purpose {
+        //? Only if the address is still the user's, an email change may have replaced it since
+        VerificationPurpose::Verify => sqlx::query!(
+            r#"
+            UPDATE users SET email_verified_at = CURRENT_TIMESTAMP WHERE id = $1 AND email = $2
+            "#,
+            token.user_id,
+            token.email
+        )
+        .execute(&mut tx)
+        .await?
+        .rows_affected(),
+        VerificationPurpose::Change => sqlx::query!(
+            r#"
+            UPDATE users SET email = $1, email_verified_at = CURRENT_TIMESTAMP WHERE id = $2
+            "#,
+            token.email,
+            token.user_id
+        )
+        .execute(&mut tx)
+        .await?
+        .rows_affected(),
+    };
+    if updated == 0 {
+        return Err(RequestError::RunTimeError(
+            "Invalid or expired verification token",
+        ));
+    }
+    tx.commit().await?;
+
+    match get_user_by_id(pool, token.user_id).await? {
+        Some(user) => Ok(user),
+        None => Err(RequestError::NotFound("User not found")),
+    }
+}
+
+/// Marks the user's account for deletion once `grace_period` seconds have passed and signs
+/// them out everywhere. Returns when the account will be purged.
+pub async fn schedule_user_deletion_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+    content: ContentAction,
+    grace_period: i64,
+) -> Result<NaiveDateTime, RequestError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE users SET deletion_requested_at = CURRENT_TIMESTAMP,
+            deletion_scheduled_at = datetime('now', '+' || $1 || ' seconds'),
+            deletion_content = $2
+        WHERE id = $3
+        "#,
+        grace_period,
+        content,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE sessions SET revoked_at = CURRENT_TIMESTAMP
+        WHERE user_id = $1 AND revoked_at IS NULL
+        "#,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    let scheduled_at = sqlx::query!(
+        r#"
+        SELECT deletion_scheduled_at as "deletion_scheduled_at!" FROM users WHERE id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(&mut tx)
+    .await?
+    .deletion_scheduled_at;
+    tx.commit().await?;
+    Ok(scheduled_at)
+}
+
+/// Keeps an account that was scheduled for deletion. Does nothing if it wasn't.
+pub async fn cancel_user_deletion_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+) -> Result<(), RequestError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        r#"
+        UPDATE users SET deletion_requested_at = NULL, deletion_scheduled_at = NULL,
+            deletion_content = NULL
+        WHERE id = $1 AND deletion_requested_at IS NOT NULL
+        "#,
+        user_id
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// The accounts whose grace period is over, along with what to do with their content.
+pub async fn get_users_due_for_deletion_in_db(
+    pool: &SqlitePool,
+) -> Result<Vec<(i64, ContentAction)>, RequestError> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query!(
+        r#"
+        SELECT id as "id!", deletion_content as "deletion_content!: ContentAction" FROM users
+        WHERE deletion_scheduled_at <= CURRENT_TIMESTAMP
+        "#
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(result
+        .into_iter()
+        .map(|user| (user.id, user.deletion_content))
+        .collect())
+}
+
+/// Removes an account whose grace period is over. Either the user and everything they wrote is
+/// deleted, or their articles and comments are kept under an anonymous placeholder and
+/// everything else that belonged to them is removed.
+///
+/// Failed logins counted against their email are forgotten too. The impersonation audit log is
+/// kept in either case: it only holds ids, and it is the record of what admins did.
+///
+/// Returns the ids of the user's data exports, whose files are for the caller to remove, or
+/// `None` without touching anything if the user logged back in since it was scheduled.
+pub async fn purge_user_in_db(
+    pool: &SqlitePool,
+    user_id: i64,
+    content: ContentAction,
+) -> Result<Option<Vec<i64>>, RequestError> {
+    let mut tx = pool.begin().await?;
+    //? Login attempts are keyed by email rather than id, so look it up before it is gone
+    let email = match sqlx::query!("SELECT email FROM users WHERE id = $1", user_id)
+        .fetch_optional(&mut tx)
+        .await?
+    {
+        Some(user) => user.email,
+        None => return Ok(None),
+    };
+    let export_ids = sqlx::query!(
+        r#"SELECT id as "id!" FROM data_exports WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_all(&mut tx)
+    .await?
+    .into_iter()
+    .map(|export| export.id)
+    .collect();
+    let result = match content {
+        //? Everything referencing the user or their articles cascades from here. Not checked with
+        //? `query!`, all those cascades make it far too expensive to analyse at compile time
+        ContentAction::Delete => {
+            sqlx::query(
+                r#"
+                DELETE FROM users WHERE id = $1 AND deletion_scheduled_at <= CURRENT_TIMESTAMP
+                "#,
+            )
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?
+        }
+        ContentAction::Anonymise => {
+            sqlx::query!(
+                r#"
+                UPDATE users SET username = 'deleted-user-' || id,
+                    email = 'deleted-user-' || id || '@deleted.invalid',
+                    password = '', bio = NULL, image = NULL, email_verified_at = NULL,
+                    role = 'reader', deletion_requested_at = NULL,
+                    deletion_scheduled_at = NULL, deletion_content = NULL,
+                    deleted_at = CURRENT_TIMESTAMP
+                WHERE id = $1 AND deletion_scheduled_at <= CURRENT_TIMESTAMP
+                "#,
+                user_id
+            )
+            .execute(&mut tx)
+            .await?
+        }
+    };
+    if result.rows_affected() == 0 {
+        return Ok(None);
+    }
+
+    if content == ContentAction::Anonymise {
+        //? The placeholder keeps the user's id, so remove everything else tied to it by hand
+        let statements = [
+            "DELETE FROM follows WHERE follower_id = $1 OR followed_id = $1",
+            "DELETE FROM favourite WHERE user_id = $1",
+            "DELETE FROM sessions WHERE user_id = $1 OR impersonator_id = $1",
+            "DELETE FROM password_reset_tokens WHERE user_id = $1",
+            "DELETE FROM email_verification_tokens WHERE user_id = $1",
+            "DELETE FROM two_factor WHERE user_id = $1",
+            "DELETE FROM recovery_codes WHERE user_id = $1",
+            "DELETE FROM api_keys WHERE user_id = $1",
+            "DELETE FROM user_identities WHERE user_id = $1",
+            "DELETE FROM data_exports WHERE user_id = $1",
+            "DELETE FROM invitations WHERE created_by = $1",
+        ];
+        for statement in statements {
+            sqlx::query(statement)
+                .bind(user_id)
+                .execute(&mut tx)
+                .await?;
+        }
+    } else {
+        //? Their articles may have been the last to use some tags
+        delete_unused_tags(&mut tx).await?;
+    }
+    let login_key = account_key(&email);
+    sqlx::query!("DELETE FROM login_attempts WHERE key = $1", login_key)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(Some(export_ids))
+}
@@ -6,8 +6,9 @@ use crate::JsonResponse;
 pub enum RequestError {
     NotFound(&'static str),
     NotAuthorized(&'static str),
-    Forbidden,
+    Forbidden(&'static str),
     RunTimeError(&'static str),
+    TooManyRequests(&'static str),
     ServerError,
     DatabaseError(sqlx::Error),
 }
@@ -53,14 +54,17 @@ impl RequestError {
                 StatusCode::UNAUTHORIZED,
                 RequestErrorJsonWrapper::new(message),
             ),
-            RequestError::Forbidden => (
-                StatusCode::FORBIDDEN,
-                RequestErrorJsonWrapper::new("Forbidden"),
-            ),
+            RequestError::Forbidden(message) => {
+                (StatusCode::FORBIDDEN, RequestErrorJsonWrapper::new(message))
+            }
             RequestError::RunTimeError(message) => (
                 StatusCode::UNPROCESSABLE_ENTITY,
                 RequestErrorJsonWrapper::new(message),
             ),
+            RequestError::TooManyRequests(message) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                RequestErrorJsonWrapper::new(message),
+            ),
             RequestError::ServerError => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 RequestErrorJsonWrapper::new("Internal Server Error"),
@@ -1,20 +1,54 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{net::SocketAddr, sync::Arc};
 
 use axum::{
-    extract::{Path, Query},
-    http::{StatusCode, Uri},
+    extract::{rejection::QueryRejection, ConnectInfo, Path, Query},
+    http::{header, HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
     Extension, Json,
 };
+use chrono::Utc;
+use jsonwebtoken::jwk::JwkSet;
 use sqlx::SqlitePool;
 
 use crate::{
-    authentication::{AuthUser, MaybeUser},
-    data_formats::{request::*, response::*, wrapper::*, ArticleQueryParams},
+    authentication::{AuthUser, ClientInfo, MaybeUser},
+    data_formats::{
+        request::*, response::*, wrapper::*, ArticleQueryParams, BodyFormatQueryParams,
+        CommentQueryParams, RevisionDiffQueryParams, SearchQueryParams,
+    },
     db_helpers::*,
     errors::RequestError,
 };
 
-use crate::authentication::{get_jwt_token, hash_password_argon2, verify_password_argon2};
+use crate::account_deletion::get_account_deletion_grace_period;
+use crate::api_keys::{generate_api_key, scopes_to_string, ApiKeyScope};
+use crate::article_scheduler::notify_article_scheduled;
+use crate::authentication::{
+    create_session, ensure_email_verified, generate_random_token, get_dummy_password_hash,
+    get_email_verification_token_expiry, get_impersonation_token, get_jwt_token,
+    get_oidc_login_state_expiry, get_password_reset_token_expiry, get_refresh_token_expiry,
+    get_two_factor_challenge_expiry, get_two_factor_challenge_token, hash_password_argon2,
+    hash_token, upgrade_password_hash, verify_password_argon2, verify_two_factor_challenge_token,
+};
+use crate::data_export::{export_path, get_data_export_expiry, spawn_data_export};
+use crate::impersonation::ensure_can_impersonate;
+use crate::jwt_keys::jwt_keys;
+use crate::login_throttle::{ensure_login_allowed, record_login_failure, record_login_success};
+use crate::mailer::{Email, Mailer};
+use crate::markdown::article_body_html;
+use crate::models::{Article, ArticleStatus, DataExportStatus, Role, User, VerificationPurpose};
+use crate::oidc::{oidc_provider, IdTokenClaims};
+use crate::pagination::{pagination_headers, ArticlePosition, Paging};
+use crate::passwords::ensure_password_allowed;
+use crate::policy::{bootstrap_admin, ensure_can_modify, ensure_can_view, roles, RequireRole};
+use crate::registration::{
+    ensure_email_domain_allowed, get_invitation_expiry, get_invitation_quota,
+    get_registration_mode, RegistrationMode,
+};
+use crate::two_factor::{
+    generate_recovery_codes, generate_totp_secret, get_otpauth_uri, normalize_recovery_code,
+    verify_totp_code,
+};
 
 // use crate::{ProfileResponse, ProfileWrapper, UpdateUserRequest};
 
@@ -24,12 +58,19 @@ type ArticleJson = ArticleWrapper<ArticleResponse>;
 type CommentJson = CommentWrapper<CommentResponse>;
 
 type JsonResult<T> = Result<Json<T>, RequestError>;
+/// A page of a list, with `Link` headers to the others.
+type PagedJsonResult<T> = Result<(HeaderMap, Json<T>), RequestError>;
 
 // ----------------- Helper Handlers -----------------
 pub async fn alive() -> &'static str {
     "alive"
 }
 
+pub async fn get_jwks() -> JsonResult<JwkSet> {
+    let keys = jwt_keys().map_err(|_| RequestError::ServerError)?;
+    Ok(Json(keys.jwks().clone()))
+}
+
 pub async fn not_found(uri: Uri) -> Result<(), (StatusCode, String)> {
     Err((
         StatusCode::NOT_FOUND,
@@ -37,61 +78,678 @@ pub async fn not_found(uri: Uri) -> Result<(), (StatusCode, String)> {
     ))
 }
 
+/// Sends a verification link for `email`, which is either the user's current address or the
+/// one they want to change to.
+async fn send_verification_email(
+    pool: &SqlitePool,
+    mailer: &dyn Mailer,
+    user: &User,
+    email: &str,
+) -> Result<(), RequestError> {
+    let token = generate_random_token();
+    let purpose = if email == user.email {
+        VerificationPurpose::Verify
+    } else {
+        VerificationPurpose::Change
+    };
+    create_email_verification_token_in_db(
+        pool,
+        user.id,
+        email,
+        purpose,
+        &hash_token(&token),
+        get_email_verification_token_expiry(),
+    )
+    .await?;
+
+    let app_url = std::env::var("APP_URL").unwrap_or_else(|_| "http://localhost:3000".into());
+    let email = Email {
+        to: email.to_owned(),
+        subject: "Verify your email".into(),
+        body: format!(
+            "Hi {},\n\nPlease confirm this email address by opening this link:\n\
+            {}/verify-email?token={}",
+            user.username, app_url, token
+        ),
+    };
+    mailer.send(email).await.map_err(|e| {
+        eprintln!("Mail error: {:?}", e);
+        RequestError::ServerError
+    })
+}
+
+/// Checks a TOTP code and uses up its time step, so the same code can't be used again.
+async fn use_totp_code(
+    pool: &SqlitePool,
+    user_id: i64,
+    secret: &str,
+    account_name: &str,
+    code: &str,
+) -> Result<bool, RequestError> {
+    match verify_totp_code(secret, account_name, code).map_err(|_| RequestError::ServerError)? {
+        Some(step) => use_totp_step_in_db(pool, user_id, step).await,
+        None => Ok(false),
+    }
+}
+
+/// Looks up an article for a reader, answering as if drafts they may not see didn't exist.
+async fn get_visible_article(
+    pool: &SqlitePool,
+    slug: &str,
+    user: Option<&AuthUser>,
+) -> Result<Article, RequestError> {
+    let article = match get_article_by_slug_in_db(pool, slug, user.map(|user| user.id)).await? {
+        Some(article) => article,
+        None => return Err(RequestError::NotFound("Article not found")),
+    };
+    ensure_can_view(user, &article)?;
+    Ok(article)
+}
+
+async fn article_response(
+    pool: &SqlitePool,
+    article: Article,
+    format: &BodyFormatQueryParams,
+) -> Result<ArticleResponse, RequestError> {
+    if !format.html {
+        return Ok(ArticleResponse::new(article));
+    }
+    let body_html = article_body_html(pool, &article).await?;
+    Ok(ArticleResponse::new(article).with_body_html(body_html))
+}
+
+async fn article_responses(
+    pool: &SqlitePool,
+    articles: Vec<Article>,
+    format: &BodyFormatQueryParams,
+) -> Result<Vec<ArticleResponse>, RequestError> {
+    let mut responses = Vec::with_capacity(articles.len());
+    for article in articles {
+        responses.push(article_response(pool, article, format).await?);
+    }
+    Ok(responses)
+}
+
+/// Looks up an article for someone about to look through or change its history, which only
+/// the people who may edit it can do.
+async fn get_editable_article(
+    pool: &SqlitePool,
+    slug: &str,
+    user: &AuthUser,
+) -> Result<Article, RequestError> {
+    user.ensure_scope(ApiKeyScope::ArticlesWrite)?;
+    let article = get_visible_article(pool, slug, Some(user)).await?;
+    ensure_can_modify(user, article.author_id)?;
+    Ok(article)
+}
+
 // ----------------- User Handlers -----------------
 pub async fn login_user(
     Extension(pool): Extension<Arc<SqlitePool>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    client: ClientInfo,
     Json(UserWrapper { user: request }): Json<UserWrapper<LoginRequest>>,
-) -> JsonResult<UserJson> {
+) -> JsonResult<LoginWrapper> {
+    ensure_login_allowed(&pool, &request.email, address.ip()).await?;
     let user = get_user_by_email(&pool, &request.email)
         .await
         .map_err(|_| RequestError::RunTimeError("Could not login user\nPlease Try again"))?;
+    //? Still check the password when the email is unknown so the response time doesn't give it away
+    let password_hash = match &user {
+        Some(user) => user.password.clone(),
+        None => get_dummy_password_hash()
+            .await
+            .map_err(|_| RequestError::ServerError)?
+            .to_owned(),
+    };
+    let is_password_correct = verify_password_argon2(request.password.clone(), &password_hash)
+        .await
+        .map_err(|_| RequestError::RunTimeError("Could not login user\nPlease Try again"))?;
+
     let user = match user {
+        Some(user) if is_password_correct => user,
+        _ => {
+            record_login_failure(&pool, &request.email, address.ip()).await?;
+            return Err(RequestError::RunTimeError("Invalid credentials"));
+        }
+    };
+    //? Not worth failing the login over, the next one will try again
+    if let Err(e) = upgrade_password_hash(&pool, user.id, &user.password, request.password).await {
+        eprintln!("Could not upgrade password hash: {:?}", e);
+    }
+
+    let two_factor = get_two_factor_in_db(&pool, user.id).await?;
+    if two_factor.is_some_and(|two_factor| two_factor.enabled_at.is_some()) {
+        let challenge_token =
+            get_two_factor_challenge_token(user.id).map_err(|_| RequestError::ServerError)?;
+        let result = TwoFactorChallengeResponse {
+            challenge_token,
+            expires_in: get_two_factor_challenge_expiry(),
+        };
+        return Ok(Json(LoginWrapper::TwoFactorRequired(TwoFactorWrapper {
+            two_factor: result,
+        })));
+    }
+
+    record_login_success(&pool, &request.email).await?;
+    let (token, refresh_token) = create_session(&pool, user.id, &client).await?;
+    let result = UserResponse::new(user, token).with_refresh_token(refresh_token);
+    Ok(Json(LoginWrapper::User(UserWrapper::wrap_with_user_data(
+        result,
+    ))))
+}
+
+pub async fn login_user_two_factor(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    client: ClientInfo,
+    Json(UserWrapper { user: request }): Json<UserWrapper<TwoFactorLoginRequest>>,
+) -> JsonResult<UserJson> {
+    let id = verify_two_factor_challenge_token(&request.challenge_token)?;
+    let user = match get_user_by_id(&pool, id).await? {
         Some(user) => user,
-        None => {
-            return Err(RequestError::RunTimeError("Email not found"));
+        None => return Err(RequestError::NotFound("User not found")),
+    };
+    let two_factor = match get_two_factor_in_db(&pool, id).await? {
+        Some(two_factor) if two_factor.enabled_at.is_some() => two_factor,
+        _ => return Err(RequestError::NotAuthorized("Invalid challenge token")),
+    };
+    ensure_login_allowed(&pool, &user.email, address.ip()).await?;
+
+    let is_code_correct = match (request.code, request.recovery_code) {
+        (Some(code), _) => use_totp_code(&pool, id, &two_factor.secret, &user.email, &code).await?,
+        (None, Some(recovery_code)) => {
+            let recovery_code = normalize_recovery_code(&recovery_code);
+            let mut matching_code = None;
+            for code in get_unused_recovery_codes_in_db(&pool, id).await? {
+                if verify_password_argon2(recovery_code.clone(), &code.code_hash)
+                    .await
+                    .map_err(|_| RequestError::ServerError)?
+                {
+                    matching_code = Some(code.id);
+                    break;
+                }
+            }
+            match matching_code {
+                Some(code_id) => {
+                    use_recovery_code_in_db(&pool, code_id).await?;
+                    true
+                }
+                None => false,
+            }
+        }
+        (None, None) => {
+            return Err(RequestError::RunTimeError(
+                "Either code or recoveryCode is required",
+            ))
+        }
+    };
+    if !is_code_correct {
+        record_login_failure(&pool, &user.email, address.ip()).await?;
+        return Err(RequestError::NotAuthorized("Invalid two-factor code"));
+    }
+
+    record_login_success(&pool, &user.email).await?;
+    let (token, refresh_token) = create_session(&pool, user.id, &client).await?;
+    let result = UserResponse::new(user, token).with_refresh_token(refresh_token);
+    Ok(Json(UserWrapper::wrap_with_user_data(result)))
+}
+
+/// Starts logging in with an OpenID Connect provider. The client sends the user to the
+/// returned URL and passes the `code` and `state` the provider redirects back with to
+/// `finish_oidc_login`. The body is optional, it is only needed to pass along an invitation
+/// code for signing up while registration is invite-only.
+pub async fn start_oidc_login(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Path(provider_name): Path<String>,
+    request: Option<Json<OidcWrapper<OidcAuthorizationRequest>>>,
+) -> JsonResult<OidcWrapper<OidcAuthorizationResponse>> {
+    let request = request
+        .map(|Json(wrapper)| wrapper.oidc)
+        .unwrap_or_default();
+    let invitation_code_hash = request
+        .invitation_code
+        .as_deref()
+        .map(str::trim)
+        .filter(|code| !code.is_empty())
+        .map(hash_token);
+    let provider = oidc_provider(&provider_name)?;
+    let state = generate_random_token();
+    let nonce = generate_random_token();
+    let code_verifier = generate_random_token();
+    let authorization_url = provider
+        .authorization_url(&state, &nonce, &code_verifier)
+        .await?;
+    create_oidc_login_state_in_db(
+        &pool,
+        &hash_token(&state),
+        &provider_name.to_lowercase(),
+        &code_verifier,
+        &nonce,
+        invitation_code_hash.as_deref(),
+        get_oidc_login_state_expiry(),
+    )
+    .await?;
+    let result = OidcAuthorizationResponse {
+        authorization_url,
+        state,
+    };
+    Ok(Json(OidcWrapper { oidc: result }))
+}
+
+/// Finds the user an external identity belongs to, linking it to the account with the same
+/// email when both we and the provider have verified it, or creating a new account on their
+/// first login. New accounts are subject to the same registration mode and email domains as
+/// `register_user`.
+async fn get_or_create_oidc_user(
+    pool: &SqlitePool,
+    claims: &IdTokenClaims,
+    invitation_code_hash: Option<&str>,
+) -> Result<User, RequestError> {
+    if let Some(user) = get_user_by_identity_in_db(pool, &claims.iss, &claims.sub).await? {
+        return Ok(user);
+    }
+    let email = claims.email.as_deref().ok_or(RequestError::RunTimeError(
+        "The login provider did not share an email address",
+    ))?;
+
+    if let Some(user) = get_user_by_email(pool, email).await? {
+        //? Only trust the provider with an existing account when it has checked the address,
+        //? otherwise anyone could take over an account by claiming its email. The account has
+        //? to have checked it too, or whoever registered it with someone else's address and a
+        //? password of their own would share the account once its real owner logs in
+        if !claims.email_verified || user.email_verified_at.is_none() {
+            return Err(RequestError::RunTimeError("Email already exists"));
         }
+        link_identity_in_db(pool, user.id, &claims.iss, &claims.sub).await?;
+        return match get_user_by_id(pool, user.id).await? {
+            Some(user) => Ok(user),
+            None => Err(RequestError::NotFound("User not found")),
+        };
+    }
+
+    let invitation_code_hash = match get_registration_mode() {
+        RegistrationMode::Open => None,
+        RegistrationMode::InviteOnly => match invitation_code_hash {
+            Some(code_hash) => Some(code_hash),
+            None => {
+                return Err(RequestError::Forbidden(
+                    "Registration requires an invitation code",
+                ))
+            }
+        },
+        RegistrationMode::Closed => return Err(RequestError::Forbidden("Registration is closed")),
     };
-    let is_password_correct = verify_password_argon2(request.password, &user.password)
+    ensure_email_domain_allowed(email)?;
+
+    //? The account can only be used through the provider until the user sets a password
+    let password_hash = hash_password_argon2(generate_random_token())
         .await
-        .map_err(|_| RequestError::RunTimeError("Could not login user\nPlease Try again"))?;
+        .map_err(|_| RequestError::ServerError)?;
+    create_user_from_identity_in_db(pool, claims, email, &password_hash, invitation_code_hash)
+        .await
+        .map_err(|e| {
+            if let RequestError::DatabaseError(sqlx::Error::Database(e)) = &e {
+                if e.message().contains("UNIQUE constraint failed") {
+                    return RequestError::RunTimeError("Email already exists");
+                }
+            }
+            e
+        })
+}
 
-    if !is_password_correct {
-        return Err(RequestError::RunTimeError("Incorrect password"));
+pub async fn finish_oidc_login(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Path(provider_name): Path<String>,
+    client: ClientInfo,
+    Json(OidcWrapper { oidc: request }): Json<OidcWrapper<OidcCallbackRequest>>,
+) -> JsonResult<LoginWrapper> {
+    let provider = oidc_provider(&provider_name)?;
+    let state = consume_oidc_login_state_in_db(
+        &pool,
+        &hash_token(&request.state),
+        &provider_name.to_lowercase(),
+    )
+    .await?;
+    let claims = provider
+        .exchange_code(&request.code, &state.code_verifier, &state.nonce)
+        .await?;
+    let user =
+        get_or_create_oidc_user(&pool, &claims, state.invitation_code_hash.as_deref()).await?;
+
+    //? Users who turned on two-factor authentication still need their second factor
+    let two_factor = get_two_factor_in_db(&pool, user.id).await?;
+    if two_factor.is_some_and(|two_factor| two_factor.enabled_at.is_some()) {
+        let challenge_token =
+            get_two_factor_challenge_token(user.id).map_err(|_| RequestError::ServerError)?;
+        let result = TwoFactorChallengeResponse {
+            challenge_token,
+            expires_in: get_two_factor_challenge_expiry(),
+        };
+        return Ok(Json(LoginWrapper::TwoFactorRequired(TwoFactorWrapper {
+            two_factor: result,
+        })));
     }
-    let token = get_jwt_token(user.id).unwrap();
-    let result = UserResponse::new(user, token);
-    Ok(Json(UserWrapper::wrap_with_user_data(result)))
+
+    let (token, refresh_token) = create_session(&pool, user.id, &client).await?;
+    let result = UserResponse::new(user, token).with_refresh_token(refresh_token);
+    Ok(Json(LoginWrapper::User(UserWrapper::wrap_with_user_data(
+        result,
+    ))))
 }
 
 pub async fn register_user(
     Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    client: ClientInfo,
     Json(UserWrapper { mut user }): Json<UserWrapper<RegisterRequest>>,
 ) -> JsonResult<UserJson> {
+    let invitation_code_hash = match get_registration_mode() {
+        RegistrationMode::Open => None,
+        RegistrationMode::InviteOnly => match user.invitation_code.as_deref() {
+            Some(code) if !code.trim().is_empty() => Some(hash_token(code.trim())),
+            _ => {
+                return Err(RequestError::Forbidden(
+                    "Registration requires an invitation code",
+                ))
+            }
+        },
+        RegistrationMode::Closed => return Err(RequestError::Forbidden("Registration is closed")),
+    };
+    ensure_email_domain_allowed(&user.email)?;
+    ensure_password_allowed(&user.password, &[&user.username, &user.email])?;
+
     user.password = hash_password_argon2(user.password)
         .await
         .map_err(|_| RequestError::RunTimeError("Could not register user\nPlease Try: again"))?;
 
-    let user = insert_user(&pool, &user).await.map_err(|e| {
-        if let RequestError::DatabaseError(sqlx::Error::Database(e)) = e {
-            if e.message().contains("UNIQUE constraint failed") {
-                return RequestError::RunTimeError("Email already exists");
+    let user = insert_user(&pool, &user, invitation_code_hash.as_deref())
+        .await
+        .map_err(|e| match e {
+            RequestError::DatabaseError(sqlx::Error::Database(e))
+                if e.message().contains("UNIQUE constraint failed") =>
+            {
+                RequestError::RunTimeError("Email already exists")
             }
-        }
-        RequestError::RunTimeError("Could not register user")
-    })?;
+            RequestError::Forbidden(message) => RequestError::Forbidden(message),
+            _ => RequestError::RunTimeError("Could not register user"),
+        })?;
+
+    //? The account already exists at this point, so a mail failure shouldn't fail registration.
+    //? The user can ask for another verification email later.
+    let email = user.email.clone();
+    if let Err(e) = send_verification_email(&pool, mailer.as_ref(), &user, &email).await {
+        eprintln!("Could not send verification email: {:?}", e);
+    }
 
-    let token = get_jwt_token(user.id).map_err(|_| {
+    let (token, refresh_token) = create_session(&pool, user.id, &client).await.map_err(|_| {
         RequestError::RunTimeError("Could not generate JWT successfully\nTry again later")
     })?;
-    let result = UserResponse::new(user, token);
+    let result = UserResponse::new(user, token).with_refresh_token(refresh_token);
     Ok(Json(UserWrapper::wrap_with_user_data(result)))
 }
 
+pub async fn refresh_token(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    client: ClientInfo,
+    Json(UserWrapper { user: request }): Json<UserWrapper<RefreshTokenRequest>>,
+) -> JsonResult<UserJson> {
+    let new_refresh_token = generate_random_token();
+    let (id, session_id) = rotate_refresh_token_in_db(
+        &pool,
+        &hash_token(&request.refresh_token),
+        &hash_token(&new_refresh_token),
+        get_refresh_token_expiry(),
+    )
+    .await?;
+    if !touch_session_in_db(&pool, session_id, id, None, client.ip_address.as_deref()).await? {
+        return Err(RequestError::NotAuthorized("Session has been revoked"));
+    }
+    let user = match get_user_by_id(&pool, id).await? {
+        Some(user) => user,
+        None => return Err(RequestError::NotFound("User not found")),
+    };
+    let token = get_jwt_token(id, session_id).map_err(|_| RequestError::ServerError)?;
+    let result = UserResponse::new(user, token).with_refresh_token(new_refresh_token);
+    Ok(Json(UserWrapper::wrap_with_user_data(result)))
+}
+
+pub async fn logout_user(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+) -> Result<(), RequestError> {
+    if let Some(user) = maybe_user {
+        //? Also how an admin ends an impersonation session early
+        let session_id = user.current_session()?;
+        revoke_session_in_db(&pool, session_id).await?;
+        return Ok(());
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+
+pub async fn list_sessions(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+) -> JsonResult<MultipleSessionsWrapper> {
+    if let Some(user) = maybe_user {
+        let current_session_id = user.ensure_session()?;
+        let sessions = list_sessions_in_db(&pool, user.id)
+            .await?
+            .into_iter()
+            .map(|session| SessionResponse::new(session, current_session_id))
+            .collect();
+        return Ok(Json(MultipleSessionsWrapper { sessions }));
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+
+pub async fn revoke_session(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+    Path(session_id): Path<i64>,
+) -> Result<(), RequestError> {
+    if let Some(user) = maybe_user {
+        user.ensure_session()?;
+        revoke_user_session_in_db(&pool, user.id, session_id).await?;
+        return Ok(());
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+
+/// Starts building an archive of everything we hold about the user. The download link is only
+/// returned here; `get_data_export` tells the client when it's ready.
+pub async fn request_data_export(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+) -> JsonResult<DataExportWrapper> {
+    if let Some(user) = maybe_user {
+        user.ensure_session()?;
+        let token = generate_random_token();
+        let export_id = create_data_export_in_db(
+            &pool,
+            user.id,
+            &hash_token(&token),
+            get_data_export_expiry(),
+        )
+        .await?;
+        spawn_data_export(pool.as_ref().clone(), user.id, export_id);
+        let export = match get_data_export_in_db(&pool, export_id).await? {
+            Some(export) => export,
+            None => return Err(RequestError::NotFound("Export not found")),
+        };
+        let result =
+            DataExportResponse::new(export).with_download_url(format!("/exports/{}", token));
+        return Ok(Json(DataExportWrapper { export: result }));
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+
+pub async fn get_data_export(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+) -> JsonResult<DataExportWrapper> {
+    if let Some(user) = maybe_user {
+        user.ensure_session()?;
+        let export = match get_latest_data_export_in_db(&pool, user.id).await? {
+            Some(export) => export,
+            None => return Err(RequestError::NotFound("Export not found")),
+        };
+        let result = DataExportResponse::new(export);
+        return Ok(Json(DataExportWrapper { export: result }));
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+
+/// Downloads a finished export. The token in the link is the only credential, so the link can
+/// be opened straight from a browser until it expires.
+pub async fn download_data_export(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, RequestError> {
+    let export = match get_data_export_by_token_in_db(&pool, &hash_token(&token)).await? {
+        Some(export) => export,
+        None => return Err(RequestError::NotFound("Export not found")),
+    };
+    match export.status {
+        DataExportStatus::Ready => {}
+        DataExportStatus::Pending => {
+            return Err(RequestError::RunTimeError("Export is not ready yet"))
+        }
+        DataExportStatus::Failed => {
+            return Err(RequestError::RunTimeError(
+                "Export failed, please request a new one",
+            ))
+        }
+    }
+    let archive = tokio::fs::read(export_path(export.id)).await.map_err(|e| {
+        eprintln!("Could not read export {}: {}", export.id, e);
+        RequestError::NotFound("Export not found")
+    })?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"conduit-export.zip\"",
+            ),
+        ],
+        archive,
+    ))
+}
+
+pub async fn forgot_password(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    Json(UserWrapper { user: request }): Json<UserWrapper<ForgotPasswordRequest>>,
+) -> Result<(), RequestError> {
+    //? Always respond the same way, and before doing anything that takes longer for a registered
+    //? email, so this can't be used to find out which emails are registered
+    tokio::spawn(async move {
+        if let Err(e) = send_password_reset_email(&pool, mailer.as_ref(), &request.email).await {
+            eprintln!("Could not send password reset email: {:?}", e);
+        }
+    });
+    Ok(())
+}
+
+/// Emails a password reset link to the user with `email`, if there is one.
+async fn send_password_reset_email(
+    pool: &SqlitePool,
+    mailer: &dyn Mailer,
+    email: &str,
+) -> Result<(), RequestError> {
+    let user = match get_user_by_email(pool, email).await? {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+    let token = generate_random_token();
+    create_password_reset_token_in_db(
+        pool,
+        user.id,
+        &hash_token(&token),
+        get_password_reset_token_expiry(),
+    )
+    .await?;
+
+    let app_url = std::env::var("APP_URL").unwrap_or_else(|_| "http://localhost:3000".into());
+    let email = Email {
+        to: user.email,
+        subject: "Reset your password".into(),
+        body: format!(
+            "Someone asked to reset the password for {}.\n\n\
+            Use this link to choose a new password:\n{}/reset-password?token={}\n\n\
+            If this wasn't you, you can ignore this email.",
+            user.username, app_url, token
+        ),
+    };
+    mailer.send(email).await.map_err(|e| {
+        eprintln!("Mail error: {:?}", e);
+        RequestError::ServerError
+    })
+}
+
+pub async fn reset_password(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Json(UserWrapper { user: request }): Json<UserWrapper<ResetPasswordRequest>>,
+) -> Result<(), RequestError> {
+    let token_hash = hash_token(&request.token);
+    let user = match get_password_reset_user_in_db(&pool, &token_hash).await? {
+        Some(user) => user,
+        None => return Err(RequestError::RunTimeError("Invalid or expired reset token")),
+    };
+    ensure_password_allowed(&request.password, &[&user.username, &user.email])?;
+    let password = hash_password_argon2(request.password)
+        .await
+        .map_err(|_| RequestError::ServerError)?;
+    let id = reset_password_in_db(&pool, &token_hash, &password).await?;
+    revoke_all_sessions_in_db(&pool, id, None).await?;
+    Ok(())
+}
+
+pub async fn verify_email(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Json(UserWrapper { user: request }): Json<UserWrapper<VerifyEmailRequest>>,
+) -> Result<(), RequestError> {
+    verify_email_in_db(&pool, &hash_token(&request.token))
+        .await
+        .map_err(|e| {
+            if let RequestError::DatabaseError(sqlx::Error::Database(e)) = &e {
+                if e.message().contains("UNIQUE constraint failed") {
+                    return RequestError::RunTimeError("Email already exists");
+                }
+            }
+            e
+        })?;
+    bootstrap_admin(&pool).await
+}
+
+pub async fn resend_verification_email(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    MaybeUser(maybe_user): MaybeUser,
+) -> Result<(), RequestError> {
+    if let Some(user) = maybe_user {
+        user.ensure_session()?;
+        let user = match get_user_by_id(&pool, user.id).await? {
+            Some(user) => user,
+            None => return Err(RequestError::NotFound("User not found")),
+        };
+        if user.email_verified_at.is_some() {
+            return Err(RequestError::RunTimeError("Email already verified"));
+        }
+        let email = user.email.clone();
+        return send_verification_email(&pool, mailer.as_ref(), &user, &email).await;
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+
 pub async fn get_current_user(
     Extension(pool): Extension<Arc<SqlitePool>>,
     MaybeUser(maybe_user): MaybeUser,
 ) -> JsonResult<UserJson> {
-    if let Some(AuthUser { id, token }) = maybe_user {
+    if let Some(user) = maybe_user {
+        user.ensure_scope(ApiKeyScope::ProfileRead)?;
+        let AuthUser { id, token, .. } = user;
         let user = get_user_by_id(&pool, id)
             .await
             .map_err(|_| RequestError::ServerError)?;
@@ -111,20 +769,318 @@ pub async fn get_current_user(
 pub async fn update_user(
     MaybeUser(maybe_user): MaybeUser,
     Extension(pool): Extension<Arc<SqlitePool>>,
-    Json(UserWrapper { user }): Json<UserWrapper<UpdateUserRequest>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    Json(UserWrapper { mut user }): Json<UserWrapper<UpdateUserRequest>>,
 ) -> JsonResult<UserJson> {
-    if let Some(AuthUser { id, token }) = maybe_user {
+    if let Some(auth_user) = maybe_user {
+        auth_user.ensure_scope(ApiKeyScope::ProfileWrite)?;
+        //? Taking over the account shouldn't be possible with just an API key
+        let session_id = if user.email.is_some() || user.password.is_some() {
+            Some(auth_user.ensure_session()?)
+        } else {
+            None
+        };
+        let AuthUser { id, token, .. } = auth_user;
+        let current_user = match get_user_by_id(&pool, id).await? {
+            Some(user) => user,
+            None => return Err(RequestError::NotFound("User not found")),
+        };
+        if let Some(password) = &user.password {
+            let username = user.username.as_deref().unwrap_or(&current_user.username);
+            let mut identifiers = vec![username, &current_user.email];
+            if let Some(email) = &user.email {
+                identifiers.push(email);
+            }
+            ensure_password_allowed(password, &identifiers)?;
+        }
+        //? A new email only replaces the current one once the new address has been verified
+        if let Some(email) = user.email.take() {
+            if email != current_user.email {
+                ensure_email_domain_allowed(&email)?;
+                if get_user_by_email(&pool, &email).await?.is_some() {
+                    return Err(RequestError::RunTimeError("Email already exists"));
+                }
+                send_verification_email(&pool, mailer.as_ref(), &current_user, &email).await?;
+            }
+        }
+        let password_changed = user.password.is_some();
         let user = update_user_in_db(&pool, id, user)
             .await
             //? Add TODO Fix here
             .map_err(|_| RequestError::ServerError)?;
+        //? Whoever knew the old password shouldn't stay signed in, but the user who changed it does
+        if password_changed {
+            revoke_all_sessions_in_db(&pool, id, session_id).await?;
+        }
         let result = UserResponse::new(user, token);
         return Ok(Json(UserWrapper::wrap_with_user_data(result)));
     }
-    Err(RequestError::Forbidden)
+    Err(RequestError::Forbidden("Forbidden"))
+}
+
+/// Schedules the account for deletion and signs the user out everywhere. Logging in again
+/// before the grace period is over restores it.
+pub async fn delete_user(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+    Json(UserWrapper { user: request }): Json<UserWrapper<DeleteUserRequest>>,
+) -> JsonResult<AccountDeletionWrapper> {
+    if let Some(user) = maybe_user {
+        user.ensure_session()?;
+        let scheduled_for = schedule_user_deletion_in_db(
+            &pool,
+            user.id,
+            request.content,
+            get_account_deletion_grace_period(),
+        )
+        .await?;
+        let result = AccountDeletionResponse::new(scheduled_for, request.content);
+        return Ok(Json(AccountDeletionWrapper { deletion: result }));
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
 }
 // ----------------- End User Handlers -----------------
 
+// ----------------- Two-Factor Handlers -----------------
+pub async fn enable_two_factor(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+) -> JsonResult<TwoFactorWrapper<TwoFactorSetupResponse>> {
+    if let Some(user) = maybe_user {
+        user.ensure_session()?;
+        let id = user.id;
+        let user = match get_user_by_id(&pool, id).await? {
+            Some(user) => user,
+            None => return Err(RequestError::NotFound("User not found")),
+        };
+        let secret = generate_totp_secret();
+        set_pending_two_factor_secret_in_db(&pool, id, &secret).await?;
+        let otpauth_uri =
+            get_otpauth_uri(&secret, &user.email).map_err(|_| RequestError::ServerError)?;
+        return Ok(Json(TwoFactorWrapper {
+            two_factor: TwoFactorSetupResponse {
+                secret,
+                otpauth_uri,
+            },
+        }));
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+
+pub async fn confirm_two_factor(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+    Json(TwoFactorWrapper {
+        two_factor: request,
+    }): Json<TwoFactorWrapper<TwoFactorCodeRequest>>,
+) -> JsonResult<TwoFactorWrapper<RecoveryCodesResponse>> {
+    if let Some(user) = maybe_user {
+        user.ensure_session()?;
+        let id = user.id;
+        let user = match get_user_by_id(&pool, id).await? {
+            Some(user) => user,
+            None => return Err(RequestError::NotFound("User not found")),
+        };
+        let two_factor = match get_two_factor_in_db(&pool, id).await? {
+            Some(two_factor) if two_factor.enabled_at.is_none() => two_factor,
+            Some(_) => {
+                return Err(RequestError::RunTimeError(
+                    "Two-factor authentication is already enabled",
+                ))
+            }
+            None => {
+                return Err(RequestError::RunTimeError(
+                    "Two-factor authentication has not been set up",
+                ))
+            }
+        };
+        let is_code_correct =
+            use_totp_code(&pool, id, &two_factor.secret, &user.email, &request.code).await?;
+        if !is_code_correct {
+            return Err(RequestError::RunTimeError("Invalid two-factor code"));
+        }
+
+        let recovery_codes = generate_recovery_codes();
+        let mut recovery_code_hashes = Vec::with_capacity(recovery_codes.len());
+        for code in &recovery_codes {
+            let hash = hash_password_argon2(code.clone())
+                .await
+                .map_err(|_| RequestError::ServerError)?;
+            recovery_code_hashes.push(hash);
+        }
+        enable_two_factor_in_db(&pool, id, &recovery_code_hashes).await?;
+        return Ok(Json(TwoFactorWrapper {
+            two_factor: RecoveryCodesResponse { recovery_codes },
+        }));
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+
+pub async fn disable_two_factor(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+    Json(TwoFactorWrapper {
+        two_factor: request,
+    }): Json<TwoFactorWrapper<TwoFactorCodeRequest>>,
+) -> Result<(), RequestError> {
+    if let Some(user) = maybe_user {
+        user.ensure_session()?;
+        let id = user.id;
+        let user = match get_user_by_id(&pool, id).await? {
+            Some(user) => user,
+            None => return Err(RequestError::NotFound("User not found")),
+        };
+        let two_factor = match get_two_factor_in_db(&pool, id).await? {
+            Some(two_factor) if two_factor.enabled_at.is_some() => two_factor,
+            _ => {
+                return Err(RequestError::RunTimeError(
+                    "Two-factor authentication is not enabled",
+                ))
+            }
+        };
+        let is_code_correct =
+            use_totp_code(&pool, id, &two_factor.secret, &user.email, &request.code).await?;
+        if !is_code_correct {
+            return Err(RequestError::RunTimeError("Invalid two-factor code"));
+        }
+        return disable_two_factor_in_db(&pool, id).await;
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+// ----------------- End Two-Factor Handlers -----------------
+
+// ----------------- Invitation Handlers -----------------
+/// Creates an invitation code for `register_user`. Admins can hand out as many as they like,
+/// everyone else is limited to `INVITATION_QUOTA` unused seats at a time.
+pub async fn create_invitation(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+    Json(InvitationWrapper {
+        invitation: request,
+    }): Json<InvitationWrapper<CreateInvitationRequest>>,
+) -> JsonResult<InvitationWrapper<InvitationResponse>> {
+    if let Some(user) = maybe_user {
+        user.ensure_session()?;
+        if request.max_uses < 1 {
+            return Err(RequestError::RunTimeError("maxUses must be at least 1"));
+        }
+        let expiry = request.expires_in.unwrap_or_else(get_invitation_expiry);
+        if expiry < 1 {
+            return Err(RequestError::RunTimeError("expiresIn must be positive"));
+        }
+        let quota = (user.role < Role::Admin).then(get_invitation_quota);
+
+        let code = generate_random_token();
+        let invitation = create_invitation_in_db(
+            &pool,
+            user.id,
+            &hash_token(&code),
+            request.max_uses,
+            expiry,
+            quota,
+        )
+        .await?;
+        return Ok(Json(InvitationWrapper {
+            invitation: InvitationResponse::new(invitation).with_code(code),
+        }));
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+
+pub async fn list_invitations(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+) -> JsonResult<MultipleInvitationsWrapper> {
+    if let Some(user) = maybe_user {
+        user.ensure_session()?;
+        let invitations = list_invitations_in_db(&pool, user.id)
+            .await?
+            .into_iter()
+            .map(InvitationResponse::new)
+            .collect();
+        return Ok(Json(MultipleInvitationsWrapper { invitations }));
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+
+pub async fn revoke_invitation(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+    Path(invitation_id): Path<i64>,
+) -> Result<(), RequestError> {
+    if let Some(user) = maybe_user {
+        user.ensure_session()?;
+        revoke_invitation_in_db(&pool, user.id, invitation_id).await?;
+        return Ok(());
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+// ----------------- End Invitation Handlers -----------------
+
+// ----------------- API Key Handlers -----------------
+pub async fn create_api_key(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+    Json(ApiKeyWrapper { api_key: request }): Json<ApiKeyWrapper<CreateApiKeyRequest>>,
+) -> JsonResult<ApiKeyWrapper<ApiKeyResponse>> {
+    if let Some(user) = maybe_user {
+        user.ensure_session()?;
+        let name = request.name.trim();
+        if name.is_empty() {
+            return Err(RequestError::RunTimeError("API key name is required"));
+        }
+        let mut scopes = request.scopes;
+        scopes.sort();
+        scopes.dedup();
+        if scopes.is_empty() {
+            return Err(RequestError::RunTimeError("At least one scope is required"));
+        }
+
+        let key = generate_api_key();
+        let api_key = create_api_key_in_db(
+            &pool,
+            user.id,
+            name,
+            &hash_token(&key),
+            &scopes_to_string(&scopes),
+        )
+        .await?;
+        return Ok(Json(ApiKeyWrapper {
+            api_key: ApiKeyResponse::new(api_key).with_key(key),
+        }));
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+
+pub async fn list_api_keys(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+) -> JsonResult<MultipleApiKeysWrapper> {
+    if let Some(user) = maybe_user {
+        user.ensure_session()?;
+        let api_keys = list_api_keys_in_db(&pool, user.id)
+            .await?
+            .into_iter()
+            .map(ApiKeyResponse::new)
+            .collect();
+        return Ok(Json(MultipleApiKeysWrapper { api_keys }));
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+
+pub async fn revoke_api_key(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+    Path(id): Path<i64>,
+) -> Result<(), RequestError> {
+    if let Some(user) = maybe_user {
+        user.ensure_session()?;
+        return revoke_api_key_in_db(&pool, user.id, id).await;
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+// ----------------- End API Key Handlers -----------------
+
 // ----------------- Profile Handlers -----------------
 pub async fn get_profile(
     Extension(pool): Extension<Arc<SqlitePool>>,
@@ -143,6 +1099,7 @@ pub async fn follow_profile(
     Path(username): Path<String>,
 ) -> JsonResult<ProfileJson> {
     if let Some(user) = maybe_user {
+        user.ensure_scope(ApiKeyScope::ProfileWrite)?;
         let profile = follow_user_in_db(&pool, user.id, &username)
             .await
             .map_err(|e| {
@@ -165,108 +1122,255 @@ pub async fn unfollow_profile(
     Path(username): Path<String>,
 ) -> JsonResult<ProfileJson> {
     if let Some(user) = user {
+        user.ensure_scope(ApiKeyScope::ProfileWrite)?;
         let profile = unfollow_user_in_db(&pool, user.id, &username).await?;
         let result = ProfileResponse::new(profile, false);
         return Ok(Json(ProfileWrapper { profile: result }));
     }
     Err(RequestError::NotAuthorized("Need to be authorized"))
 }
+
+pub async fn set_user_role(
+    RequireRole { user, .. }: RequireRole<roles::Admin>,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    Path(username): Path<String>,
+    Json(UserWrapper { user: request }): Json<UserWrapper<UpdateRoleRequest>>,
+) -> Result<(), RequestError> {
+    user.ensure_session()?;
+    //? Stops the last admin from accidentally locking everyone out of role management
+    if let Some(current_user) = get_user_by_id(&pool, user.id).await? {
+        if current_user.username == username {
+            return Err(RequestError::RunTimeError(
+                "Admins cannot change their own role",
+            ));
+        }
+    }
+    set_user_role_in_db(&pool, &username, request.role).await
+}
+
+/// Lets an admin act as another user to reproduce a problem they reported. The token can't be
+/// refreshed, and every request made with it is written to the impersonation audit log.
+pub async fn impersonate_user(
+    RequireRole { user, .. }: RequireRole<roles::Admin>,
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    client: ClientInfo,
+    Path(username): Path<String>,
+) -> JsonResult<UserJson> {
+    user.ensure_session()?;
+    let target = match get_user_by_username(&pool, &username).await? {
+        Some(target) => target,
+        None => return Err(RequestError::NotFound("User not found")),
+    };
+    ensure_can_impersonate(user.id, target.id, target.role)?;
+    let session_id = create_impersonation_session_in_db(
+        &pool,
+        target.id,
+        user.id,
+        client.user_agent.as_deref(),
+        client.ip_address.as_deref(),
+    )
+    .await?;
+    let token = get_impersonation_token(target.id, session_id, user.id)
+        .map_err(|_| RequestError::ServerError)?;
+    let result = UserResponse::new(target, token);
+    Ok(Json(UserWrapper::wrap_with_user_data(result)))
+}
 // ----------------- End Profile Handlers -----------------
 
 // ----------------- Article Handlers -----------------
 
+fn parse_article_query(
+    params: Result<Query<ArticleQueryParams>, QueryRejection>,
+) -> Result<ArticleQueryParams, RequestError> {
+    match params {
+        Ok(Query(params)) => Ok(params),
+        Err(_) => Err(RequestError::RunTimeError("Could not parse query params")),
+    }
+}
+
+async fn paged_articles(
+    pool: &SqlitePool,
+    uri: &Uri,
+    paging: &Paging<ArticlePosition>,
+    page: ArticlePage,
+    article_count: i64,
+    format: &BodyFormatQueryParams,
+) -> PagedJsonResult<MultipleArticlesWrapper> {
+    let next_cursor = paging.next_cursor(&page)?;
+    let headers = paging.headers(uri, Some(article_count), next_cursor.as_deref());
+    let articles = article_responses(pool, page.items, format).await?;
+    Ok((
+        headers,
+        Json(MultipleArticlesWrapper {
+            articles,
+            article_count,
+            next_cursor,
+        }),
+    ))
+}
+
 pub async fn list_articles(
     Extension(pool): Extension<Arc<SqlitePool>>,
     maybe_user: MaybeUser,
-    Query(params): Query<HashMap<String, String>>,
-) -> JsonResult<MultipleArticlesWrapper> {
-    let data: ArticleQueryParams = serde_json::from_value(serde_json::json!(params))
-        .map_err(|_| RequestError::RunTimeError("Could not parse query params"))?;
-    let articles = list_all_articles(&pool, maybe_user.get_id(), data).await?;
-
-    let articles = articles
-        .into_iter()
-        .map(ArticleResponse::new)
-        .collect::<Vec<ArticleResponse>>();
-    let article_count = articles.len();
-
-    Ok(Json(MultipleArticlesWrapper {
-        articles,
-        article_count,
-    }))
+    uri: Uri,
+    params: Result<Query<ArticleQueryParams>, QueryRejection>,
+    Query(format): Query<BodyFormatQueryParams>,
+) -> PagedJsonResult<MultipleArticlesWrapper> {
+    let data = parse_article_query(params)?;
+    let paging = Paging::new("articles", data.limit, data.offset, data.cursor.as_deref())?;
+    let (page, article_count) =
+        list_all_articles(&pool, maybe_user.get_id(), data, paging.after).await?;
+    paged_articles(&pool, &uri, &paging, page, article_count, &format).await
 }
 
+/// Articles that were renamed are still reachable at their old slugs, which redirect to the
+/// current one.
 pub async fn get_article(
     Extension(pool): Extension<Arc<SqlitePool>>,
     maybe_user: MaybeUser,
     Path(slug): Path<String>,
-) -> JsonResult<ArticleJson> {
-    let article = get_article_by_slug_in_db(&pool, &slug, maybe_user.get_id()).await?;
-    let article = match article {
-        Some(article) => article,
-        None => {
-            return Err(RequestError::NotFound("Article not found"));
+    Query(format): Query<BodyFormatQueryParams>,
+) -> Result<Response, RequestError> {
+    let user = maybe_user.0.as_ref();
+    match get_visible_article(&pool, &slug, user).await {
+        Ok(article) => {
+            let article = article_response(&pool, article, &format).await?;
+            Ok(Json(ArticleWrapper { article }).into_response())
         }
-    };
-    let article = ArticleResponse::new(article);
-    Ok(Json(ArticleWrapper { article }))
+        Err(RequestError::NotFound(message)) => {
+            let current_slug = match get_renamed_article_slug_in_db(&pool, &slug).await? {
+                Some(current_slug) => current_slug,
+                None => return Err(RequestError::NotFound(message)),
+            };
+            //? Don't reveal that a draft was renamed to someone who can't see it
+            get_visible_article(&pool, &current_slug, user).await?;
+            Ok(Redirect::permanent(&format!("/articles/{}", current_slug)).into_response())
+        }
+        Err(e) => Err(e),
+    }
 }
 
 pub async fn create_article(
-    MaybeUser(maybe_user): MaybeUser,
+    RequireRole { user, .. }: RequireRole<roles::Author>,
     Extension(pool): Extension<Arc<SqlitePool>>,
     Json(ArticleWrapper { article }): Json<ArticleWrapper<CreateArticleRequest>>,
 ) -> JsonResult<ArticleJson> {
-    if let Some(user) = maybe_user {
-        let article = create_article_in_db(&pool, user.id, article).await?;
-        let article = ArticleResponse::new(article);
-        return Ok(Json(ArticleWrapper { article }));
+    user.ensure_scope(ApiKeyScope::ArticlesWrite)?;
+    ensure_email_verified(&pool, user.id).await?;
+    let is_scheduled = article.publish_at.is_some();
+    if let Some(publish_at) = article.publish_at {
+        if publish_at <= Utc::now().naive_utc() {
+            return Err(RequestError::RunTimeError(
+                "publishAt must be in the future",
+            ));
+        }
+        if matches!(
+            article.status,
+            Some(ArticleStatus::Draft | ArticleStatus::Unlisted)
+        ) {
+            return Err(RequestError::RunTimeError(
+                "Only articles that will be published can be scheduled",
+            ));
+        }
     }
-    Err(RequestError::NotAuthorized("Need to be authorized"))
+    let article = create_article_in_db(&pool, user.id, article).await?;
+    if is_scheduled {
+        notify_article_scheduled();
+    }
+    let article = ArticleResponse::new(article);
+    Ok(Json(ArticleWrapper { article }))
 }
 
 pub async fn delete_article(
     Extension(pool): Extension<Arc<SqlitePool>>,
-    maybe_user: MaybeUser,
+    MaybeUser(maybe_user): MaybeUser,
     Path(slug): Path<String>,
 ) -> Result<(), RequestError> {
-    if let Some(id) = maybe_user.get_id() {
-        delete_article_in_db(&pool, id, &slug).await?;
+    if let Some(user) = maybe_user {
+        user.ensure_scope(ApiKeyScope::ArticlesWrite)?;
+        let article = get_visible_article(&pool, &slug, Some(&user)).await?;
+        ensure_can_modify(&user, article.author_id)?;
+        delete_article_in_db(&pool, &slug, article.author_id).await?;
         return Ok(());
     }
     Err(RequestError::NotAuthorized("Need to be authorized"))
 }
 
+/// Full-text search over titles, descriptions, bodies and tags, best match first. Can be
+/// narrowed down with `tag` and `author`, sorted and asked for `bodyHtml` like `list_articles`.
+pub async fn search_articles(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    maybe_user: MaybeUser,
+    uri: Uri,
+    params: Result<Query<SearchQueryParams>, QueryRejection>,
+    Query(format): Query<BodyFormatQueryParams>,
+) -> PagedJsonResult<MultipleArticlesWrapper> {
+    let params = match params {
+        Ok(Query(params)) => params,
+        Err(_) => return Err(RequestError::RunTimeError("Could not parse query params")),
+    };
+    let (limit, offset) = (params.limit, params.offset);
+    let (results, article_count) =
+        search_articles_in_db(&pool, maybe_user.get_id(), params).await?;
+    let mut articles = Vec::with_capacity(results.len());
+    for result in results {
+        let body_html = if format.html {
+            Some(article_body_html(&pool, &result.article).await?)
+        } else {
+            None
+        };
+        let article = ArticleResponse::from_search_result(result);
+        articles.push(match body_html {
+            Some(body_html) => article.with_body_html(body_html),
+            None => article,
+        });
+    }
+    let headers = pagination_headers(&uri, limit, offset, article_count);
+
+    Ok((
+        headers,
+        Json(MultipleArticlesWrapper {
+            articles,
+            article_count,
+            next_cursor: None,
+        }),
+    ))
+}
+
 pub async fn get_article_feed(
     Extension(pool): Extension<Arc<SqlitePool>>,
     MaybeUser(maybe_user): MaybeUser,
-    Query(params): Query<HashMap<String, String>>,
-) -> JsonResult<MultipleArticlesWrapper> {
+    uri: Uri,
+    params: Result<Query<ArticleQueryParams>, QueryRejection>,
+    Query(format): Query<BodyFormatQueryParams>,
+) -> PagedJsonResult<MultipleArticlesWrapper> {
     if let Some(user) = maybe_user {
-        let data: ArticleQueryParams = serde_json::from_value(serde_json::json!(params))
-            .map_err(|_| RequestError::RunTimeError("Could not parse query params"))?;
-        let articles = list_articles_feed_in_db(&pool, user.id, data).await?;
-        let articles = articles
-            .into_iter()
-            .map(ArticleResponse::new)
-            .collect::<Vec<ArticleResponse>>();
-        let article_count = articles.len();
-        return Ok(Json(MultipleArticlesWrapper {
-            articles,
-            article_count,
-        }));
+        user.ensure_scope(ApiKeyScope::ProfileRead)?;
+        let data = parse_article_query(params)?;
+        let paging = Paging::new("feed", data.limit, data.offset, data.cursor.as_deref())?;
+        let (page, article_count) =
+            list_articles_feed_in_db(&pool, user.id, data, paging.after).await?;
+        return paged_articles(&pool, &uri, &paging, page, article_count, &format).await;
     }
     Err(RequestError::NotAuthorized("Need to be authorized"))
 }
 
 pub async fn update_article(
     Extension(pool): Extension<Arc<SqlitePool>>,
-    maybe_user: MaybeUser,
+    MaybeUser(maybe_user): MaybeUser,
     Path(slug): Path<String>,
     Json(ArticleWrapper { article }): Json<ArticleWrapper<UpdateArticleRequest>>,
 ) -> JsonResult<ArticleJson> {
-    if let Some(id) = maybe_user.get_id() {
-        let article = update_article_in_db(&pool, id, &slug, article).await?;
+    if let Some(user) = maybe_user {
+        user.ensure_scope(ApiKeyScope::ArticlesWrite)?;
+        let author_id = get_visible_article(&pool, &slug, Some(&user))
+            .await?
+            .author_id;
+        ensure_can_modify(&user, author_id)?;
+        if article.status == Some(ArticleStatus::Published) {
+            ensure_email_verified(&pool, user.id).await?;
+        }
+        let article = update_article_in_db(&pool, user.id, &slug, author_id, article).await?;
         let article = ArticleResponse::new(article);
         return Ok(Json(ArticleWrapper { article }));
     }
@@ -279,6 +1383,8 @@ pub async fn favourite_article(
     Extension(pool): Extension<Arc<SqlitePool>>,
 ) -> Result<(), RequestError> {
     if let Some(user) = maybe_user {
+        user.ensure_scope(ApiKeyScope::ArticlesWrite)?;
+        get_visible_article(&pool, &slug, Some(&user)).await?;
         favourite_article_in_db(&pool, &slug, user.id).await?;
         return Ok(());
     }
@@ -291,22 +1397,167 @@ pub async fn unfavourite_article(
     Extension(pool): Extension<Arc<SqlitePool>>,
 ) -> Result<(), RequestError> {
     if let Some(user) = maybe_user {
+        user.ensure_scope(ApiKeyScope::ArticlesWrite)?;
         unfavourite_article_in_db(&pool, user.id, &slug).await?;
         return Ok(());
     }
     Err(RequestError::NotAuthorized("Need to be authorized"))
 }
 
+/// Makes the article visible to everyone and lists it again.
+pub async fn publish_article(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+    Path(slug): Path<String>,
+) -> JsonResult<ArticleJson> {
+    set_article_status(&pool, maybe_user, &slug, ArticleStatus::Published).await
+}
+
+/// Turns the article back into a draft only its author can see.
+pub async fn unpublish_article(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+    Path(slug): Path<String>,
+) -> JsonResult<ArticleJson> {
+    set_article_status(&pool, maybe_user, &slug, ArticleStatus::Draft).await
+}
+
+async fn set_article_status(
+    pool: &SqlitePool,
+    maybe_user: Option<AuthUser>,
+    slug: &str,
+    status: ArticleStatus,
+) -> JsonResult<ArticleJson> {
+    if let Some(user) = maybe_user {
+        user.ensure_scope(ApiKeyScope::ArticlesWrite)?;
+        let author_id = get_visible_article(pool, slug, Some(&user))
+            .await?
+            .author_id;
+        ensure_can_modify(&user, author_id)?;
+        if status == ArticleStatus::Published {
+            ensure_email_verified(pool, user.id).await?;
+        }
+        set_article_status_in_db(pool, slug, author_id, status).await?;
+        let article = match get_article_by_slug_in_db(pool, slug, Some(user.id)).await? {
+            Some(article) => article,
+            None => return Err(RequestError::NotFound("Article not found")),
+        };
+        let article = ArticleResponse::new(article);
+        return Ok(Json(ArticleWrapper { article }));
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+
+pub async fn list_drafts(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+    uri: Uri,
+    params: Result<Query<ArticleQueryParams>, QueryRejection>,
+    Query(format): Query<BodyFormatQueryParams>,
+) -> PagedJsonResult<MultipleArticlesWrapper> {
+    if let Some(user) = maybe_user {
+        user.ensure_scope(ApiKeyScope::ProfileRead)?;
+        let data = parse_article_query(params)?;
+        let paging = Paging::new("drafts", data.limit, data.offset, data.cursor.as_deref())?;
+        let (page, article_count) = list_drafts_in_db(
+            &pool,
+            user.id,
+            data.sort,
+            data.limit,
+            data.offset,
+            paging.after,
+        )
+        .await?;
+        return paged_articles(&pool, &uri, &paging, page, article_count, &format).await;
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+
+pub async fn list_article_revisions(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+    Path(slug): Path<String>,
+) -> JsonResult<MultipleRevisionsWrapper> {
+    if let Some(user) = maybe_user {
+        let article = get_editable_article(&pool, &slug, &user).await?;
+        let revisions = list_article_revisions_in_db(&pool, article.id)
+            .await?
+            .into_iter()
+            .map(RevisionResponse::summary)
+            .collect();
+        return Ok(Json(MultipleRevisionsWrapper { revisions }));
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+
+pub async fn get_article_revision(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+    Path((slug, number)): Path<(String, i64)>,
+) -> JsonResult<RevisionWrapper> {
+    if let Some(user) = maybe_user {
+        let article = get_editable_article(&pool, &slug, &user).await?;
+        let revision = get_article_revision_in_db(&pool, article.id, number).await?;
+        let revision = RevisionResponse::new(revision);
+        return Ok(Json(RevisionWrapper { revision }));
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+
+/// What changed between `?against=` (the revision before by default) and revision `number`.
+pub async fn diff_article_revisions(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+    Path((slug, number)): Path<(String, i64)>,
+    Query(params): Query<RevisionDiffQueryParams>,
+) -> JsonResult<RevisionDiffWrapper> {
+    if let Some(user) = maybe_user {
+        let article = get_editable_article(&pool, &slug, &user).await?;
+        let to = get_article_revision_in_db(&pool, article.id, number).await?;
+        let against = params.against.unwrap_or(number - 1);
+        let from = get_article_revision_in_db(&pool, article.id, against).await?;
+        let diff = RevisionDiffResponse::new(&from, &to);
+        return Ok(Json(RevisionDiffWrapper { diff }));
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+
+/// Brings back the content of an old revision as a new revision, leaving the history intact.
+pub async fn restore_article_revision(
+    Extension(pool): Extension<Arc<SqlitePool>>,
+    MaybeUser(maybe_user): MaybeUser,
+    Path((slug, number)): Path<(String, i64)>,
+) -> JsonResult<ArticleJson> {
+    if let Some(user) = maybe_user {
+        let article = get_editable_article(&pool, &slug, &user).await?;
+        let revision = get_article_revision_in_db(&pool, article.id, number).await?;
+        let request = UpdateArticleRequest {
+            title: Some(revision.title),
+            description: Some(revision.description),
+            body: Some(revision.body),
+            status: None,
+            tag_list: None,
+            add_tags: None,
+            remove_tags: None,
+        };
+        let article =
+            update_article_in_db(&pool, user.id, &slug, article.author_id, request).await?;
+        let article = ArticleResponse::new(article);
+        return Ok(Json(ArticleWrapper { article }));
+    }
+    Err(RequestError::NotAuthorized("Need to be authorized"))
+}
+
 // ----------------- End Article Handlers -----------------
 
 // ----------------- Comment Handlers -----------------
 
 pub async fn get_comment(
-    Path(slug): Path<String>,
-    Path(id): Path<i64>,
+    Path((slug, id)): Path<(String, i64)>,
     maybe_user: MaybeUser,
     Extension(pool): Extension<Arc<SqlitePool>>,
 ) -> JsonResult<CommentJson> {
+    get_visible_article(&pool, &slug, maybe_user.0.as_ref()).await?;
     let comment = get_comment_for_article_in_db(&pool, id, &slug).await?;
     // This unwrap should be safe
     let (user, following) =
@@ -319,21 +1570,62 @@ pub async fn get_comment(
     Ok(Json(CommentWrapper { comment }))
 }
 
+/// All of the article's comments, or a page of them if a `limit` is given.
 pub async fn get_comments(
     Path(slug): Path<String>,
     maybe_user: MaybeUser,
     Extension(pool): Extension<Arc<SqlitePool>>,
-) -> JsonResult<MultipleCommentsWrapper> {
-    let comments = get_comments_for_article_in_db(&pool, &slug).await?;
-    let mut result = Vec::with_capacity(comments.len());
-    for comment in comments {
+    uri: Uri,
+    params: Result<Query<CommentQueryParams>, QueryRejection>,
+) -> PagedJsonResult<MultipleCommentsWrapper> {
+    let params = match params {
+        Ok(Query(params)) => params,
+        Err(_) => return Err(RequestError::RunTimeError("Could not parse query params")),
+    };
+    get_visible_article(&pool, &slug, maybe_user.0.as_ref()).await?;
+    let limit = params.page_limit();
+    let paging = match limit {
+        Some(limit) => Some(Paging::new(
+            "comments",
+            limit,
+            params.offset,
+            params.cursor.as_deref(),
+        )?),
+        None => None,
+    };
+    let page = get_comments_for_article_in_db(
+        &pool,
+        &slug,
+        limit,
+        params.offset,
+        paging.as_ref().and_then(|paging| paging.after),
+    )
+    .await?;
+    let (headers, next_cursor) = match &paging {
+        Some(paging) => {
+            let next_cursor = paging.next_cursor(&page)?;
+            (
+                paging.headers(&uri, None, next_cursor.as_deref()),
+                next_cursor,
+            )
+        }
+        None => (HeaderMap::new(), None),
+    };
+    let mut result = Vec::with_capacity(page.items.len());
+    for comment in page.items {
         let (user, following) =
             get_profile_by_id_in_db(&pool, maybe_user.get_id(), comment.author_id).await?;
         let profile_response = ProfileResponse::new(user, following);
         let comment = CommentResponse::new(comment, profile_response);
         result.push(comment);
     }
-    Ok(Json(MultipleCommentsWrapper { comments: result }))
+    Ok((
+        headers,
+        Json(MultipleCommentsWrapper {
+            comments: result,
+            next_cursor,
+        }),
+    ))
 }
 
 pub async fn add_comment(
@@ -343,6 +1635,9 @@ pub async fn add_comment(
     Json(CommentWrapper { comment }): Json<CommentWrapper<CommentRequest>>,
 ) -> JsonResult<CommentJson> {
     if let Some(user) = maybe_user {
+        user.ensure_scope(ApiKeyScope::CommentsWrite)?;
+        ensure_email_verified(&pool, user.id).await?;
+        get_visible_article(&pool, &slug, Some(&user)).await?;
         let comment = add_comments_to_article_in_db(&pool, user.id, &slug, comment).await?;
         let user = match get_user_by_id(&pool, comment.author_id).await? {
             Some(user) => user,
@@ -358,13 +1653,15 @@ pub async fn add_comment(
 }
 
 pub async fn delete_comment(
-    Path(slug): Path<String>,
-    Path(id): Path<i64>,
+    Path((slug, id)): Path<(String, i64)>,
     MaybeUser(maybe_user): MaybeUser,
     Extension(pool): Extension<Arc<SqlitePool>>,
 ) -> Result<(), RequestError> {
     if let Some(user) = maybe_user {
-        delete_comment_in_db(&pool, user.id, id, &slug).await?;
+        user.ensure_scope(ApiKeyScope::CommentsWrite)?;
+        let comment = get_comment_for_article_in_db(&pool, id, &slug).await?;
+        ensure_can_modify(&user, comment.author_id)?;
+        delete_comment_in_db(&pool, id, &slug, comment.author_id).await?;
         return Ok(());
     }
     Err(RequestError::NotAuthorized("Need to be authorized"))
@@ -0,0 +1,76 @@
+use axum::http::request::Parts;
+use sqlx::SqlitePool;
+
+use crate::authentication::get_setting_from_env;
+use crate::db_helpers::{get_user_role_in_db, record_impersonated_request_in_db};
+use crate::errors::RequestError;
+use crate::models::Role;
+
+const IMPERSONATION_TOKEN_EXPIRY_DURATION: i64 = 15 * 60;
+
+/// How long an impersonation token lasts. It can't be refreshed, so this is the whole session.
+pub fn get_impersonation_token_expiry() -> i64 {
+    get_setting_from_env(
+        "IMPERSONATION_TOKEN_EXPIRY_DURATION",
+        IMPERSONATION_TOKEN_EXPIRY_DURATION,
+    )
+}
+
+/// Whether `IMPERSONATION_READ_ONLY` is turned on, which only lets impersonation tokens make
+/// requests that don't change anything.
+pub fn is_impersonation_read_only() -> bool {
+    std::env::var("IMPERSONATION_READ_ONLY")
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false)
+}
+
+/// Fails unless `admin` may act as a user with `role`. Admins can't impersonate each other, so
+/// impersonation never grants more than the admin already has.
+pub fn ensure_can_impersonate(admin_id: i64, user_id: i64, role: Role) -> Result<(), RequestError> {
+    if admin_id == user_id {
+        return Err(RequestError::RunTimeError(
+            "Admins cannot impersonate themselves",
+        ));
+    }
+    if role >= Role::Admin {
+        return Err(RequestError::Forbidden("Admins cannot be impersonated"));
+    }
+    Ok(())
+}
+
+/// Writes a request made under impersonation to the audit log. Refuses it if the admin has lost
+/// their role since, or if it would change something while impersonation is read-only; blocked
+/// requests are still logged.
+pub async fn audit_impersonated_request(
+    pool: &SqlitePool,
+    parts: &Parts,
+    session_id: i64,
+    admin_id: i64,
+    user_id: i64,
+) -> Result<(), RequestError> {
+    if get_user_role_in_db(pool, admin_id).await? != Some(Role::Admin) {
+        return Err(RequestError::NotAuthorized(
+            "Impersonation is no longer allowed",
+        ));
+    }
+    let blocked = is_impersonation_read_only() && !parts.method.is_safe();
+    let path = parts
+        .uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or_else(|| parts.uri.path());
+    record_impersonated_request_in_db(
+        pool,
+        session_id,
+        admin_id,
+        user_id,
+        parts.method.as_str(),
+        path,
+        blocked,
+    )
+    .await?;
+    if blocked {
+        return Err(RequestError::Forbidden("Impersonation is read-only"));
+    }
+    Ok(())
+}
@@ -0,0 +1,261 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, OctetKeyPairParameters,
+        OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use pkcs1::der::Decode;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use spki::{ObjectIdentifier, SubjectPublicKeyInfoRef};
+
+use crate::errors::RequestError;
+
+const RSA_ENCRYPTION_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+const ED25519_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.101.112");
+
+const JWT_ISSUER: &str = "conduit";
+
+static JWT_KEYS: OnceLock<JwtKeys> = OnceLock::new();
+
+/// What a token is for. Every kind is signed with the same keys but has its own audience and
+/// type, so a token handed out for one purpose is refused everywhere else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Access,
+    TwoFactorChallenge,
+    Cursor,
+}
+
+impl TokenKind {
+    fn audience(self) -> &'static str {
+        match self {
+            TokenKind::Access => "conduit:api",
+            TokenKind::TwoFactorChallenge => "conduit:login",
+            TokenKind::Cursor => "conduit:pagination",
+        }
+    }
+
+    fn typ(self) -> &'static str {
+        match self {
+            TokenKind::Access => "access",
+            TokenKind::TwoFactorChallenge => "two_factor_challenge",
+            TokenKind::Cursor => "cursor",
+        }
+    }
+}
+
+/// The claims every token carries, around the ones specific to its kind.
+#[derive(Debug, Serialize, Deserialize)]
+struct TokenClaims<T> {
+    iss: String,
+    aud: String,
+    typ: String,
+    #[serde(flatten)]
+    claims: T,
+}
+
+struct VerificationKey {
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+/// Every key used to sign and verify our JWTs, loaded once at startup.
+///
+/// With `JWT_ALGORITHM=HS256` (the default) tokens are signed with `JWT_SECRET` and there is
+/// nothing to publish. With `RS256` or `EdDSA` tokens carry a `kid` header naming the key that
+/// signed them, and older public keys listed in `JWT_VERIFICATION_KEYS` keep being accepted so
+/// keys can be rotated without logging everyone out.
+pub struct JwtKeys {
+    issuer: String,
+    algorithm: Algorithm,
+    signing_key_id: Option<String>,
+    encoding_key: EncodingKey,
+    verification_keys: HashMap<Option<String>, VerificationKey>,
+    jwks: JwkSet,
+}
+
+fn read_key_file(path: &str) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("Failed to read key file {}", path))
+}
+
+/// Parses a PEM encoded public key into the algorithm it is used with, a key to verify
+/// signatures and its public JWK representation.
+fn load_public_key(key_id: &str, pem: &[u8]) -> Result<(VerificationKey, Jwk)> {
+    let (_, der) = spki::der::pem::decode_vec(pem)
+        .map_err(|e| anyhow::anyhow!("Public key must be PEM encoded: {}", e))?;
+    let spki = SubjectPublicKeyInfoRef::try_from(der.as_slice())
+        .context("Public key must be a SubjectPublicKeyInfo")?;
+    let public_key = spki.subject_public_key.raw_bytes();
+
+    let (algorithm, key, parameters) = if spki.algorithm.oid == RSA_ENCRYPTION_OID {
+        let rsa_key =
+            pkcs1::RsaPublicKey::from_der(public_key).context("Invalid RSA public key")?;
+        let n = URL_SAFE_NO_PAD.encode(rsa_key.modulus.as_bytes());
+        let e = URL_SAFE_NO_PAD.encode(rsa_key.public_exponent.as_bytes());
+        let key = DecodingKey::from_rsa_components(&n, &e)?;
+        let parameters = AlgorithmParameters::RSA(RSAKeyParameters {
+            key_type: RSAKeyType::RSA,
+            n,
+            e,
+        });
+        (Algorithm::RS256, key, parameters)
+    } else if spki.algorithm.oid == ED25519_OID {
+        let key = DecodingKey::from_ed_der(public_key);
+        let parameters = AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+            key_type: OctetKeyPairType::OctetKeyPair,
+            curve: EllipticCurve::Ed25519,
+            x: URL_SAFE_NO_PAD.encode(public_key),
+        });
+        (Algorithm::EdDSA, key, parameters)
+    } else {
+        anyhow::bail!("Key {} is neither an RSA nor an Ed25519 key", key_id);
+    };
+
+    let jwk = Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            algorithm: Some(algorithm),
+            key_id: Some(key_id.to_owned()),
+            ..Default::default()
+        },
+        algorithm: parameters,
+    };
+    Ok((VerificationKey { algorithm, key }, jwk))
+}
+
+impl JwtKeys {
+    pub fn from_env() -> Result<Self> {
+        let issuer = std::env::var("JWT_ISSUER").unwrap_or_else(|_| JWT_ISSUER.to_owned());
+        let algorithm = std::env::var("JWT_ALGORITHM").unwrap_or_else(|_| "HS256".to_owned());
+        match algorithm.as_str() {
+            "HS256" => {
+                let secret = std::env::var("JWT_SECRET").context("JWT_SECRET must be set")?;
+                let verification_key = VerificationKey {
+                    algorithm: Algorithm::HS256,
+                    key: DecodingKey::from_secret(secret.as_bytes()),
+                };
+                Ok(Self {
+                    issuer,
+                    algorithm: Algorithm::HS256,
+                    signing_key_id: None,
+                    encoding_key: EncodingKey::from_secret(secret.as_bytes()),
+                    verification_keys: HashMap::from([(None, verification_key)]),
+                    jwks: JwkSet { keys: Vec::new() },
+                })
+            }
+            "RS256" | "EdDSA" => {
+                let algorithm = if algorithm == "RS256" {
+                    Algorithm::RS256
+                } else {
+                    Algorithm::EdDSA
+                };
+                let signing_key_id = std::env::var("JWT_SIGNING_KEY_ID")
+                    .context("JWT_SIGNING_KEY_ID must be set")?;
+                let private_key = read_key_file(
+                    &std::env::var("JWT_PRIVATE_KEY_PATH")
+                        .context("JWT_PRIVATE_KEY_PATH must be set")?,
+                )?;
+                let encoding_key = match algorithm {
+                    Algorithm::RS256 => EncodingKey::from_rsa_pem(&private_key)?,
+                    _ => EncodingKey::from_ed_pem(&private_key)?,
+                };
+
+                //? The signing key's public half comes first, followed by retired keys that
+                //? tokens may still have been signed with
+                let mut public_keys = vec![(
+                    signing_key_id.clone(),
+                    std::env::var("JWT_PUBLIC_KEY_PATH")
+                        .context("JWT_PUBLIC_KEY_PATH must be set")?,
+                )];
+                if let Ok(keys) = std::env::var("JWT_VERIFICATION_KEYS") {
+                    for entry in keys.split(',').filter(|entry| !entry.trim().is_empty()) {
+                        let (key_id, path) = entry.trim().split_once('=').with_context(|| {
+                            format!("JWT_VERIFICATION_KEYS entry {} must be kid=path", entry)
+                        })?;
+                        public_keys.push((key_id.to_owned(), path.to_owned()));
+                    }
+                }
+
+                let mut verification_keys = HashMap::new();
+                let mut jwks = JwkSet { keys: Vec::new() };
+                for (key_id, path) in public_keys {
+                    let (verification_key, jwk) = load_public_key(&key_id, &read_key_file(&path)?)?;
+                    if key_id == signing_key_id && verification_key.algorithm != algorithm {
+                        anyhow::bail!("JWT_PUBLIC_KEY_PATH does not match JWT_ALGORITHM");
+                    }
+                    verification_keys.insert(Some(key_id), verification_key);
+                    jwks.keys.push(jwk);
+                }
+
+                Ok(Self {
+                    issuer,
+                    algorithm,
+                    signing_key_id: Some(signing_key_id),
+                    encoding_key,
+                    verification_keys,
+                    jwks,
+                })
+            }
+            other => anyhow::bail!("Unsupported JWT_ALGORITHM {}", other),
+        }
+    }
+
+    pub fn encode<T: Serialize>(&self, kind: TokenKind, claims: &T) -> Result<String> {
+        let mut header = Header::new(self.algorithm);
+        header.kid = self.signing_key_id.clone();
+        let claims = TokenClaims {
+            iss: self.issuer.clone(),
+            aud: kind.audience().to_owned(),
+            typ: kind.typ().to_owned(),
+            claims,
+        };
+        jsonwebtoken::encode(&header, &claims, &self.encoding_key).context("Failed to sign token")
+    }
+
+    /// Verifies a token made by `encode` for the same `kind` and returns its claims.
+    pub fn decode<T: DeserializeOwned>(
+        &self,
+        kind: TokenKind,
+        token: &str,
+    ) -> Result<T, RequestError> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|_| RequestError::NotAuthorized("Invalid Token"))?;
+        let verification_key = self
+            .verification_keys
+            .get(&header.kid)
+            .ok_or(RequestError::NotAuthorized("Invalid Token"))?;
+        let mut validation = Validation::new(verification_key.algorithm);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[kind.audience()]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+        let token_data =
+            jsonwebtoken::decode::<TokenClaims<T>>(token, &verification_key.key, &validation)
+                .map_err(|_| RequestError::NotAuthorized("Invalid Token"))?;
+        if token_data.claims.typ != kind.typ() {
+            return Err(RequestError::NotAuthorized("Invalid Token"));
+        }
+        Ok(token_data.claims.claims)
+    }
+
+    /// The public keys other services can use to verify our tokens.
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+}
+
+/// Loads the keys from the environment. Called once when the server starts.
+pub fn init_jwt_keys() -> Result<()> {
+    let keys = JwtKeys::from_env()?;
+    //? Already initialised (e.g. a second server in the same process), keep the first keys
+    let _ = JWT_KEYS.set(keys);
+    Ok(())
+}
+
+pub fn jwt_keys() -> Result<&'static JwtKeys> {
+    JWT_KEYS.get().context("JWT keys have not been initialised")
+}
@@ -1,32 +1,83 @@
+mod account_deletion;
+mod api_keys;
+mod article_scheduler;
 mod authentication;
+mod data_export;
 mod data_formats;
 mod db_helpers;
 mod errors;
 mod handlers;
+mod impersonation;
+mod jwt_keys;
+mod login_throttle;
+pub mod mailer;
+mod markdown;
 mod models;
+mod oidc;
+mod pagination;
+mod passwords;
+mod policy;
+mod registration;
+mod two_factor;
 
 use anyhow::Context;
 pub use anyhow::Result;
 use axum::http::StatusCode;
 use axum::{routing::*, Extension, Json, Router};
 use handlers::*;
-use sqlx::{migrate::MigrateDatabase, Sqlite, SqlitePool};
+use mailer::Mailer;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection};
+use sqlx::{migrate::MigrateDatabase, Connection, Sqlite, SqlitePool};
 use std::fmt::Write;
 use std::{
     net::{SocketAddr, TcpListener},
+    str::FromStr,
     sync::Arc,
 };
 pub type JsonResponse<T> = (StatusCode, Json<T>);
 
+const MAX_SLUG_LENGTH: usize = 80;
+
+/// Turns a title into the part of an article's URL: transliterated to ASCII, lowercased, and
+/// with every run of anything but letters and digits turned into a single `-`. It may still
+/// clash with another article's, see `db_helpers::create_article_in_db`.
 pub fn slugify(title: &str) -> String {
-    title.to_lowercase().replace(' ', "-")
+    let mut slug = String::with_capacity(title.len());
+    for c in deunicode::deunicode(title).chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if c == '\'' {
+            //? "Don't" reads better as "dont" than "don-t"
+            continue;
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    //? Only ASCII is left, so this can't split a character
+    slug.truncate(MAX_SLUG_LENGTH);
+    match slug.trim_end_matches('-') {
+        "" => "article".to_owned(),
+        slug => slug.to_owned(),
+    }
 }
 
-pub async fn run_app(app: Router, address: SocketAddr) -> Result<()> {
+pub async fn run_app(app: Router, address: SocketAddr, mailer: Arc<dyn Mailer>) -> Result<()> {
+    jwt_keys::init_jwt_keys()?;
+    oidc::init_oidc_providers()?;
+    passwords::init_password_settings()?;
     let db = init_db().await?;
-    let app = app.layer(Extension(Arc::new(db)));
+    policy::bootstrap_admin(&db)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to set up the first admin: {:?}", e))?;
+    data_export::resume_pending_data_exports(&db)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to resume data exports: {:?}", e))?;
+    account_deletion::spawn_account_purge(db.clone());
+    data_export::spawn_data_export_cleanup(db.clone());
+    article_scheduler::spawn_article_scheduler(db.clone());
+    let app = app.layer(Extension(Arc::new(db))).layer(Extension(mailer));
     axum::Server::bind(&address)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await?;
     Ok(())
 }
@@ -42,14 +93,25 @@ pub async fn init_db() -> Result<SqlitePool> {
     } else {
         println!("Database already exists");
     }
-    let pool = SqlitePool::connect(&db_url).await?;
     println!("Running Migrations");
-    // Run migrations
+    //? SQLite can only change a table by building a new one and dropping the old, which with
+    //? foreign keys on would also delete every row that references it. So migrations run with
+    //? them off, and rows left pointing at nothing are reported afterwards.
+    let options = SqliteConnectOptions::from_str(&db_url)?.foreign_keys(false);
+    let mut connection = SqliteConnection::connect_with(&options).await?;
     sqlx::migrate!("./migrations")
-        .run(&pool)
+        .run(&mut connection)
         .await
         .context("Failed to run migrations")?;
+    let dangling = sqlx::query("PRAGMA foreign_key_check")
+        .fetch_all(&mut connection)
+        .await?;
+    if !dangling.is_empty() {
+        eprintln!("{} rows reference rows that don't exist", dangling.len());
+    }
+    connection.close().await?;
     println!("Migrations completed");
+    let pool = SqlitePool::connect(&db_url).await?;
     Ok(pool)
 }
 
@@ -76,16 +138,52 @@ pub fn get_random_free_port() -> (u16, SocketAddr) {
 pub fn make_router() -> Router {
     Router::new()
         .route("/check_health", get(alive))
+        .route("/.well-known/jwks.json", get(get_jwks))
         .route("/users/login", post(login_user))
+        .route("/users/login/2fa", post(login_user_two_factor))
+        .route("/users/oidc/:provider/authorize", post(start_oidc_login))
+        .route("/users/oidc/:provider/callback", post(finish_oidc_login))
         .route("/users", post(register_user))
-        .route("/user", get(get_current_user).put(update_user))
+        .route("/users/logout", post(logout_user))
+        .route("/users/token/refresh", post(refresh_token))
+        .route("/users/password/forgot", post(forgot_password))
+        .route("/users/password/reset", post(reset_password))
+        .route("/users/email/verify", post(verify_email))
+        .route(
+            "/user",
+            get(get_current_user).put(update_user).delete(delete_user),
+        )
+        .route("/user/email/verify", post(resend_verification_email))
+        .route(
+            "/user/2fa",
+            post(enable_two_factor).delete(disable_two_factor),
+        )
+        .route("/user/2fa/confirm", post(confirm_two_factor))
+        .route(
+            "/user/invitations",
+            get(list_invitations).post(create_invitation),
+        )
+        .route("/user/invitations/:id", delete(revoke_invitation))
+        .route("/user/api-keys", get(list_api_keys).post(create_api_key))
+        .route("/user/api-keys/:id", delete(revoke_api_key))
+        .route(
+            "/user/export",
+            get(get_data_export).post(request_data_export),
+        )
+        .route("/exports/:token", get(download_data_export))
+        .route("/user/drafts", get(list_drafts))
+        .route("/user/sessions", get(list_sessions))
+        .route("/user/sessions/:id", delete(revoke_session))
         .route("/profiles/:username", get(get_profile))
         .route(
             "/profiles/:username/follow",
             post(follow_profile).delete(unfollow_profile),
         )
+        .route("/profiles/:username/role", put(set_user_role))
+        .route("/profiles/:username/impersonate", post(impersonate_user))
         .route("/articles", get(list_articles).post(create_article))
         .route("/articles/feed", get(get_article_feed))
+        .route("/articles/search", get(search_articles))
         .route(
             "/articles/:slug",
             get(get_article).put(update_article).delete(delete_article),
@@ -98,6 +196,23 @@ pub fn make_router() -> Router {
             "/articles/:slug/comments/:id",
             get(get_comment).delete(delete_comment),
         )
+        .route("/articles/:slug/revisions", get(list_article_revisions))
+        .route(
+            "/articles/:slug/revisions/:number",
+            get(get_article_revision),
+        )
+        .route(
+            "/articles/:slug/revisions/:number/diff",
+            get(diff_article_revisions),
+        )
+        .route(
+            "/articles/:slug/revisions/:number/restore",
+            post(restore_article_revision),
+        )
+        .route(
+            "/articles/:slug/publish",
+            post(publish_article).delete(unpublish_article),
+        )
         .route(
             "/articles/:slug/favorite",
             post(favourite_article).delete(unfavourite_article),
@@ -0,0 +1,75 @@
+use std::net::IpAddr;
+
+use sqlx::SqlitePool;
+
+use crate::authentication::get_setting_from_env;
+use crate::db_helpers::{
+    clear_failed_logins_in_db, is_login_locked_in_db, lock_login_in_db, record_failed_login_in_db,
+};
+use crate::errors::RequestError;
+
+const LOGIN_MAX_FAILED_ATTEMPTS: i64 = 5;
+const LOGIN_FAILURE_WINDOW: i64 = 15 * 60;
+const LOGIN_LOCKOUT_DURATION: i64 = 60;
+const LOGIN_MAX_LOCKOUT_DURATION: i64 = 60 * 60;
+//? The per-IP limit is looser since many users can share an address
+const LOGIN_IP_ATTEMPTS_MULTIPLIER: i64 = 4;
+
+/// The key failed logins for an email are counted under.
+pub fn account_key(email: &str) -> String {
+    format!("account:{}", email.trim().to_lowercase())
+}
+
+fn ip_key(ip: IpAddr) -> String {
+    format!("ip:{}", ip)
+}
+
+/// How long to lock a key out for once it has failed `failures` times in a row. Starts at
+/// `LOGIN_LOCKOUT_DURATION` when the limit is hit and doubles with every failure after that.
+fn lockout_duration(failures: i64, max_attempts: i64) -> Option<i64> {
+    if failures < max_attempts {
+        return None;
+    }
+    let base = get_setting_from_env("LOGIN_LOCKOUT_DURATION", LOGIN_LOCKOUT_DURATION);
+    let max = get_setting_from_env("LOGIN_MAX_LOCKOUT_DURATION", LOGIN_MAX_LOCKOUT_DURATION);
+    let exponent = (failures - max_attempts).min(32) as u32;
+    Some(base.saturating_mul(2_i64.saturating_pow(exponent)).min(max))
+}
+
+/// Fails with `TooManyRequests` while either the account or the client address is locked out.
+pub async fn ensure_login_allowed(
+    pool: &SqlitePool,
+    email: &str,
+    ip: IpAddr,
+) -> Result<(), RequestError> {
+    if is_login_locked_in_db(pool, &[account_key(email), ip_key(ip)]).await? {
+        return Err(RequestError::TooManyRequests(
+            "Too many failed login attempts, try again later",
+        ));
+    }
+    Ok(())
+}
+
+pub async fn record_login_failure(
+    pool: &SqlitePool,
+    email: &str,
+    ip: IpAddr,
+) -> Result<(), RequestError> {
+    let window = get_setting_from_env("LOGIN_FAILURE_WINDOW", LOGIN_FAILURE_WINDOW);
+    let max_attempts = get_setting_from_env("LOGIN_MAX_FAILED_ATTEMPTS", LOGIN_MAX_FAILED_ATTEMPTS);
+    let keys = [
+        (account_key(email), max_attempts),
+        (ip_key(ip), max_attempts * LOGIN_IP_ATTEMPTS_MULTIPLIER),
+    ];
+    for (key, max_attempts) in keys {
+        let failures = record_failed_login_in_db(pool, &key, window).await?;
+        if let Some(duration) = lockout_duration(failures, max_attempts) {
+            lock_login_in_db(pool, &key, duration).await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn record_login_success(pool: &SqlitePool, email: &str) -> Result<(), RequestError> {
+    clear_failed_logins_in_db(pool, &account_key(email)).await
+}
@@ -0,0 +1,149 @@
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::{Context, Result};
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Outbound mail transport used for anything the server needs to tell a user out of band.
+#[axum::async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<()>;
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn from_env() -> Result<Self> {
+        let host = std::env::var("SMTP_HOST").context("SMTP_HOST must be set")?;
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host)
+            .context("Failed to create SMTP transport")?;
+        if let Ok(port) = std::env::var("SMTP_PORT") {
+            transport = transport.port(port.parse().context("SMTP_PORT must be a number")?);
+        }
+        if let (Ok(username), Ok(password)) = (
+            std::env::var("SMTP_USERNAME"),
+            std::env::var("SMTP_PASSWORD"),
+        ) {
+            transport = transport.credentials(Credentials::new(username, password));
+        }
+        let from = std::env::var("MAIL_FROM")
+            .context("MAIL_FROM must be set")?
+            .parse()
+            .context("MAIL_FROM must be a valid mailbox")?;
+        Ok(Self {
+            transport: transport.build(),
+            from,
+        })
+    }
+}
+
+#[axum::async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: Email) -> Result<()> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(email.to.parse().context("Invalid recipient address")?)
+            .subject(email.subject)
+            .body(email.body)
+            .context("Failed to build email")?;
+        self.transport
+            .send(message)
+            .await
+            .context("Failed to send email")?;
+        Ok(())
+    }
+}
+
+/// How many emails `OutboxMailer` keeps in memory before dropping the oldest.
+const OUTBOX_CAPACITY: usize = 1000;
+
+/// Keeps the most recently sent emails in memory and, when given a directory, also writes each
+/// one to disk as JSON. Useful for development and for asserting on sent mail without a mail
+/// server.
+#[derive(Default)]
+pub struct OutboxMailer {
+    directory: Option<PathBuf>,
+    outbox: Mutex<VecDeque<Email>>,
+    sent_count: AtomicUsize,
+}
+
+impl OutboxMailer {
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    pub fn with_directory(directory: PathBuf) -> Self {
+        Self {
+            directory: Some(directory),
+            ..Self::default()
+        }
+    }
+
+    pub fn sent_mail(&self) -> Vec<Email> {
+        self.outbox.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+#[axum::async_trait]
+impl Mailer for OutboxMailer {
+    async fn send(&self, email: Email) -> Result<()> {
+        //? Taken once, so two emails sent in the same second can't get the same file name
+        let index = self.sent_count.fetch_add(1, Ordering::Relaxed);
+        if let Some(directory) = &self.directory {
+            tokio::fs::create_dir_all(directory)
+                .await
+                .context("Failed to create outbox directory")?;
+            let path = directory.join(format!(
+                "{}-{}.json",
+                time::OffsetDateTime::now_utc().unix_timestamp(),
+                index
+            ));
+            tokio::fs::write(path, serde_json::to_vec_pretty(&email)?)
+                .await
+                .context("Failed to write email to outbox")?;
+        }
+        let mut outbox = self.outbox.lock().unwrap();
+        if outbox.len() == OUTBOX_CAPACITY {
+            outbox.pop_front();
+        }
+        outbox.push_back(email);
+        Ok(())
+    }
+}
+
+/// Picks the transport from `MAIL_TRANSPORT` (`smtp`, the default, `file` or `memory`).
+pub fn mailer_from_env() -> Result<Arc<dyn Mailer>> {
+    //? Falling back to a transport that delivers nothing would lose every reset and verification
+    //? email without anyone noticing, so SMTP has to be configured unless told otherwise
+    let transport = std::env::var("MAIL_TRANSPORT").unwrap_or_else(|_| "smtp".to_owned());
+    let mailer: Arc<dyn Mailer> = match transport.as_str() {
+        "smtp" => Arc::new(SmtpMailer::from_env()?),
+        "file" => {
+            let directory = std::env::var("MAIL_OUTBOX_DIR").unwrap_or_else(|_| "outbox".into());
+            Arc::new(OutboxMailer::with_directory(directory.into()))
+        }
+        "memory" => Arc::new(OutboxMailer::in_memory()),
+        other => anyhow::bail!("Unknown MAIL_TRANSPORT {}", other),
+    };
+    Ok(mailer)
+}
@@ -2,7 +2,7 @@
 
 use std::net::SocketAddr;
 
-use realworld::{make_router, run_app};
+use realworld::{mailer::mailer_from_env, make_router, run_app};
 
 #[tokio::main]
 async fn main() {
@@ -10,7 +10,14 @@ async fn main() {
     // init_db().await.unwrap();
     let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
     let router = make_router();
-    match run_app(router, addr).await {
+    let mailer = match mailer_from_env() {
+        Ok(mailer) => mailer,
+        Err(error) => {
+            println!("Error: {}", error);
+            return;
+        }
+    };
+    match run_app(router, addr, mailer).await {
         Ok(_) => println!("Server started on {}", addr),
         Err(error) => println!("Error: {}", error),
     }
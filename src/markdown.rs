@@ -0,0 +1,107 @@
+use std::{collections::HashSet, sync::OnceLock};
+
+use ammonia::Builder;
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag, TagEnd};
+use sqlx::SqlitePool;
+
+use crate::db_helpers::{get_cached_body_html_in_db, store_body_html_in_db};
+use crate::errors::RequestError;
+use crate::models::Article;
+use crate::slugify;
+
+/// Bumped whenever a change here would render the same Markdown differently, so HTML cached by
+/// an older version is rendered again.
+const RENDERER_VERSION: i64 = 1;
+
+//? Keeps heading ids from clobbering the client's own, e.g. a heading called "Header"
+const HEADING_ID_PREFIX: &str = "user-content-";
+
+static SANITIZER: OnceLock<Builder<'static>> = OnceLock::new();
+
+/// What rendered Markdown may contain: ammonia's defaults, which already drop scripts, event
+/// handlers and `javascript:` links, plus heading anchors and the language of code blocks.
+fn sanitizer() -> &'static Builder<'static> {
+    SANITIZER.get_or_init(|| {
+        let mut builder = Builder::default();
+        builder.add_tag_attributes("code", &["class"]);
+        for heading in ["h1", "h2", "h3", "h4", "h5", "h6"] {
+            builder.add_tag_attributes(heading, &["id"]);
+        }
+        builder.attribute_filter(|element, attribute, value| match (element, attribute) {
+            ("code", "class") if is_language_class(value) => Some(value.into()),
+            ("code", "class") => None,
+            _ => Some(value.into()),
+        });
+        builder
+    })
+}
+
+/// `language-rust` and the like, which is how fenced code blocks say what they contain so
+/// clients can highlight them.
+fn is_language_class(class: &str) -> bool {
+    match class.strip_prefix("language-") {
+        Some(language) => {
+            !language.is_empty()
+                && language
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '#' | '-' | '_'))
+        }
+        None => false,
+    }
+}
+
+/// Renders CommonMark, with tables, strikethrough and footnotes, into HTML that is safe to put
+/// on a page. Headings get an `id` made from their text so they can be linked to, prefixed with
+/// `user-content-`.
+pub fn render_markdown(markdown: &str) -> String {
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_FOOTNOTES;
+    let mut events: Vec<Event> = Parser::new_ext(markdown, options).collect();
+
+    let mut anchors = HashSet::new();
+    for start in 0..events.len() {
+        if !matches!(events[start], Event::Start(Tag::Heading { .. })) {
+            continue;
+        }
+        let text: String = events[start + 1..]
+            .iter()
+            .take_while(|event| !matches!(event, Event::End(TagEnd::Heading(_))))
+            .filter_map(|event| match event {
+                Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+                _ => None,
+            })
+            .collect();
+        let base = slugify(&text);
+        let mut anchor = base.clone();
+        let mut suffix = 1;
+        while !anchors.insert(anchor.clone()) {
+            suffix += 1;
+            anchor = format!("{}-{}", base, suffix);
+        }
+        if let Event::Start(Tag::Heading { id, .. }) = &mut events[start] {
+            *id = Some(CowStr::from(format!("{}{}", HEADING_ID_PREFIX, anchor)));
+        }
+    }
+
+    let mut unsafe_html = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut unsafe_html, events.into_iter());
+    sanitizer().clean(&unsafe_html).to_string()
+}
+
+/// The article's body as HTML, see `render_markdown`. Each revision is only rendered once per
+/// `RENDERER_VERSION` and the result is kept with it.
+pub async fn article_body_html(
+    pool: &SqlitePool,
+    article: &Article,
+) -> Result<String, RequestError> {
+    match get_cached_body_html_in_db(pool, article.id, &article.body, RENDERER_VERSION).await? {
+        Some((_, Some(html))) => Ok(html),
+        Some((number, None)) => {
+            let html = render_markdown(&article.body);
+            store_body_html_in_db(pool, article.id, number, &html, RENDERER_VERSION).await?;
+            Ok(html)
+        }
+        //? Every edit to the body makes a revision, so this shouldn't happen
+        None => Ok(render_markdown(&article.body)),
+    }
+}
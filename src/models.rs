@@ -1,4 +1,54 @@
 use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+/// What a user is allowed to do, from least to most privileged.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, sqlx::Type,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Role {
+    /// Can comment, favourite and follow but not publish articles
+    Reader,
+    Author,
+    /// Can edit and delete anyone's articles and comments
+    Moderator,
+    Admin,
+}
+
+/// What using an email verification token does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+pub enum VerificationPurpose {
+    /// Confirms the address the account already has
+    Verify,
+    /// Replaces the account's address with the one the token was sent to
+    Change,
+}
+
+/// What happens to a user's articles and comments when their account is purged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum ContentAction {
+    Delete,
+    /// Keep them, attributed to an anonymous placeholder instead of the user
+    #[serde(alias = "anonymize")]
+    Anonymise,
+}
+
+/// Who can see an article.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum ArticleStatus {
+    /// Only the author (and moderators) can see it
+    Draft,
+    #[default]
+    Published,
+    /// Anyone with the link can read it, but it isn't listed anywhere
+    Unlisted,
+}
 
 #[derive(Debug, Clone, sqlx::FromRow)]
 pub struct User {
@@ -9,6 +59,8 @@ pub struct User {
     pub image: Option<String>,
     pub bio: Option<String>,
     pub created_at: NaiveDateTime,
+    pub email_verified_at: Option<NaiveDateTime>,
+    pub role: Role,
 }
 
 #[derive(Debug, Clone, sqlx::FromRow)]
@@ -28,6 +80,46 @@ pub struct Article {
     pub author_image: Option<String>,
     pub author_bio: Option<String>,
     pub following: bool,
+    pub status: ArticleStatus,
+    pub published_at: Option<NaiveDateTime>,
+    /// When a scheduled draft will be published
+    pub publish_at: Option<NaiveDateTime>,
+}
+
+/// An article matching a search. The highlights have the matched words between `\u{2}` and
+/// `\u{3}`, which `SearchHighlightResponse` turns into `<mark>` tags.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ArticleSearchResult {
+    #[sqlx(flatten)]
+    pub article: Article,
+    pub title_highlight: String,
+    /// The part of the body that matched best
+    pub snippet: String,
+}
+
+/// An article from a list, along with how many articles the list has across all its pages and
+/// where the article is in the list's order.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct CountedArticle {
+    #[sqlx(flatten)]
+    pub article: Article,
+    pub total_count: i64,
+    pub sort_key: f64,
+    /// Breaks ties between equal sort keys
+    pub sort_id: i64,
+}
+
+/// What an article looked like after one of its edits.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ArticleRevision {
+    pub number: i64,
+    /// `None` once the account that made the edit is gone
+    pub editor_username: Option<String>,
+    pub title: String,
+    pub description: String,
+    pub body: String,
+    pub changes: String,
+    pub created_at: NaiveDateTime,
 }
 
 #[derive(Debug, Clone, sqlx::FromRow)]
@@ -36,18 +128,72 @@ pub struct Comment {
     pub body: String,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
-    pub article_id: i64,
     pub author_id: i64,
 }
 
 #[derive(Debug, Clone, sqlx::FromRow)]
-pub struct Tag {
+pub struct TwoFactor {
+    pub secret: String,
+    pub enabled_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct RecoveryCode {
+    pub id: i64,
+    pub code_hash: String,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    pub scopes: String,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Session {
     pub id: i64,
-    pub tag: String,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub last_seen_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
 }
 
+/// An authorization request we sent a user to an OpenID Connect provider with.
 #[derive(Debug, Clone, sqlx::FromRow)]
-pub struct ArticleTag {
-    pub article_id: i64,
-    pub tag_id: i64,
+pub struct OidcLoginState {
+    pub code_verifier: String,
+    pub nonce: String,
+    pub invitation_code_hash: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum DataExportStatus {
+    Pending,
+    Ready,
+    Failed,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct DataExport {
+    pub id: i64,
+    pub status: DataExportStatus,
+    pub created_at: NaiveDateTime,
+    pub completed_at: Option<NaiveDateTime>,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Invitation {
+    pub id: i64,
+    pub max_uses: i64,
+    pub uses: i64,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
 }
@@ -0,0 +1,296 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::{OnceCell, RwLock};
+
+use crate::errors::RequestError;
+
+const DEFAULT_SCOPES: &str = "openid email profile";
+
+static OIDC_PROVIDERS: OnceLock<HashMap<String, OidcProvider>> = OnceLock::new();
+
+/// The parts of the issuer's `/.well-known/openid-configuration` we need.
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// The claims we read from a validated ID token.
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+    pub name: Option<String>,
+}
+
+/// An OpenID Connect issuer users can log in with, configured through `OIDC_<NAME>_*` variables.
+///
+/// Discovery and the issuer's keys are fetched the first time they are needed. The keys are
+/// fetched again when a token is signed with one we haven't seen, so the issuer can rotate them.
+pub struct OidcProvider {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+    scopes: String,
+    http: reqwest::Client,
+    metadata: OnceCell<ProviderMetadata>,
+    jwks: RwLock<Option<JwkSet>>,
+}
+
+fn provider_var(name: &str, suffix: &str) -> Result<String> {
+    let key = format!("OIDC_{}_{}", name.to_uppercase(), suffix);
+    std::env::var(&key).with_context(|| format!("{} must be set", key))
+}
+
+fn upstream_error(error: impl std::fmt::Display) -> RequestError {
+    eprintln!("OIDC error: {}", error);
+    RequestError::ServerError
+}
+
+/// The S256 PKCE challenge for a code verifier.
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+impl OidcProvider {
+    fn from_env(name: &str) -> Result<Self> {
+        Ok(Self {
+            issuer: provider_var(name, "ISSUER")?,
+            client_id: provider_var(name, "CLIENT_ID")?,
+            client_secret: provider_var(name, "CLIENT_SECRET").ok(),
+            redirect_uri: provider_var(name, "REDIRECT_URI")?,
+            scopes: provider_var(name, "SCOPES").unwrap_or_else(|_| DEFAULT_SCOPES.to_owned()),
+            http: reqwest::Client::new(),
+            metadata: OnceCell::new(),
+            jwks: RwLock::new(None),
+        })
+    }
+
+    async fn metadata(&self) -> Result<&ProviderMetadata, RequestError> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.issuer.trim_end_matches('/')
+                );
+                let metadata = self
+                    .http
+                    .get(&url)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(upstream_error)?
+                    .json::<ProviderMetadata>()
+                    .await
+                    .map_err(upstream_error)?;
+                //? The spec requires the discovered issuer to match the one we were configured with
+                if metadata.issuer != self.issuer {
+                    return Err(upstream_error(format!(
+                        "{} reports issuer {}",
+                        url, metadata.issuer
+                    )));
+                }
+                Ok(metadata)
+            })
+            .await
+    }
+
+    /// Where to send the user to log in. `state` and `nonce` are checked again in the callback.
+    pub async fn authorization_url(
+        &self,
+        state: &str,
+        nonce: &str,
+        code_verifier: &str,
+    ) -> Result<String, RequestError> {
+        let metadata = self.metadata().await?;
+        let url = url::Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.client_id),
+                ("redirect_uri", &self.redirect_uri),
+                ("scope", &self.scopes),
+                ("state", state),
+                ("nonce", nonce),
+                ("code_challenge", &pkce_challenge(code_verifier)),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(upstream_error)?;
+        Ok(url.into())
+    }
+
+    /// Redeems an authorization code and returns the validated claims of the ID token.
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, RequestError> {
+        let metadata = self.metadata().await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.redirect_uri),
+            ("client_id", &self.client_id),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(client_secret) = &self.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+        let response = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(upstream_error)?;
+        if response.status().is_client_error() {
+            return Err(RequestError::NotAuthorized("Invalid authorization code"));
+        }
+        let token = response
+            .error_for_status()
+            .map_err(upstream_error)?
+            .json::<TokenResponse>()
+            .await
+            .map_err(upstream_error)?;
+
+        let claims = self.verify_id_token(&token.id_token).await?;
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(RequestError::NotAuthorized("Invalid ID token"));
+        }
+        Ok(claims)
+    }
+
+    async fn fetch_jwks(&self) -> Result<JwkSet, RequestError> {
+        let metadata = self.metadata().await?;
+        let jwks = self
+            .http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(upstream_error)?
+            .json::<JwkSet>()
+            .await
+            .map_err(upstream_error)?;
+        *self.jwks.write().await = Some(jwks.clone());
+        Ok(jwks)
+    }
+
+    async fn decoding_key(&self, key_id: Option<&str>) -> Result<DecodingKey, RequestError> {
+        let find_key = |jwks: &JwkSet| match key_id {
+            Some(key_id) => jwks.find(key_id).cloned(),
+            //? Without a kid the issuer has to have exactly one key for us to know which it used
+            None if jwks.keys.len() == 1 => jwks.keys.first().cloned(),
+            None => None,
+        };
+
+        let cached = self.jwks.read().await.as_ref().and_then(find_key);
+        let jwk = match cached {
+            Some(jwk) => jwk,
+            None => find_key(&self.fetch_jwks().await?)
+                .ok_or(RequestError::NotAuthorized("Invalid ID token"))?,
+        };
+        DecodingKey::from_jwk(&jwk).map_err(upstream_error)
+    }
+
+    async fn verify_id_token(&self, id_token: &str) -> Result<IdTokenClaims, RequestError> {
+        let header = jsonwebtoken::decode_header(id_token)
+            .map_err(|_| RequestError::NotAuthorized("Invalid ID token"))?;
+        //? ID tokens have to be signed with the issuer's published keys, never a shared secret
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(RequestError::NotAuthorized("Invalid ID token"));
+        }
+        let key = self.decoding_key(header.kid.as_deref()).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let token_data = jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|e| {
+                eprintln!("Error verifying ID token: {}", e);
+                RequestError::NotAuthorized("Invalid ID token")
+            })?;
+        Ok(token_data.claims)
+    }
+}
+
+/// Turns the claims into something usable as a username: lowercase letters, digits, `-` and
+/// `_`. The caller still has to make it unique.
+pub fn username_from_claims(claims: &IdTokenClaims) -> String {
+    let candidates = [
+        claims.preferred_username.as_deref(),
+        claims.name.as_deref(),
+        claims
+            .email
+            .as_deref()
+            .and_then(|email| email.split('@').next()),
+    ];
+    candidates
+        .into_iter()
+        .flatten()
+        .map(|candidate| {
+            candidate
+                .trim()
+                .to_lowercase()
+                .chars()
+                .map(|c| {
+                    if c.is_whitespace() || c == '.' {
+                        '-'
+                    } else {
+                        c
+                    }
+                })
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+                .collect::<String>()
+        })
+        .find(|username| !username.is_empty())
+        .unwrap_or_else(|| "user".to_owned())
+}
+
+/// Loads the providers listed in `OIDC_PROVIDERS`. Called once when the server starts.
+pub fn init_oidc_providers() -> Result<()> {
+    let mut providers = HashMap::new();
+    if let Ok(names) = std::env::var("OIDC_PROVIDERS") {
+        for name in names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            providers.insert(name.to_lowercase(), OidcProvider::from_env(name)?);
+        }
+    }
+    //? Already initialised (e.g. a second server in the same process), keep the first providers
+    let _ = OIDC_PROVIDERS.set(providers);
+    Ok(())
+}
+
+pub fn oidc_provider(name: &str) -> Result<&'static OidcProvider, RequestError> {
+    OIDC_PROVIDERS
+        .get()
+        .and_then(|providers| providers.get(&name.to_lowercase()))
+        .ok_or(RequestError::NotFound("Unknown login provider"))
+}
@@ -0,0 +1,210 @@
+use axum::http::{header, HeaderMap, HeaderValue, Uri};
+use chrono::NaiveDateTime;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::data_formats::ArticleSort;
+use crate::errors::RequestError;
+use crate::jwt_keys::{jwt_keys, TokenKind};
+
+const CURSOR_EXPIRY_DURATION: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CursorClaim<P> {
+    /// Which list the cursor pages through, so one can't be replayed against another
+    list: String,
+    position: P,
+    exp: i64,
+}
+
+/// Where a keyset page starts: just past the item listed at `at` with id `id`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ListPosition {
+    pub at: NaiveDateTime,
+    pub id: i64,
+}
+
+/// Where a page of articles starts: just past the article with these sort keys, in the order
+/// given by `sort`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ArticlePosition {
+    pub sort: ArticleSort,
+    //? Compared for equality with the key the database computes again, so serde_json's
+    //? `float_roundtrip` has to be on for it to come back from the cursor to the last bit
+    pub sort_key: f64,
+    pub sort_id: i64,
+    /// The time the first page was ranked at, so later pages rank the same way
+    pub as_of: NaiveDateTime,
+}
+
+/// One page of a list, and where the next one starts if there is one.
+pub struct Page<T, P> {
+    pub items: Vec<T>,
+    pub next: Option<P>,
+}
+
+impl<T, P> Page<T, P> {
+    /// Makes a page out of up to `limit + 1` items, the extra one only telling that there are
+    /// more.
+    pub fn from_overfetched(mut items: Vec<T>, limit: u32, position: impl Fn(&T) -> P) -> Self {
+        let limit = limit as usize;
+        let has_more = items.len() > limit;
+        items.truncate(limit);
+        let next = match items.last() {
+            Some(last) if has_more => Some(position(last)),
+            _ => None,
+        };
+        Page { items, next }
+    }
+
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U, P> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            next: self.next,
+        }
+    }
+}
+
+/// How a request wants a list paged: right after the position in its cursor if it came with one,
+/// at its `offset` otherwise.
+pub struct Paging<P> {
+    list: &'static str,
+    pub limit: u32,
+    pub offset: u32,
+    pub after: Option<P>,
+}
+
+impl<P: Serialize + DeserializeOwned> Paging<P> {
+    pub fn new(
+        list: &'static str,
+        limit: u32,
+        offset: u32,
+        cursor: Option<&str>,
+    ) -> Result<Self, RequestError> {
+        let after = cursor
+            .map(|cursor| decode_cursor(list, cursor))
+            .transpose()?;
+        Ok(Paging {
+            list,
+            limit,
+            offset,
+            after,
+        })
+    }
+
+    /// The cursor for the page after `page`, if there is one.
+    pub fn next_cursor<T>(&self, page: &Page<T, P>) -> Result<Option<String>, RequestError> {
+        page.next
+            .as_ref()
+            .map(|position| encode_cursor(self.list, position))
+            .transpose()
+    }
+
+    /// `Link` headers that page the same way the request did. Lists that aren't counted can only
+    /// link onwards with cursors.
+    pub fn headers(
+        &self,
+        uri: &Uri,
+        total_count: Option<i64>,
+        next_cursor: Option<&str>,
+    ) -> HeaderMap {
+        match (&self.after, total_count) {
+            (None, Some(total_count)) => {
+                pagination_headers(uri, self.limit, self.offset, total_count)
+            }
+            _ => cursor_pagination_headers(uri, self.limit, next_cursor),
+        }
+    }
+}
+
+/// An opaque, signed cursor for the page of `list` that starts after `position`.
+fn encode_cursor<P: Serialize>(list: &str, position: &P) -> Result<String, RequestError> {
+    let expiry_date = OffsetDateTime::now_utc() + time::Duration::seconds(CURSOR_EXPIRY_DURATION);
+    let claim = CursorClaim {
+        list: list.to_owned(),
+        position,
+        exp: expiry_date.unix_timestamp(),
+    };
+    jwt_keys()
+        .and_then(|keys| keys.encode(TokenKind::Cursor, &claim))
+        .map_err(|_| RequestError::ServerError)
+}
+
+/// Reads a cursor made by `encode_cursor` for the same `list`.
+fn decode_cursor<P: DeserializeOwned>(list: &str, cursor: &str) -> Result<P, RequestError> {
+    let claim = jwt_keys()
+        .map_err(|_| RequestError::ServerError)?
+        .decode::<CursorClaim<P>>(TokenKind::Cursor, cursor)
+        .map_err(|_| RequestError::RunTimeError("Invalid cursor"))?;
+    if claim.list != list {
+        return Err(RequestError::RunTimeError("Invalid cursor"));
+    }
+    Ok(claim.position)
+}
+
+/// A `Link` header pointing at the first, previous, next and last pages of a list that is paged
+/// with `limit` and `offset`. The rest of the query string is kept as it was.
+pub fn pagination_headers(uri: &Uri, limit: u32, offset: u32, total_count: i64) -> HeaderMap {
+    if limit == 0 {
+        return HeaderMap::new();
+    }
+    let (limit, offset) = (u64::from(limit), u64::from(offset));
+    let total_count = total_count.max(0) as u64;
+    let last = total_count.saturating_sub(1) / limit * limit;
+
+    let mut links = vec![(0, "first")];
+    if offset > 0 {
+        //? From past the end, the previous page is the last one that has anything
+        links.push((offset.saturating_sub(limit).min(last), "prev"));
+    }
+    if offset + limit < total_count {
+        links.push((offset + limit, "next"));
+    }
+    links.push((last, "last"));
+
+    let links = links
+        .into_iter()
+        .map(|(offset, rel)| {
+            let page = [("limit", limit.to_string()), ("offset", offset.to_string())];
+            (page_url(uri, &page), rel)
+        })
+        .collect();
+    link_headers(links)
+}
+
+/// A `Link` header pointing at the first page of a list that is paged with cursors, and at the
+/// next one if there is one.
+fn cursor_pagination_headers(uri: &Uri, limit: u32, next_cursor: Option<&str>) -> HeaderMap {
+    let mut links = vec![(page_url(uri, &[("limit", limit.to_string())]), "first")];
+    if let Some(cursor) = next_cursor {
+        let page = [("limit", limit.to_string()), ("cursor", cursor.to_owned())];
+        links.push((page_url(uri, &page), "next"));
+    }
+    link_headers(links)
+}
+
+fn link_headers(links: Vec<(String, &str)>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let links = links
+        .into_iter()
+        .map(|(url, rel)| format!("<{}>; rel=\"{}\"", url, rel))
+        .collect::<Vec<String>>()
+        .join(", ");
+    if let Ok(links) = HeaderValue::from_str(&links) {
+        headers.insert(header::LINK, links);
+    }
+    headers
+}
+
+fn page_url(uri: &Uri, page: &[(&str, String)]) -> String {
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    for (key, value) in url::form_urlencoded::parse(uri.query().unwrap_or_default().as_bytes()) {
+        if !matches!(key.as_ref(), "limit" | "offset" | "cursor") {
+            query.append_pair(&key, &value);
+        }
+    }
+    for (key, value) in page {
+        query.append_pair(key, value);
+    }
+    format!("{}?{}", uri.path(), query.finish())
+}
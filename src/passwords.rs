@@ -0,0 +1,140 @@
+use std::{collections::HashSet, sync::OnceLock};
+
+use anyhow::{Context, Result};
+use argon2::{Algorithm, Argon2, Params, PasswordHash, Version};
+
+use crate::authentication::get_setting_from_env;
+use crate::errors::RequestError;
+
+const PASSWORD_MIN_LENGTH: usize = 8;
+//? Long enough for any passphrase, short enough that nobody can make us hash megabytes. Longer
+//? passwords are turned away before hashing, at login as well as when they are picked
+const PASSWORD_MAX_LENGTH: usize = 128;
+
+static PASSWORD_SETTINGS: OnceLock<PasswordSettings> = OnceLock::new();
+
+/// How passwords are hashed and which ones users are allowed to pick, loaded once at startup.
+///
+/// The argon2 cost is set with `ARGON2_MEMORY_COST` (in KiB), `ARGON2_TIME_COST` and
+/// `ARGON2_PARALLELISM`, defaulting to the argon2 crate's recommendation. Raising them only
+/// affects new hashes; older ones are upgraded the next time their owner logs in.
+pub struct PasswordSettings {
+    params: Params,
+    min_length: usize,
+    max_length: usize,
+    /// Lowercased passwords from `PASSWORD_BREACHED_LIST_PATH`, one per line
+    breached_passwords: HashSet<String>,
+}
+
+impl PasswordSettings {
+    fn from_env() -> Result<Self> {
+        let params = Params::new(
+            get_setting_from_env("ARGON2_MEMORY_COST", Params::DEFAULT_M_COST),
+            get_setting_from_env("ARGON2_TIME_COST", Params::DEFAULT_T_COST),
+            get_setting_from_env("ARGON2_PARALLELISM", Params::DEFAULT_P_COST),
+            None,
+        )
+        .map_err(|e| anyhow::anyhow!("Invalid argon2 parameters: {}", e))?;
+
+        let breached_passwords = match std::env::var("PASSWORD_BREACHED_LIST_PATH") {
+            Ok(path) if !path.trim().is_empty() => std::fs::read_to_string(path.trim())
+                .with_context(|| format!("Failed to read breached password list {}", path))?
+                .lines()
+                .map(|line| line.trim().to_lowercase())
+                .filter(|line| !line.is_empty())
+                .collect(),
+            _ => HashSet::new(),
+        };
+
+        Ok(PasswordSettings {
+            params,
+            min_length: get_setting_from_env("PASSWORD_MIN_LENGTH", PASSWORD_MIN_LENGTH),
+            max_length: get_setting_from_env("PASSWORD_MAX_LENGTH", PASSWORD_MAX_LENGTH),
+            breached_passwords,
+        })
+    }
+
+    /// The hasher new password hashes are made with.
+    pub fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+
+    /// Whether `hash` was made with a different algorithm or cheaper parameters than we use now,
+    /// and should be replaced once we have the password again. Hashes that are already stronger
+    /// are left alone.
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        let hash = match PasswordHash::new(hash) {
+            Ok(hash) => hash,
+            Err(_) => return false,
+        };
+        if hash.algorithm != Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13.into())
+        {
+            return true;
+        }
+        match Params::try_from(&hash) {
+            Ok(params) => {
+                params.m_cost() < self.params.m_cost()
+                    || params.t_cost() < self.params.t_cost()
+                    || params.p_cost() < self.params.p_cost()
+            }
+            Err(_) => true,
+        }
+    }
+
+    /// Whether `password` is longer than any password users may pick, and so never worth hashing.
+    pub fn is_too_long(&self, password: &str) -> bool {
+        password.chars().count() > self.max_length
+    }
+
+    /// Fails unless `password` is one users may pick. `identifiers` are the user's username and
+    /// email addresses, none of which may be used as the password.
+    pub fn ensure_password_allowed(
+        &self,
+        password: &str,
+        identifiers: &[&str],
+    ) -> Result<(), RequestError> {
+        if password.chars().count() < self.min_length {
+            return Err(RequestError::RunTimeError("Password is too short"));
+        }
+        if self.is_too_long(password) {
+            return Err(RequestError::RunTimeError("Password is too long"));
+        }
+        let password = password.trim().to_lowercase();
+        if identifiers
+            .iter()
+            .any(|identifier| identifier.trim().to_lowercase() == password)
+        {
+            return Err(RequestError::RunTimeError(
+                "Password cannot be the same as your username or email",
+            ));
+        }
+        if self.breached_passwords.contains(&password) {
+            return Err(RequestError::RunTimeError(
+                "Password has appeared in a data breach, please choose another one",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Loads the settings from the environment. Called once when the server starts.
+pub fn init_password_settings() -> Result<()> {
+    let settings = PasswordSettings::from_env()?;
+    //? Already initialised (e.g. a second server in the same process), keep the first settings
+    let _ = PASSWORD_SETTINGS.set(settings);
+    Ok(())
+}
+
+pub fn password_settings() -> Result<&'static PasswordSettings> {
+    PASSWORD_SETTINGS
+        .get()
+        .context("Password settings have not been initialised")
+}
+
+/// Checks `password` against the policy, see `PasswordSettings::ensure_password_allowed`.
+pub fn ensure_password_allowed(password: &str, identifiers: &[&str]) -> Result<(), RequestError> {
+    password_settings()
+        .map_err(|_| RequestError::ServerError)?
+        .ensure_password_allowed(password, identifiers)
+}
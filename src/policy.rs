@@ -0,0 +1,101 @@
+use std::marker::PhantomData;
+
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+
+use sqlx::SqlitePool;
+
+use crate::authentication::{AuthUser, MaybeUser};
+use crate::db_helpers::promote_first_admin_in_db;
+use crate::errors::RequestError;
+use crate::models::{Article, ArticleStatus, Role};
+
+/// Whether `user` may edit or delete something written by `author_id`. Authors can only touch
+/// their own articles and comments, moderators and admins can touch anyone's.
+pub fn ensure_can_modify(user: &AuthUser, author_id: i64) -> Result<(), RequestError> {
+    if user.id == author_id || user.role >= Role::Moderator {
+        return Ok(());
+    }
+    Err(RequestError::Forbidden("Forbidden"))
+}
+
+/// Whether `user` may read the article. Drafts are hidden from everyone but their author and
+/// moderators, as if they didn't exist; unlisted articles are open to anyone with the link.
+pub fn ensure_can_view(user: Option<&AuthUser>, article: &Article) -> Result<(), RequestError> {
+    match (article.status, user) {
+        (ArticleStatus::Published | ArticleStatus::Unlisted, _) => Ok(()),
+        (ArticleStatus::Draft, Some(user))
+            if user.id == article.author_id || user.role >= Role::Moderator =>
+        {
+            Ok(())
+        }
+        (ArticleStatus::Draft, _) => Err(RequestError::NotFound("Article not found")),
+    }
+}
+
+/// Makes the account with the email in `ADMIN_EMAIL` an admin once it is verified, if there
+/// is no admin yet. Checked when the server starts and whenever an email is verified.
+pub async fn bootstrap_admin(pool: &SqlitePool) -> Result<(), RequestError> {
+    match std::env::var("ADMIN_EMAIL") {
+        Ok(email) if !email.trim().is_empty() => {
+            if promote_first_admin_in_db(pool, email.trim()).await? {
+                println!("Made {} the first admin", email.trim());
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The least privileged role a `RequireRole` extractor lets through.
+pub trait MinimumRole {
+    const ROLE: Role;
+}
+
+pub mod roles {
+    use super::{MinimumRole, Role};
+
+    pub struct Author;
+    pub struct Admin;
+
+    impl MinimumRole for Author {
+        const ROLE: Role = Role::Author;
+    }
+
+    impl MinimumRole for Admin {
+        const ROLE: Role = Role::Admin;
+    }
+}
+
+/// Extracts the logged in user, rejecting the request unless their role is at least `R`.
+pub struct RequireRole<R> {
+    pub user: AuthUser,
+    role: PhantomData<fn() -> R>,
+}
+
+#[axum::async_trait]
+impl<S, R> FromRequestParts<S> for RequireRole<R>
+where
+    S: Send + Sync + 'static,
+    R: MinimumRole,
+{
+    type Rejection = RequestError;
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> std::result::Result<Self, Self::Rejection> {
+        let user = match MaybeUser::from_request_parts(parts, state).await? {
+            MaybeUser(Some(user)) => user,
+            MaybeUser(None) => return Err(RequestError::NotAuthorized("Need to be authorized")),
+        };
+        if user.role < R::ROLE {
+            return Err(RequestError::Forbidden(
+                "Your role does not allow this action",
+            ));
+        }
+        Ok(RequireRole {
+            user,
+            role: PhantomData,
+        })
+    }
+}
@@ -0,0 +1,54 @@
+use crate::authentication::get_setting_from_env;
+use crate::errors::RequestError;
+
+const INVITATION_QUOTA: i64 = 5;
+const INVITATION_EXPIRY_DURATION: i64 = 7 * 24 * 60 * 60;
+
+/// Who may create an account with `register_user`, set with `REGISTRATION_MODE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistrationMode {
+    Open,
+    /// Only with an invitation code from an existing user
+    InviteOnly,
+    Closed,
+}
+
+pub fn get_registration_mode() -> RegistrationMode {
+    match std::env::var("REGISTRATION_MODE").as_deref() {
+        Ok("invite") | Ok("invite-only") => RegistrationMode::InviteOnly,
+        Ok("closed") => RegistrationMode::Closed,
+        _ => RegistrationMode::Open,
+    }
+}
+
+/// Fails unless the email's domain is in `REGISTRATION_ALLOWED_EMAIL_DOMAINS`. Every domain is
+/// allowed when that isn't set.
+pub fn ensure_email_domain_allowed(email: &str) -> Result<(), RequestError> {
+    let allowed_domains = match std::env::var("REGISTRATION_ALLOWED_EMAIL_DOMAINS") {
+        Ok(domains) if !domains.trim().is_empty() => domains,
+        _ => return Ok(()),
+    };
+    let domain = email
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.trim().to_lowercase())
+        .unwrap_or_default();
+    let is_allowed = allowed_domains
+        .split(',')
+        .map(|allowed| allowed.trim().to_lowercase())
+        .any(|allowed| !allowed.is_empty() && allowed == domain);
+    if !is_allowed {
+        return Err(RequestError::Forbidden(
+            "Registration is not open to this email domain",
+        ));
+    }
+    Ok(())
+}
+
+/// How many unused invitation seats a user who isn't an admin can have outstanding at once.
+pub fn get_invitation_quota() -> i64 {
+    get_setting_from_env("INVITATION_QUOTA", INVITATION_QUOTA)
+}
+
+pub fn get_invitation_expiry() -> i64 {
+    get_setting_from_env("INVITATION_EXPIRY_DURATION", INVITATION_EXPIRY_DURATION)
+}
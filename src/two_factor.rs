@@ -0,0 +1,84 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use rand::Rng;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const TOTP_DIGITS: usize = 6;
+const TOTP_SKEW: u8 = 1;
+const TOTP_STEP: u64 = 30;
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Generates a new base32 encoded TOTP secret.
+pub fn generate_totp_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+fn build_totp(secret: &str, account_name: &str) -> Result<TOTP> {
+    let issuer = std::env::var("TOTP_ISSUER").unwrap_or_else(|_| "Conduit".to_owned());
+    let secret = Secret::Encoded(secret.to_owned())
+        .to_bytes()
+        .map_err(|_| anyhow::anyhow!("Invalid TOTP secret"))?;
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        TOTP_SKEW,
+        TOTP_STEP,
+        secret,
+        Some(issuer),
+        account_name.to_owned(),
+    )
+    .context("Failed to build TOTP")
+}
+
+/// Returns the `otpauth://` URI authenticator apps use to enrol the secret.
+pub fn get_otpauth_uri(secret: &str, account_name: &str) -> Result<String> {
+    Ok(build_totp(secret, account_name)?.get_url())
+}
+
+/// Returns the time step `code` belongs to if it is valid now, give or take `TOTP_SKEW` steps.
+/// Steps only go up, so remembering the last one used is enough to refuse a code twice.
+pub fn verify_totp_code(secret: &str, account_name: &str, code: &str) -> Result<Option<i64>> {
+    let totp = build_totp(secret, account_name)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("System clock is before the unix epoch")?
+        .as_secs();
+    let current_step = now / TOTP_STEP;
+    let skew = u64::from(TOTP_SKEW);
+    for step in current_step.saturating_sub(skew)..=current_step + skew {
+        //? `check` also looks at the steps around the one it's given, so compare one at a time
+        if constant_time_eq(
+            totp.generate(step * TOTP_STEP).as_bytes(),
+            code.trim().as_bytes(),
+        ) {
+            return Ok(Some(step as i64));
+        }
+    }
+    Ok(None)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Generates the one-time recovery codes handed to the user when they enable two-factor auth.
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code: String = (0..10)
+                .map(|_| {
+                    RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char
+                })
+                .collect();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+/// Recovery codes are compared case-insensitively and without surrounding whitespace.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.trim().to_lowercase()
+}
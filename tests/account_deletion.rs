@@ -0,0 +1,176 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{app_with_env, email, TestApp, PASSWORD};
+use reqwest::StatusCode;
+use serde_json::json;
+use sqlx::SqlitePool;
+
+fn app() -> &'static TestApp {
+    app_with_env(&[
+        ("ACCOUNT_DELETION_GRACE_PERIOD", "0"),
+        ("ACCOUNT_PURGE_INTERVAL", "1"),
+        ("LOGIN_MAX_FAILED_ATTEMPTS", "2"),
+        ("ADMIN_EMAIL", "purged_admin@example.com"),
+    ])
+}
+
+async fn database() -> SqlitePool {
+    SqlitePool::connect(&std::env::var("DATABASE_URL").unwrap())
+        .await
+        .unwrap()
+}
+
+async fn user_id(username: &str) -> i64 {
+    sqlx::query_scalar("SELECT id FROM users WHERE username = $1")
+        .bind(username)
+        .fetch_one(&database().await)
+        .await
+        .unwrap()
+}
+
+async fn delete_account(app: &TestApp, token: &str, content: &str) {
+    let response = app
+        .request(
+            reqwest::Method::DELETE,
+            "/user",
+            Some(token),
+            Some(json!({"user": {"content": content}})),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+}
+
+/// Waits for the purge to rename or remove the account, whichever the user chose.
+async fn wait_until_purged(username: &str) {
+    let pool = database().await;
+    for _ in 0..50 {
+        let left: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE username = $1")
+            .bind(username)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        if left == 0 {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("{} was never purged", username);
+}
+
+#[tokio::test]
+async fn purging_an_account_forgets_the_failed_logins_against_its_email() {
+    let app = app();
+    let token = app.register("purged").await;
+    for _ in 0..2 {
+        let response = app
+            .post(
+                "/users/login",
+                None,
+                json!({"user": {"email": email("purged"), "password": "not the password"}}),
+            )
+            .await;
+        assert_ne!(response.status, StatusCode::OK);
+    }
+    assert_eq!(
+        app.login("purged").await.status,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    let response = app
+        .request(
+            reqwest::Method::DELETE,
+            "/user",
+            Some(&token),
+            Some(json!({"user": {"content": "delete"}})),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+    //? The email is only free again once the purge has run
+    let mut registered = false;
+    for _ in 0..50 {
+        let response = app
+            .post(
+                "/users",
+                None,
+                json!({"user": {
+                    "username": "purged",
+                    "email": email("purged"),
+                    "password": PASSWORD,
+                }}),
+            )
+            .await;
+        if response.status == StatusCode::OK {
+            registered = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(registered, "the account was never purged");
+    assert_eq!(app.login("purged").await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn a_purged_accounts_id_is_never_handed_out_again() {
+    let app = app();
+    let token = app.register("purged_id").await;
+    let purged_id = user_id("purged_id").await;
+    delete_account(app, &token, "delete").await;
+    wait_until_purged("purged_id").await;
+
+    app.register("after_purged_id").await;
+    assert!(user_id("after_purged_id").await > purged_id);
+}
+
+#[tokio::test]
+async fn purging_an_account_removes_its_export_archives() {
+    let app = app();
+    let token = app.register("purged_exporter").await;
+    let response = app.post("/user/export", Some(&token), json!({})).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let export_id = response.body["export"]["id"].as_i64().unwrap();
+    let archive = std::path::Path::new(&std::env::var("DATA_EXPORT_DIR").unwrap())
+        .join(format!("{}.zip", export_id));
+    for _ in 0..50 {
+        if archive.exists() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(archive.exists(), "the export was never written");
+
+    delete_account(app, &token, "anonymise").await;
+    wait_until_purged("purged_exporter").await;
+    assert!(!archive.exists());
+}
+
+#[tokio::test]
+async fn purging_an_admin_ends_the_sessions_they_impersonated_in() {
+    let app = app();
+    let admin = app.register_verified("purged_admin").await;
+    let admin_id = user_id("purged_admin").await;
+    app.register("impersonated_by_purged").await;
+    let response = app
+        .post(
+            "/profiles/impersonated_by_purged/impersonate",
+            Some(&admin),
+            json!({}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let impersonation = response.body["user"]["token"].as_str().unwrap().to_owned();
+
+    delete_account(app, &admin, "anonymise").await;
+    wait_until_purged("purged_admin").await;
+    let sessions: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM sessions WHERE impersonator_id = $1")
+            .bind(admin_id)
+            .fetch_one(&database().await)
+            .await
+            .unwrap();
+    assert_eq!(sessions, 0);
+    let response = app.get("/user", Some(&impersonation)).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,228 @@
+mod common;
+
+use common::{app, email, PASSWORD};
+use reqwest::StatusCode;
+use serde_json::json;
+
+#[tokio::test]
+async fn registering_sends_a_verification_email() {
+    let app = app();
+    let token = app.register("verify_me").await;
+    let user = app.get("/user", Some(&token)).await;
+    assert_eq!(user.body["user"]["emailVerified"], false);
+
+    let mail = app.mail_to(&email("verify_me"));
+    assert_eq!(mail.len(), 1);
+    assert_eq!(mail[0].subject, "Verify your email");
+
+    let verification = app.token_from_last_mail(&email("verify_me"));
+    let response = app
+        .post(
+            "/users/email/verify",
+            None,
+            json!({"user": {"token": verification}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let user = app.get("/user", Some(&token)).await;
+    assert_eq!(user.body["user"]["emailVerified"], true);
+}
+
+#[tokio::test]
+async fn password_reset_goes_through_the_emailed_link() {
+    let app = app();
+    app.register("forgetful").await;
+    let response = app
+        .post(
+            "/users/password/forgot",
+            None,
+            json!({"user": {"email": email("forgetful")}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    app.wait_for_mail(&email("forgetful"), "Reset your password")
+        .await;
+    let mail = app.mail_to(&email("forgetful"));
+    assert_eq!(mail.last().unwrap().subject, "Reset your password");
+
+    let reset = app.token_from_last_mail(&email("forgetful"));
+    let new_password = "a much better password";
+    let response = app
+        .post(
+            "/users/password/reset",
+            None,
+            json!({"user": {"token": reset, "password": new_password}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+    assert_ne!(app.login("forgetful").await.status, StatusCode::OK);
+    let response = app
+        .post(
+            "/users/login",
+            None,
+            json!({"user": {"email": email("forgetful"), "password": new_password}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+    let response = app
+        .post(
+            "/users/password/reset",
+            None,
+            json!({"user": {"token": reset, "password": PASSWORD}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn password_reset_for_an_unknown_email_sends_nothing() {
+    let app = app();
+    let response = app
+        .post(
+            "/users/password/forgot",
+            None,
+            json!({"user": {"email": "nobody@example.com"}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    assert!(app.mail_to("nobody@example.com").is_empty());
+}
+
+#[tokio::test]
+async fn the_memory_outbox_only_keeps_the_latest_mail() {
+    use realworld::mailer::{Email, Mailer, OutboxMailer};
+
+    let mailer = OutboxMailer::in_memory();
+    for index in 0..1001 {
+        let email = Email {
+            to: "someone@example.com".into(),
+            subject: index.to_string(),
+            body: String::new(),
+        };
+        mailer.send(email).await.unwrap();
+    }
+    let sent = mailer.sent_mail();
+    assert_eq!(sent.len(), 1000);
+    assert_eq!(sent[0].subject, "1");
+    assert_eq!(sent[999].subject, "1000");
+}
+
+#[tokio::test]
+async fn the_file_outbox_keeps_mail_sent_at_the_same_time() {
+    use realworld::mailer::{Email, Mailer, OutboxMailer};
+
+    let directory = std::env::temp_dir().join(format!("outbox-test-{}", std::process::id()));
+    let mailer = std::sync::Arc::new(OutboxMailer::with_directory(directory.clone()));
+    let sends: Vec<_> = (0..20)
+        .map(|index| {
+            let mailer = mailer.clone();
+            tokio::spawn(async move {
+                let email = Email {
+                    to: "someone@example.com".into(),
+                    subject: index.to_string(),
+                    body: String::new(),
+                };
+                mailer.send(email).await.unwrap();
+            })
+        })
+        .collect();
+    for send in sends {
+        send.await.unwrap();
+    }
+    assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 20);
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[tokio::test]
+async fn resending_verification_keeps_a_pending_email_change() {
+    let app = app();
+    let token = app.register("changer").await;
+    let response = app
+        .put(
+            "/user",
+            Some(&token),
+            json!({"user": {"email": "changer.new@example.com"}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let change = app.token_from_last_mail("changer.new@example.com");
+
+    let response = app
+        .post("/user/email/verify", Some(&token), json!({}))
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let verification = app.token_from_last_mail(&email("changer"));
+
+    let response = app
+        .post(
+            "/users/email/verify",
+            None,
+            json!({"user": {"token": change}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let user = app.get("/user", Some(&token)).await;
+    assert_eq!(user.body["user"]["email"], "changer.new@example.com");
+
+    //? The old address isn't the user's any more, so confirming it mustn't bring it back
+    let response = app
+        .post(
+            "/users/email/verify",
+            None,
+            json!({"user": {"token": verification}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let user = app.get("/user", Some(&token)).await;
+    assert_eq!(user.body["user"]["email"], "changer.new@example.com");
+}
+
+#[tokio::test]
+async fn a_signed_out_session_is_refused_even_if_it_was_just_used() {
+    let app = app();
+    let token = app.register("signed_out").await;
+    assert_eq!(app.get("/user", Some(&token)).await.status, StatusCode::OK);
+    let response = app.post("/users/logout", Some(&token), json!({})).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(
+        app.get("/user", Some(&token)).await.status,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn changing_the_password_signs_out_every_other_session() {
+    let app = app();
+    let current = app.register("password_changer").await;
+    let other = app.login("password_changer").await;
+    assert_eq!(other.status, StatusCode::OK, "{}", other.body);
+    let other_token = other.body["user"]["token"].as_str().unwrap();
+    let other_refresh_token = other.body["user"]["refreshToken"].as_str().unwrap();
+
+    let response = app
+        .put(
+            "/user",
+            Some(&current),
+            json!({"user": {"password": "a much better password"}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+    assert_eq!(
+        app.get("/user", Some(&current)).await.status,
+        StatusCode::OK
+    );
+    let response = app.get("/user", Some(other_token)).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    let response = app
+        .post(
+            "/users/token/refresh",
+            None,
+            json!({"user": {"refreshToken": other_refresh_token}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
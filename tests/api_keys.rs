@@ -0,0 +1,59 @@
+mod common;
+
+use common::{app, TestApp};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+async fn create_api_key(app: &TestApp, token: &str, scopes: Value) -> String {
+    let response = app
+        .post(
+            "/user/api-keys",
+            Some(token),
+            json!({"apiKey": {"name": "ci", "scopes": scopes}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    response.body["apiKey"]["key"].as_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn the_current_user_never_echoes_an_api_key() {
+    let app = app();
+    let token = app.register("key_owner").await;
+    let key = create_api_key(app, &token, json!(["profile:read"])).await;
+
+    let response = app.get("/user", Some(&key)).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(response.body["user"]["username"], "key_owner");
+    assert!(response.body["user"].get("token").is_none());
+
+    let response = app.get("/user", Some(&token)).await;
+    assert_eq!(response.body["user"]["token"], token.as_str());
+}
+
+#[tokio::test]
+async fn using_an_api_key_records_when_it_was_last_used() {
+    let app = app();
+    let token = app.register("key_user").await;
+    let key = create_api_key(app, &token, json!(["profile:read"])).await;
+
+    let response = app.get("/user/api-keys", Some(&token)).await;
+    assert!(response.body["apiKeys"][0]["lastUsedAt"].is_null());
+
+    for _ in 0..2 {
+        assert_eq!(app.get("/user", Some(&key)).await.status, StatusCode::OK);
+    }
+    let response = app.get("/user/api-keys", Some(&token)).await;
+    assert!(response.body["apiKeys"][0]["lastUsedAt"].is_string());
+}
+
+#[tokio::test]
+async fn api_keys_only_do_what_they_were_scoped_for() {
+    let app = app();
+    let token = app.register("key_scoped").await;
+    let key = create_api_key(app, &token, json!(["profile:read"])).await;
+    let response = app
+        .put("/user", Some(&key), json!({"user": {"bio": "hello"}}))
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+}
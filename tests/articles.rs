@@ -0,0 +1,228 @@
+mod common;
+
+use std::time::Duration;
+
+use chrono::Utc;
+use common::{app, TestApp};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+
+async fn revisions(app: &TestApp, token: &str, slug: &str) -> Vec<Value> {
+    let response = app
+        .get(&format!("/articles/{}/revisions", slug), Some(token))
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    response.body["revisions"].as_array().unwrap().clone()
+}
+
+#[tokio::test]
+async fn an_update_records_its_revision_and_status_together() {
+    let app = app();
+    let token = app.register_verified("revision_author").await;
+    let slug = app.create_article(&token, "Revised often").await["slug"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    let response = app
+        .put(
+            &format!("/articles/{}", slug),
+            Some(&token),
+            json!({"article": {"body": "b2", "status": "published"}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(response.body["article"]["status"], "published");
+    let numbers: Vec<_> = revisions(app, &token, &slug)
+        .await
+        .iter()
+        .map(|revision| revision["number"].as_i64().unwrap())
+        .collect();
+    assert_eq!(numbers, [2, 1]);
+
+    let response = app
+        .put(
+            &format!("/articles/{}", slug),
+            Some(&token),
+            json!({"article": {"status": "draft"}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(revisions(app, &token, &slug).await.len(), 2);
+}
+
+#[tokio::test]
+async fn articles_created_at_once_with_one_title_get_their_own_slugs() {
+    let app = app();
+    let token = app.register_verified("slug_collider").await;
+    let requests: Vec<_> = (0..8)
+        .map(|_| {
+            let token = token.clone();
+            tokio::spawn(async move {
+                app.post(
+                    "/articles",
+                    Some(&token),
+                    json!({"article": {"title": "Same title", "description": "d", "body": "b"}}),
+                )
+                .await
+            })
+        })
+        .collect();
+    let mut slugs = Vec::new();
+    for request in requests {
+        let response = request.await.unwrap();
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        slugs.push(
+            response.body["article"]["slug"]
+                .as_str()
+                .unwrap()
+                .to_owned(),
+        );
+    }
+    slugs.sort();
+    slugs.dedup();
+    assert_eq!(slugs.len(), 8);
+    assert!(slugs.contains(&"same-title".to_owned()));
+}
+
+#[tokio::test]
+async fn titles_that_would_shadow_a_route_get_a_suffix() {
+    let app = app();
+    let token = app.register_verified("slug_shadower").await;
+    for (title, slug) in [("Feed", "feed-2"), ("Search", "search-2")] {
+        let article = app.create_article(&token, title).await;
+        assert_eq!(article["slug"], slug);
+        let response = app.get(&format!("/articles/{}", slug), None).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    }
+}
+
+#[tokio::test]
+async fn heading_ids_are_prefixed_so_they_cant_clash_with_the_page() {
+    let app = app();
+    let token = app.register_verified("heading_writer").await;
+    let response = app
+        .post(
+            "/articles",
+            Some(&token),
+            json!({"article": {
+                "title": "Headings",
+                "description": "d",
+                "body": "## Intro\n\ntext\n\n## Intro",
+            }}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let slug = response.body["article"]["slug"].as_str().unwrap();
+
+    for _ in 0..2 {
+        let response = app
+            .get(&format!("/articles/{}?html=true", slug), None)
+            .await;
+        let html = response.body["article"]["bodyHtml"].as_str().unwrap();
+        assert!(html.contains(r#"<h2 id="user-content-intro">"#), "{}", html);
+        assert!(
+            html.contains(r#"<h2 id="user-content-intro-2">"#),
+            "{}",
+            html
+        );
+    }
+}
+
+/// Every slug in the list at `path`, following `nextCursor` `limit` articles at a time.
+async fn slugs_by_cursor(app: &TestApp, path: &str, limit: u32) -> Vec<String> {
+    let mut slugs = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let mut url = format!("{}&limit={}", path, limit);
+        if let Some(cursor) = &cursor {
+            url = format!("{}&cursor={}", url, cursor);
+        }
+        let response = app.get(&url, None).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        for article in response.body["articles"].as_array().unwrap() {
+            slugs.push(article["slug"].as_str().unwrap().to_owned());
+        }
+        match response.body["nextCursor"].as_str() {
+            Some(next) => cursor = Some(next.to_owned()),
+            None => return slugs,
+        }
+    }
+}
+
+#[tokio::test]
+async fn cursors_page_through_articles_published_at_the_same_time() {
+    let app = app();
+    let token = app.register_verified("cursor_pager").await;
+    let mut created = Vec::new();
+    for n in 0..5 {
+        let article = app.create_article(&token, &format!("Tied {}", n)).await;
+        created.push(article["slug"].as_str().unwrap().to_owned());
+    }
+    //? A time whose julianday doesn't survive JSON unless floats are read back exactly
+    let pool = SqlitePool::connect(&std::env::var("DATABASE_URL").unwrap())
+        .await
+        .unwrap();
+    sqlx::query(
+        "UPDATE articles SET published_at = '2026-01-01 00:00:08'
+        WHERE author_id = (SELECT id FROM users WHERE username = 'cursor_pager')",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let newest = slugs_by_cursor(app, "/articles?author=cursor_pager", 2).await;
+    let mut expected = created.clone();
+    expected.reverse();
+    assert_eq!(newest, expected);
+
+    let oldest = slugs_by_cursor(app, "/articles?author=cursor_pager&sort=oldest", 2).await;
+    assert_eq!(oldest, created);
+}
+
+#[tokio::test]
+async fn a_scheduled_article_stays_hidden_until_it_is_published() {
+    let app = app();
+    let token = app.register_verified("scheduler").await;
+    let reader = app.register_verified("schedule_reader").await;
+    let past = (Utc::now() - chrono::Duration::minutes(1)).to_rfc3339();
+    let article = json!({"title": "Later", "description": "d", "body": "b", "publishAt": past});
+    let response = app
+        .post("/articles", Some(&token), json!({ "article": article }))
+        .await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let publish_at = (Utc::now() + chrono::Duration::seconds(2)).to_rfc3339();
+    let article =
+        json!({"title": "Later", "description": "d", "body": "b", "publishAt": publish_at});
+    let response = app
+        .post("/articles", Some(&token), json!({ "article": article }))
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(response.body["article"]["status"], "draft");
+    assert!(response.body["article"]["publishAt"].is_string());
+    let path = format!(
+        "/articles/{}",
+        response.body["article"]["slug"].as_str().unwrap()
+    );
+    assert_eq!(
+        app.get(&path, Some(&reader)).await.status,
+        StatusCode::NOT_FOUND
+    );
+    let listed = app.get("/articles?author=scheduler", None).await;
+    assert_eq!(listed.body["articlesCount"], 0);
+
+    for _ in 0..50 {
+        let response = app.get(&path, Some(&reader)).await;
+        if response.status == StatusCode::OK {
+            assert_eq!(response.body["article"]["status"], "published");
+            assert!(response.body["article"].get("publishAt").is_none());
+            let listed = app.get("/articles?author=scheduler", None).await;
+            assert_eq!(listed.body["articlesCount"], 1);
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("The scheduled article was never published");
+}
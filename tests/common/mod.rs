@@ -0,0 +1,224 @@
+//! Starts the server once per test binary and talks to it over HTTP.
+
+#![allow(dead_code)]
+
+pub mod mock_issuer;
+
+use std::{
+    net::{SocketAddr, TcpStream},
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use realworld::{get_random_free_port, mailer::OutboxMailer, make_router, run_app};
+use reqwest::{header::HeaderMap, Method, StatusCode};
+use serde_json::{json, Value};
+
+pub const PASSWORD: &str = "correct horse battery";
+
+pub struct TestApp {
+    pub address: SocketAddr,
+    pub mailer: Arc<OutboxMailer>,
+}
+
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Value,
+}
+
+static APP: OnceLock<TestApp> = OnceLock::new();
+
+/// The server for this test binary, on a fresh database.
+pub fn app() -> &'static TestApp {
+    app_with_env(&[])
+}
+
+/// Like `app`, with extra environment variables. Every test in a binary has to ask for the same
+/// ones, since the server is only started once.
+pub fn app_with_env(env: &[(&str, &str)]) -> &'static TestApp {
+    APP.get_or_init(|| {
+        let directory = std::env::temp_dir().join(format!(
+            "realworld-test-{}-{}",
+            std::process::id(),
+            time::OffsetDateTime::now_utc().unix_timestamp_nanos()
+        ));
+        std::fs::create_dir_all(&directory).unwrap();
+        let database_url = format!("sqlite://{}", directory.join("test.db").display());
+        let data_export_dir = directory.join("exports").display().to_string();
+        let defaults = [
+            ("DATABASE_URL", database_url.as_str()),
+            ("DATA_EXPORT_DIR", data_export_dir.as_str()),
+            ("JWT_SECRET", "test-secret"),
+            ("REQUIRE_EMAIL_VERIFICATION", "true"),
+            //? The default argon2 cost makes every registration take seconds in a debug build
+            ("ARGON2_MEMORY_COST", "64"),
+            ("ARGON2_TIME_COST", "1"),
+        ];
+        for (key, value) in defaults.iter().chain(env) {
+            std::env::set_var(key, value);
+        }
+
+        let (_, address) = get_random_free_port();
+        let mailer = Arc::new(OutboxMailer::in_memory());
+        let server_mailer = mailer.clone();
+        std::thread::spawn(move || {
+            tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(run_app(make_router(), address, server_mailer))
+                .unwrap();
+        });
+        for _ in 0..200 {
+            if TcpStream::connect(address).is_ok() {
+                return TestApp { address, mailer };
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        panic!("The server didn't start");
+    })
+}
+
+impl TestApp {
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.address, path)
+    }
+
+    pub async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> TestResponse {
+        //? A client per request, since each test has its own runtime
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let mut request = client.request(method, self.url(path));
+        if let Some(token) = token {
+            request = request.header("Authorization", format!("Token {}", token));
+        }
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let response = request.send().await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let text = response.text().await.unwrap();
+        let body = serde_json::from_str(&text).unwrap_or(Value::String(text));
+        TestResponse {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    pub async fn get(&self, path: &str, token: Option<&str>) -> TestResponse {
+        self.request(Method::GET, path, token, None).await
+    }
+
+    pub async fn post(&self, path: &str, token: Option<&str>, body: Value) -> TestResponse {
+        self.request(Method::POST, path, token, Some(body)).await
+    }
+
+    pub async fn put(&self, path: &str, token: Option<&str>, body: Value) -> TestResponse {
+        self.request(Method::PUT, path, token, Some(body)).await
+    }
+
+    pub async fn delete(&self, path: &str, token: Option<&str>) -> TestResponse {
+        self.request(Method::DELETE, path, token, None).await
+    }
+
+    /// Registers `username` with an `@example.com` address and returns their access token.
+    pub async fn register(&self, username: &str) -> String {
+        let response = self
+            .post(
+                "/users",
+                None,
+                json!({"user": {
+                    "username": username,
+                    "email": email(username),
+                    "password": PASSWORD,
+                }}),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        response.body["user"]["token"].as_str().unwrap().to_owned()
+    }
+
+    /// Registers `username` and verifies their email, so they can write.
+    pub async fn register_verified(&self, username: &str) -> String {
+        let token = self.register(username).await;
+        let verification = self.token_from_last_mail(&email(username));
+        let response = self
+            .post(
+                "/users/email/verify",
+                None,
+                json!({"user": {"token": verification}}),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        token
+    }
+
+    pub async fn login(&self, username: &str) -> TestResponse {
+        self.post(
+            "/users/login",
+            None,
+            json!({"user": {"email": email(username), "password": PASSWORD}}),
+        )
+        .await
+    }
+
+    pub fn mail_to(&self, address: &str) -> Vec<realworld::mailer::Email> {
+        self.mailer
+            .sent_mail()
+            .into_iter()
+            .filter(|email| email.to == address)
+            .collect()
+    }
+
+    /// Waits for mail that is sent after the response, like password resets, to reach `address`.
+    pub async fn wait_for_mail(&self, address: &str, subject: &str) {
+        for _ in 0..100 {
+            if self
+                .mail_to(address)
+                .iter()
+                .any(|email| email.subject == subject)
+            {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("No \"{}\" mail reached {}", subject, address);
+    }
+
+    /// The `token=` from the last link emailed to `address`.
+    pub fn token_from_last_mail(&self, address: &str) -> String {
+        let mail = self.mail_to(address);
+        let body = &mail.last().expect("no mail was sent").body;
+        let start = body.find("token=").expect("the mail has no token") + "token=".len();
+        body[start..]
+            .split(|c: char| c.is_whitespace())
+            .next()
+            .unwrap()
+            .to_owned()
+    }
+
+    pub async fn create_article(&self, token: &str, title: &str) -> Value {
+        let response = self
+            .post(
+                "/articles",
+                Some(token),
+                json!({"article": {"title": title, "description": "d", "body": "b"}}),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        response.body["article"].clone()
+    }
+}
+
+pub fn email(username: &str) -> String {
+    format!("{}@example.com", username)
+}
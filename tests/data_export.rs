@@ -0,0 +1,86 @@
+mod common;
+
+use std::{io::Read, time::Duration};
+
+use common::{app, TestApp};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+/// Asks for an export and waits until it is ready, returning its download link.
+async fn export(app: &TestApp, token: &str) -> String {
+    let response = app.post("/user/export", Some(token), json!({})).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(response.body["export"]["status"], "pending");
+    let download_url = response.body["export"]["downloadUrl"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    for _ in 0..50 {
+        let response = app.get("/user/export", Some(token)).await;
+        assert!(response.body["export"].get("downloadUrl").is_none());
+        if response.body["export"]["status"] == "ready" {
+            return download_url;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("The export was never ready");
+}
+
+fn read_file(archive: &mut zip::ZipArchive<std::io::Cursor<Vec<u8>>>, name: &str) -> String {
+    let mut contents = String::new();
+    archive
+        .by_name(name)
+        .unwrap()
+        .read_to_string(&mut contents)
+        .unwrap();
+    contents
+}
+
+#[tokio::test]
+async fn an_export_holds_the_users_profile_and_writing() {
+    let app = app();
+    let token = app.register_verified("exporter").await;
+    let article = app.create_article(&token, "Exported article").await;
+    let slug = article["slug"].as_str().unwrap();
+    let response = app
+        .post(
+            &format!("/articles/{}/comments", slug),
+            Some(&token),
+            json!({"comment": {"body": "An exported comment"}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+    let download_url = export(app, &token).await;
+    //? Opened without a token, like a link from an email
+    let response = reqwest::get(app.url(&download_url)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "application/zip");
+    let bytes = response.bytes().await.unwrap().to_vec();
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+
+    let profile: Value = serde_json::from_str(&read_file(&mut archive, "profile.json")).unwrap();
+    assert_eq!(profile["username"], "exporter");
+    assert_eq!(profile["email"], "exporter@example.com");
+    let articles: Value = serde_json::from_str(&read_file(&mut archive, "articles.json")).unwrap();
+    assert_eq!(articles[0]["slug"], slug);
+    let markdown = read_file(&mut archive, &format!("articles/{}.md", slug));
+    assert!(markdown.contains("Exported article"), "{}", markdown);
+    let comments: Value = serde_json::from_str(&read_file(&mut archive, "comments.json")).unwrap();
+    assert_eq!(comments[0]["body"], "An exported comment");
+    assert_eq!(comments[0]["article"], slug);
+    let sessions: Value = serde_json::from_str(&read_file(&mut archive, "sessions.json")).unwrap();
+    assert_eq!(sessions.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn an_export_link_only_opens_that_export() {
+    let app = app();
+    let token = app.register("export_guesser").await;
+    let download_url = export(app, &token).await;
+
+    let response = app.get(&format!("{}x", download_url), None).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = app.get("/user/export", None).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use common::{app_with_env, TestApp};
+use reqwest::StatusCode;
+use serde_json::json;
+use tokio::sync::OnceCell;
+
+static ADMIN_TOKEN: OnceCell<String> = OnceCell::const_new();
+
+fn app() -> &'static TestApp {
+    app_with_env(&[
+        ("ADMIN_EMAIL", "impersonator@example.com"),
+        ("IMPERSONATION_READ_ONLY", "true"),
+    ])
+}
+
+async fn admin(app: &'static TestApp) -> &'static str {
+    ADMIN_TOKEN
+        .get_or_init(|| app.register_verified("impersonator"))
+        .await
+}
+
+async fn impersonate(app: &'static TestApp, username: &str) -> String {
+    let response = app
+        .post(
+            &format!("/profiles/{}/impersonate", username),
+            Some(admin(app).await),
+            json!({}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    response.body["user"]["token"].as_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn read_only_impersonation_can_look_but_not_change_anything() {
+    let app = app();
+    let token = app.register_verified("impersonated").await;
+    let slug = app.create_article(&token, "Untouched").await["slug"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let impersonation = impersonate(app, "impersonated").await;
+
+    let response = app.get("/user", Some(&impersonation)).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(response.body["user"]["username"], "impersonated");
+
+    let response = app
+        .put(
+            &format!("/articles/{}", slug),
+            Some(&impersonation),
+            json!({"article": {"body": "Changed by an admin"}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    let response = app
+        .post(
+            "/articles",
+            Some(&impersonation),
+            json!({"article": {"title": "Ghost written", "description": "d", "body": "b"}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    let response = app
+        .put(
+            "/user",
+            Some(&impersonation),
+            json!({"user": {"bio": "Not mine"}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+    let article = app.get(&format!("/articles/{}", slug), None).await;
+    assert_eq!(article.body["article"]["body"], "b");
+    let user = app.get("/user", Some(&token)).await;
+    assert_eq!(user.body["user"]["bio"], "");
+}
+
+#[tokio::test]
+async fn impersonation_tokens_cannot_manage_the_account() {
+    let app = app();
+    app.register_verified("impersonated_account").await;
+    let impersonation = impersonate(app, "impersonated_account").await;
+    let response = app.get("/user/sessions", Some(&impersonation)).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn only_admins_can_impersonate_and_never_other_admins() {
+    let app = app();
+    let token = app.register_verified("would_be_impersonator").await;
+    let response = app
+        .post(
+            "/profiles/impersonator/impersonate",
+            Some(&token),
+            json!({}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+    let response = app
+        .post(
+            "/profiles/impersonator/impersonate",
+            Some(admin(app).await),
+            json!({}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+}
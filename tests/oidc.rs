@@ -0,0 +1,103 @@
+mod common;
+
+use common::{
+    mock_issuer::{app_with_mock_issuer, authorize, callback, issuer, login},
+    TestApp,
+};
+use reqwest::StatusCode;
+use serde_json::json;
+
+fn app() -> &'static TestApp {
+    app_with_mock_issuer(&[])
+}
+
+#[tokio::test]
+async fn the_first_login_creates_an_account_and_later_ones_reuse_it() {
+    let app = app();
+    let claims = json!({
+        "sub": "sso-1",
+        "email": "sso_first@example.com",
+        "email_verified": true,
+        "preferred_username": "SSO First",
+    });
+    let response = login(app, claims.clone()).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let user = &response.body["user"];
+    assert_eq!(user["username"], "sso-first");
+    assert_eq!(user["emailVerified"], true);
+    let token = user["token"].as_str().unwrap().to_owned();
+    assert_eq!(app.get("/user", Some(&token)).await.status, StatusCode::OK);
+
+    let response = login(app, claims).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(response.body["user"]["username"], "sso-first");
+}
+
+#[tokio::test]
+async fn a_verified_email_links_to_the_existing_account() {
+    let app = app();
+    app.register_verified("sso_linked").await;
+    let claims = json!({
+        "sub": "sso-2",
+        "email": "sso_linked@example.com",
+        "email_verified": true,
+    });
+    let response = login(app, claims).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(response.body["user"]["username"], "sso_linked");
+}
+
+#[tokio::test]
+async fn an_unverified_email_cannot_take_over_an_account() {
+    let app = app();
+    app.register("sso_victim").await;
+    let claims = json!({
+        "sub": "sso-3",
+        "email": "sso_victim@example.com",
+        "email_verified": false,
+    });
+    let response = login(app, claims).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn an_unverified_account_is_not_linked_even_to_a_verified_email() {
+    let app = app();
+    //? As if someone registered the address first, with a password they know
+    let squatter = app.register("sso_squatted").await;
+    let claims = json!({
+        "sub": "sso-6",
+        "email": "sso_squatted@example.com",
+        "email_verified": true,
+    });
+    let response = login(app, claims).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let response = app.get("/user", Some(&squatter)).await;
+    assert_eq!(response.body["user"]["emailVerified"], false);
+}
+
+#[tokio::test]
+async fn id_tokens_for_another_login_are_refused() {
+    let app = app();
+    let claims = json!({
+        "sub": "sso-4",
+        "email": "sso_nonce@example.com",
+        "nonce": "someone-elses-nonce",
+    });
+    let response = login(app, claims).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn a_login_state_can_only_be_used_once() {
+    let app = app();
+    let (authorization_url, state) = authorize(app, json!({})).await;
+    let claims = json!({"sub": "sso-5", "email": "sso_once@example.com"});
+    let code = issuer().grant(&authorization_url, claims.clone());
+    let response = callback(app, &code, &state).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+    let code = issuer().grant(&authorization_url, claims);
+    let response = callback(app, &code, &state).await;
+    assert_ne!(response.status, StatusCode::OK);
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use common::{
+    mock_issuer::{app_with_mock_issuer, authorize, callback, issuer, login},
+    TestApp, TestResponse,
+};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+
+fn app() -> &'static TestApp {
+    app_with_mock_issuer(&[
+        ("REGISTRATION_MODE", "invite"),
+        ("REGISTRATION_ALLOWED_EMAIL_DOMAINS", "example.com"),
+    ])
+}
+
+/// Nobody can register to hand out invitations, so store one for `code` straight away.
+async fn seed_invitation(inviter: &str, code: &str) {
+    let pool = SqlitePool::connect(&std::env::var("DATABASE_URL").unwrap())
+        .await
+        .unwrap();
+    let inviter_id: i64 = sqlx::query_scalar(
+        "INSERT INTO users (email, username, password) VALUES ($1, $2, '') RETURNING id",
+    )
+    .bind(format!("{}@example.com", inviter))
+    .bind(inviter)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO invitations (created_by, code_hash, expires_at)
+        VALUES ($1, $2, datetime('now', '+1 day'))",
+    )
+    .bind(inviter_id)
+    .bind(format!("{:x}", Sha256::digest(code.as_bytes())))
+    .execute(&pool)
+    .await
+    .unwrap();
+}
+
+async fn sign_up_with_code(app: &TestApp, code: &str, claims: Value) -> TestResponse {
+    let (authorization_url, state) = authorize(app, json!({ "invitationCode": code })).await;
+    let code = issuer().grant(&authorization_url, claims);
+    callback(app, &code, &state).await
+}
+
+#[tokio::test]
+async fn signing_up_through_a_provider_needs_an_invitation() {
+    let app = app();
+    let claims = json!({"sub": "uninvited", "email": "uninvited@example.com"});
+    let response = login(app, claims).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn an_invitation_code_lets_one_identity_sign_up() {
+    let app = app();
+    seed_invitation("inviter_one", "invite-one").await;
+    let claims = json!({"sub": "invited", "email": "invited@example.com"});
+    let response = sign_up_with_code(app, "invite-one", claims.clone()).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+    //? Logging in again doesn't need the code, the account already exists
+    let response = login(app, claims).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+    let claims = json!({"sub": "invited-twice", "email": "invited_twice@example.com"});
+    let response = sign_up_with_code(app, "invite-one", claims).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn signing_up_through_a_provider_respects_the_email_domains() {
+    let app = app();
+    seed_invitation("inviter_two", "invite-two").await;
+    let claims = json!({"sub": "outsider", "email": "outsider@elsewhere.com"});
+    let response = sign_up_with_code(app, "invite-two", claims).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+    //? The refused sign-up didn't use up the invitation
+    let claims = json!({"sub": "insider", "email": "insider@example.com"});
+    let response = sign_up_with_code(app, "invite-two", claims).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+}
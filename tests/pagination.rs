@@ -0,0 +1,206 @@
+mod common;
+
+use std::collections::HashMap;
+
+use common::{app, TestApp, TestResponse};
+use reqwest::StatusCode;
+use serde_json::json;
+use sqlx::SqlitePool;
+
+/// The `Link` header's URLs by their `rel`.
+fn links_by_rel(response: &TestResponse) -> HashMap<String, String> {
+    let header = match response.headers.get("link") {
+        Some(header) => header.to_str().unwrap(),
+        None => return HashMap::new(),
+    };
+    header
+        .split(", ")
+        .map(|link| {
+            let (url, rel) = link.split_once("; rel=").unwrap();
+            (
+                rel.trim_matches('"').to_owned(),
+                url.trim_matches(['<', '>']).to_owned(),
+            )
+        })
+        .collect()
+}
+
+async fn write_articles(app: &TestApp, username: &str, count: usize) {
+    let token = app.register_verified(username).await;
+    for n in 0..count {
+        app.create_article(&token, &format!("{} {}", username, n))
+            .await;
+    }
+}
+
+#[tokio::test]
+async fn every_page_links_to_its_neighbours_and_counts_the_whole_list() {
+    let app = app();
+    write_articles(app, "page_linker", 5).await;
+
+    let response = app.get("/articles?author=page_linker&limit=2", None).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(response.body["articles"].as_array().unwrap().len(), 2);
+    assert_eq!(response.body["articlesCount"], 5);
+    let page = |offset: u32| format!("/articles?author=page_linker&limit=2&offset={}", offset);
+    let expected = |pairs: &[(&str, u32)]| {
+        pairs
+            .iter()
+            .map(|&(rel, offset)| (rel.to_owned(), page(offset)))
+            .collect::<HashMap<_, _>>()
+    };
+    assert_eq!(
+        links_by_rel(&response),
+        expected(&[("first", 0), ("next", 2), ("last", 4)])
+    );
+
+    let response = app.get(&page(2), None).await;
+    assert_eq!(response.body["articlesCount"], 5);
+    assert_eq!(
+        links_by_rel(&response),
+        expected(&[("first", 0), ("prev", 0), ("next", 4), ("last", 4)])
+    );
+
+    let response = app.get(&page(4), None).await;
+    assert_eq!(response.body["articles"].as_array().unwrap().len(), 1);
+    assert_eq!(
+        links_by_rel(&response),
+        expected(&[("first", 0), ("prev", 2), ("last", 4)])
+    );
+}
+
+#[tokio::test]
+async fn a_page_past_the_end_still_counts_and_links_back() {
+    let app = app();
+    write_articles(app, "page_overshooter", 3).await;
+
+    let response = app
+        .get("/articles?author=page_overshooter&limit=2&offset=10", None)
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert!(response.body["articles"].as_array().unwrap().is_empty());
+    assert_eq!(response.body["articlesCount"], 3);
+    let links = links_by_rel(&response);
+    assert!(links["prev"].ends_with("offset=2"), "{:?}", links);
+    assert!(links["last"].ends_with("offset=2"), "{:?}", links);
+    assert!(!links.contains_key("next"), "{:?}", links);
+
+    let response = app
+        .get(
+            "/articles/search?q=page_overshooter&limit=2&offset=10",
+            None,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(response.body["articlesCount"], 3);
+    let links = links_by_rel(&response);
+    assert!(links["prev"].ends_with("offset=2"), "{:?}", links);
+    assert!(!links.contains_key("next"), "{:?}", links);
+
+    let response = app
+        .get("/articles?author=page_nobody&limit=2&offset=4", None)
+        .await;
+    assert_eq!(response.body["articlesCount"], 0);
+    let links = links_by_rel(&response);
+    assert!(links["prev"].ends_with("offset=0"), "{:?}", links);
+    assert!(links["last"].ends_with("offset=0"), "{:?}", links);
+}
+
+#[tokio::test]
+async fn the_last_cursor_page_only_links_back_to_the_first() {
+    let app = app();
+    write_articles(app, "page_cursor", 3).await;
+
+    let response = app.get("/articles?author=page_cursor&limit=2", None).await;
+    let cursor = response.body["nextCursor"].as_str().unwrap();
+    let response = app
+        .get(
+            &format!("/articles?author=page_cursor&limit=2&cursor={}", cursor),
+            None,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(response.body["articlesCount"], 3);
+    assert!(response.body.get("nextCursor").is_none());
+    let links = links_by_rel(&response);
+    assert_eq!(links["first"], "/articles?author=page_cursor&limit=2");
+    assert!(!links.contains_key("next"), "{:?}", links);
+    assert!(!links.contains_key("last"), "{:?}", links);
+}
+
+/// Comment ids from `path` onwards, following `nextCursor` to the end.
+async fn comment_ids_by_cursor(app: &TestApp, path: &str) -> Vec<i64> {
+    let mut ids = Vec::new();
+    let mut path = path.to_owned();
+    //? Bounded, so a cursor that doesn't move on fails the test instead of hanging it
+    for _ in 0..20 {
+        let response = app.get(&path, None).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        for comment in response.body["comments"].as_array().unwrap() {
+            ids.push(comment["id"].as_i64().unwrap());
+        }
+        match response.body["nextCursor"].as_str() {
+            Some(cursor) => {
+                path = format!(
+                    "{}&cursor={}",
+                    path.split("&cursor=").next().unwrap(),
+                    cursor
+                )
+            }
+            None => return ids,
+        }
+    }
+    panic!("Never reached the last page of {}", path);
+}
+
+#[tokio::test]
+async fn comment_cursors_page_through_comments_written_at_the_same_time() {
+    let app = app();
+    let token = app.register_verified("comment_pager").await;
+    let slug = app.create_article(&token, "Much discussed").await["slug"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let path = format!("/articles/{}/comments", slug);
+    for n in 0..25 {
+        let response = app
+            .post(
+                &path,
+                Some(&token),
+                json!({"comment": {"body": format!("Comment {}", n)}}),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    }
+    let pool = SqlitePool::connect(&std::env::var("DATABASE_URL").unwrap())
+        .await
+        .unwrap();
+    sqlx::query(
+        "UPDATE comments SET created_at = '2026-01-01 00:00:08'
+        WHERE article_id = (SELECT id FROM articles WHERE slug = $1)",
+    )
+    .bind(&slug)
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let all = app.get(&path, None).await;
+    let all: Vec<i64> = all.body["comments"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|comment| comment["id"].as_i64().unwrap())
+        .collect();
+    assert_eq!(all.len(), 25);
+    let paged = comment_ids_by_cursor(app, &format!("{}?limit=3", path)).await;
+    assert_eq!(paged, all);
+
+    //? Without a limit, a cursor carries on a default-sized page at a time
+    let first = app.get(&format!("{}?limit=2", path), None).await;
+    let cursor = first.body["nextCursor"].as_str().unwrap();
+    let response = app.get(&format!("{}?cursor={}", path, cursor), None).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(response.body["comments"].as_array().unwrap().len(), 20);
+    assert_eq!(response.body["comments"][0]["id"], all[2]);
+    assert!(response.body["nextCursor"].is_string());
+}
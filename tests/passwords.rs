@@ -0,0 +1,126 @@
+mod common;
+
+use std::{path::PathBuf, sync::OnceLock};
+
+use argon2::{password_hash::SaltString, Algorithm, Argon2, Params, PasswordHasher, Version};
+use common::{app_with_env, email, TestApp, PASSWORD};
+use reqwest::StatusCode;
+use serde_json::json;
+use sqlx::SqlitePool;
+
+static BREACHED_LIST: OnceLock<PathBuf> = OnceLock::new();
+
+fn app() -> &'static TestApp {
+    let path = BREACHED_LIST.get_or_init(|| {
+        let path = std::env::temp_dir().join(format!("breached-{}.txt", std::process::id()));
+        std::fs::write(&path, "password123\n  Hunter2Hunter2  \n\n").unwrap();
+        path
+    });
+    app_with_env(&[
+        ("PASSWORD_BREACHED_LIST_PATH", &path.display().to_string()),
+        ("PASSWORD_MAX_LENGTH", "32"),
+    ])
+}
+
+async fn database() -> SqlitePool {
+    SqlitePool::connect(&std::env::var("DATABASE_URL").unwrap())
+        .await
+        .unwrap()
+}
+
+async fn register_with(app: &TestApp, username: &str, password: &str) -> (StatusCode, String) {
+    let response = app
+        .post(
+            "/users",
+            None,
+            json!({"user": {
+                "username": username,
+                "email": email(username),
+                "password": password,
+            }}),
+        )
+        .await;
+    let message = response.body["errors"]["body"][0]
+        .as_str()
+        .unwrap_or_default()
+        .to_owned();
+    (response.status, message)
+}
+
+#[tokio::test]
+async fn only_passwords_the_policy_allows_can_be_picked() {
+    let app = app();
+    for (password, message) in [
+        ("short", "Password is too short"),
+        (&"x".repeat(33), "Password is too long"),
+        (
+            "Policy_Picker",
+            "Password cannot be the same as your username or email",
+        ),
+        (
+            "policy_picker@example.com",
+            "Password cannot be the same as your username or email",
+        ),
+        (
+            "hunter2hunter2",
+            "Password has appeared in a data breach, please choose another one",
+        ),
+    ] {
+        let (status, error) = register_with(app, "policy_picker", password).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", password);
+        assert_eq!(error, message);
+    }
+
+    let (status, error) = register_with(app, "policy_picker", &"x".repeat(32)).await;
+    assert_eq!(status, StatusCode::OK, "{}", error);
+}
+
+#[tokio::test]
+async fn a_password_over_the_limit_is_refused_at_login() {
+    let app = app();
+    app.register("long_typer").await;
+    let response = app
+        .post(
+            "/users/login",
+            None,
+            json!({"user": {
+                "email": email("long_typer"),
+                "password": format!("{}{}", PASSWORD, "x".repeat(1 << 20)),
+            }}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(response.body["errors"]["body"][0], "Invalid credentials");
+    assert_eq!(app.login("long_typer").await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn a_weaker_hash_is_upgraded_at_login() {
+    let app = app();
+    app.register("old_hash").await;
+    let database = database().await;
+    let weak = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(32, 1, 1, None).unwrap(),
+    )
+    .hash_password(
+        PASSWORD.as_bytes(),
+        &SaltString::generate(rand::thread_rng()),
+    )
+    .unwrap()
+    .to_string();
+    sqlx::query("UPDATE users SET password = $1 WHERE username = 'old_hash'")
+        .bind(&weak)
+        .execute(&database)
+        .await
+        .unwrap();
+
+    assert_eq!(app.login("old_hash").await.status, StatusCode::OK);
+    let hash: String = sqlx::query_scalar("SELECT password FROM users WHERE username = 'old_hash'")
+        .fetch_one(&database)
+        .await
+        .unwrap();
+    assert!(hash.contains("m=64,t=1,p=1"), "{}", hash);
+    assert_eq!(app.login("old_hash").await.status, StatusCode::OK);
+}
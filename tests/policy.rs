@@ -0,0 +1,170 @@
+mod common;
+
+use common::{app_with_env, email, TestApp};
+use reqwest::StatusCode;
+use serde_json::json;
+
+fn app() -> &'static TestApp {
+    app_with_env(&[("ADMIN_EMAIL", "policy_admin@example.com")])
+}
+
+async fn role(app: &TestApp, token: &str) -> String {
+    let response = app.get("/user", Some(token)).await;
+    response.body["user"]["role"].as_str().unwrap().to_owned()
+}
+
+async fn add_comment(app: &TestApp, token: &str, slug: &str) -> i64 {
+    let response = app
+        .post(
+            &format!("/articles/{}/comments", slug),
+            Some(token),
+            json!({"comment": {"body": "Nice"}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    response.body["comment"]["id"].as_i64().unwrap()
+}
+
+#[tokio::test]
+async fn the_admin_email_becomes_an_admin_once_verified_and_can_appoint_moderators() {
+    let app = app();
+    let admin = app.register("policy_admin").await;
+    assert_eq!(role(app, &admin).await, "author");
+
+    let verification = app.token_from_last_mail(&email("policy_admin"));
+    let response = app
+        .post(
+            "/users/email/verify",
+            None,
+            json!({"user": {"token": verification}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(role(app, &admin).await, "admin");
+
+    let author = app.register_verified("policy_writer").await;
+    let moderator = app.register_verified("policy_moderator").await;
+    let response = app
+        .put(
+            "/profiles/policy_moderator/role",
+            Some(&admin),
+            json!({"user": {"role": "moderator"}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+    let slug = app.create_article(&author, "Moderated article").await["slug"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let response = app
+        .put(
+            &format!("/articles/{}", slug),
+            Some(&moderator),
+            json!({"article": {"description": "Edited by a moderator"}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(
+        response.body["article"]["author"]["username"],
+        "policy_writer"
+    );
+
+    let comment_id = add_comment(app, &author, &slug).await;
+    let response = app
+        .delete(
+            &format!("/articles/{}/comments/{}", slug, comment_id),
+            Some(&moderator),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+}
+
+#[tokio::test]
+async fn authors_can_only_change_their_own_articles_and_comments() {
+    let app = app();
+    let owner = app.register_verified("policy_owner").await;
+    let other = app.register_verified("policy_other").await;
+    let slug = app.create_article(&owner, "Owned article").await["slug"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let path = format!("/articles/{}", slug);
+
+    let response = app
+        .put(
+            &path,
+            Some(&other),
+            json!({"article": {"body": "Mine now"}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    let response = app.delete(&path, Some(&other)).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    let response = app.delete(&format!("{}/publish", path), Some(&other)).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+    let comment_id = add_comment(app, &owner, &slug).await;
+    let comment_path = format!("{}/comments/{}", path, comment_id);
+    let response = app.delete(&comment_path, Some(&other)).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+    assert_eq!(app.get(&path, None).await.body["article"]["body"], "b");
+    assert_eq!(app.get(&comment_path, None).await.status, StatusCode::OK);
+    assert_eq!(
+        app.delete(&comment_path, Some(&owner)).await.status,
+        StatusCode::OK
+    );
+    assert_eq!(app.delete(&path, Some(&owner)).await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn drafts_are_hidden_from_everyone_but_their_author() {
+    let app = app();
+    let author = app.register_verified("policy_drafter").await;
+    let reader = app.register_verified("policy_reader").await;
+    let response = app
+        .post(
+            "/articles",
+            Some(&author),
+            json!({"article": {
+                "title": "Secret draft",
+                "description": "d",
+                "body": "b",
+                "status": "draft",
+            }}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let path = format!(
+        "/articles/{}",
+        response.body["article"]["slug"].as_str().unwrap()
+    );
+
+    assert_eq!(app.get(&path, None).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        app.get(&path, Some(&reader)).await.status,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(app.get(&path, Some(&author)).await.status, StatusCode::OK);
+    let response = app
+        .put(
+            &path,
+            Some(&reader),
+            json!({"article": {"body": "Found it"}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = app
+        .post(&format!("{}/publish", path), Some(&reader), json!({}))
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = app.get(&format!("{}/revisions", path), Some(&reader)).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    let response = app.delete(&path, Some(&reader)).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        app.get(&path, Some(&author)).await.body["article"]["body"],
+        "b"
+    );
+}
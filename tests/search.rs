@@ -0,0 +1,88 @@
+mod common;
+
+use common::{app, TestApp};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+async fn write(app: &TestApp, token: &str, title: &str, body: &str, status: &str) -> String {
+    let response = app
+        .post(
+            "/articles",
+            Some(token),
+            json!({"article": {"title": title, "description": "d", "body": body, "status": status}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    response.body["article"]["slug"]
+        .as_str()
+        .unwrap()
+        .to_owned()
+}
+
+fn slugs(response: &Value) -> Vec<&str> {
+    response["articles"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|article| article["slug"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn drafts_are_only_found_by_their_author() {
+    let app = app();
+    let author = app.register_verified("search_author").await;
+    let reader = app.register_verified("search_reader").await;
+    let published = write(app, &author, "Zebrafish out", "b", "published").await;
+    let draft = write(app, &author, "Zebrafish in", "b", "draft").await;
+
+    for token in [None, Some(reader.as_str())] {
+        let response = app.get("/articles/search?q=zebrafish", token).await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        assert_eq!(slugs(&response.body), [published.as_str()]);
+        assert_eq!(response.body["articlesCount"], 1);
+    }
+    let response = app.get("/articles/search?q=zebrafish", Some(&author)).await;
+    assert_eq!(response.body["articlesCount"], 2);
+    assert!(slugs(&response.body).contains(&draft.as_str()));
+}
+
+#[tokio::test]
+async fn results_come_best_match_first_unless_sorted() {
+    let app = app();
+    let token = app.register_verified("search_sorter").await;
+    let in_body = write(app, &token, "First", "All about the quokka", "published").await;
+    let in_title = write(app, &token, "Quokka facts", "b", "published").await;
+
+    let response = app.get("/articles/search?q=quokk", None).await;
+    assert_eq!(slugs(&response.body), [in_title.as_str(), in_body.as_str()]);
+    assert_eq!(
+        response.body["articles"][0]["highlight"]["title"],
+        "<mark>Quokka</mark> facts"
+    );
+    assert!(response.body["articles"][0].get("bodyHtml").is_none());
+
+    let response = app
+        .post(
+            &format!("/articles/{}/favorite", in_body),
+            Some(&token),
+            json!({}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let response = app
+        .get(
+            "/articles/search?q=quokka&sort=most_favourited&html=true",
+            None,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(slugs(&response.body), [in_body.as_str(), in_title.as_str()]);
+    assert_eq!(
+        response.body["articles"][0]["bodyHtml"],
+        "<p>All about the quokka</p>\n"
+    );
+
+    let response = app.get("/articles/search?q=quokka&sort=best", None).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use common::{app, TestApp};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+fn sorted_tags(article: &Value) -> Vec<String> {
+    let mut tags: Vec<String> = article["tagList"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tag| tag.as_str().unwrap().to_owned())
+        .collect();
+    tags.sort();
+    tags
+}
+
+async fn all_tags(app: &TestApp) -> Vec<String> {
+    let response = app.get("/tags", None).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    serde_json::from_value(response.body["tagList"].clone()).unwrap()
+}
+
+async fn update_tags(app: &TestApp, token: &str, slug: &str, article: Value) -> Vec<String> {
+    let response = app
+        .put(
+            &format!("/articles/{}", slug),
+            Some(token),
+            json!({ "article": article }),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    sorted_tags(&response.body["article"])
+}
+
+#[tokio::test]
+async fn tags_can_be_replaced_added_and_removed() {
+    let app = app();
+    let token = app.register_verified("tagger").await;
+    let response = app
+        .post(
+            "/articles",
+            Some(&token),
+            json!({"article": {
+                "title": "Tagged",
+                "description": "d",
+                "body": "b",
+                "tagList": ["tagger_a", "tagger_b"],
+            }}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let slug = response.body["article"]["slug"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    assert_eq!(
+        sorted_tags(&response.body["article"]),
+        ["tagger_a", "tagger_b"]
+    );
+
+    let tags = update_tags(
+        app,
+        &token,
+        &slug,
+        json!({"tagList": ["tagger_b", "tagger_c"]}),
+    )
+    .await;
+    assert_eq!(tags, ["tagger_b", "tagger_c"]);
+
+    let tags = update_tags(
+        app,
+        &token,
+        &slug,
+        json!({"addTags": ["tagger_d", "tagger_c"], "removeTags": ["tagger_b", "tagger_x"]}),
+    )
+    .await;
+    assert_eq!(tags, ["tagger_c", "tagger_d"]);
+
+    let tags = update_tags(app, &token, &slug, json!({"body": "Only the body"})).await;
+    assert_eq!(tags, ["tagger_c", "tagger_d"]);
+}
+
+#[tokio::test]
+async fn tags_no_article_uses_are_dropped_from_the_tag_list() {
+    let app = app();
+    let token = app.register_verified("tag_cleaner").await;
+    let mut slugs = Vec::new();
+    for (title, tags) in [
+        ("Kept tags", json!(["cleaner_shared"])),
+        ("Dropped tags", json!(["cleaner_shared", "cleaner_own"])),
+    ] {
+        let response = app
+            .post(
+                "/articles",
+                Some(&token),
+                json!({"article": {"title": title, "description": "d", "body": "b", "tagList": tags}}),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+        slugs.push(
+            response.body["article"]["slug"]
+                .as_str()
+                .unwrap()
+                .to_owned(),
+        );
+    }
+    let tags = all_tags(app).await;
+    assert!(tags.contains(&"cleaner_own".to_owned()));
+
+    update_tags(app, &token, &slugs[1], json!({"tagList": []})).await;
+    let tags = all_tags(app).await;
+    assert!(!tags.contains(&"cleaner_own".to_owned()), "{:?}", tags);
+    assert!(tags.contains(&"cleaner_shared".to_owned()), "{:?}", tags);
+
+    let response = app
+        .delete(&format!("/articles/{}", slugs[0]), Some(&token))
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let tags = all_tags(app).await;
+    assert!(!tags.contains(&"cleaner_shared".to_owned()), "{:?}", tags);
+}
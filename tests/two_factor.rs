@@ -0,0 +1,153 @@
+mod common;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use common::{app, TestApp};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+/// The code for `steps` time steps from now.
+fn totp_code(secret: &str, steps: i64) -> String {
+    let secret = Secret::Encoded(secret.to_owned()).to_bytes().unwrap();
+    let totp = TOTP::new(Algorithm::SHA1, 6, 1, 30, secret, None, "test".into()).unwrap();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    totp.generate((now as i64 + steps * 30) as u64)
+}
+
+/// Enables two-factor authentication for a new user and returns their secret and recovery
+/// codes.
+async fn enable_two_factor(app: &TestApp, username: &str) -> (String, Vec<String>) {
+    let token = app.register(username).await;
+    let setup = app.post("/user/2fa", Some(&token), json!({})).await;
+    assert_eq!(setup.status, StatusCode::OK, "{}", setup.body);
+    let secret = setup.body["twoFactor"]["secret"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    let confirm = app
+        .post(
+            "/user/2fa/confirm",
+            Some(&token),
+            json!({"twoFactor": {"code": totp_code(&secret, 0)}}),
+        )
+        .await;
+    assert_eq!(confirm.status, StatusCode::OK, "{}", confirm.body);
+    let recovery_codes = confirm.body["twoFactor"]["recoveryCodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|code| code.as_str().unwrap().to_owned())
+        .collect();
+    (secret, recovery_codes)
+}
+
+async fn challenge(app: &TestApp, username: &str) -> String {
+    let login = app.login(username).await;
+    assert_eq!(login.status, StatusCode::OK, "{}", login.body);
+    assert!(login.body.get("user").is_none(), "logged in without 2FA");
+    login.body["twoFactor"]["challengeToken"]
+        .as_str()
+        .unwrap()
+        .to_owned()
+}
+
+async fn finish_login(app: &TestApp, username: &str, code: Value) -> StatusCode {
+    let mut request = code;
+    request["challengeToken"] = challenge(app, username).await.into();
+    app.post("/users/login/2fa", None, json!({ "user": request }))
+        .await
+        .status
+}
+
+#[tokio::test]
+async fn login_needs_a_totp_code_that_hasnt_been_used() {
+    let app = app();
+    let (secret, _) = enable_two_factor(app, "totp_user").await;
+
+    //? Confirming two-factor authentication used up the current code
+    let code = json!({"code": totp_code(&secret, 0)});
+    assert_eq!(
+        finish_login(app, "totp_user", code).await,
+        StatusCode::UNAUTHORIZED
+    );
+
+    let next_code = json!({"code": totp_code(&secret, 1)});
+    assert_eq!(
+        finish_login(app, "totp_user", next_code.clone()).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        finish_login(app, "totp_user", next_code).await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn a_wrong_totp_code_is_refused() {
+    let app = app();
+    let (secret, _) = enable_two_factor(app, "totp_wrong").await;
+    let wrong_code = format!(
+        "{:06}",
+        (totp_code(&secret, 1).parse::<u32>().unwrap() + 1) % 1000000
+    );
+    assert_eq!(
+        finish_login(app, "totp_wrong", json!({ "code": wrong_code })).await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn recovery_codes_work_once() {
+    let app = app();
+    let (_, recovery_codes) = enable_two_factor(app, "recovering").await;
+    assert_eq!(recovery_codes.len(), 10);
+
+    //? Case and surrounding whitespace don't matter
+    let code = format!("  {}  ", recovery_codes[3].to_uppercase());
+    assert_eq!(
+        finish_login(app, "recovering", json!({ "recoveryCode": code })).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        finish_login(app, "recovering", json!({ "recoveryCode": code })).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        finish_login(
+            app,
+            "recovering",
+            json!({ "recoveryCode": recovery_codes[4] })
+        )
+        .await,
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn challenge_tokens_are_not_access_tokens() {
+    let app = app();
+    enable_two_factor(app, "challenged").await;
+    let challenge_token = challenge(app, "challenged").await;
+    let response = app.get("/user", Some(&challenge_token)).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn access_tokens_are_not_challenge_tokens() {
+    let app = app();
+    let (secret, _) = enable_two_factor(app, "swapped").await;
+    let access_token = app.register("swapped_other").await;
+    let response = app
+        .post(
+            "/users/login/2fa",
+            None,
+            json!({"user": {"challengeToken": access_token, "code": totp_code(&secret, 1)}}),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}